@@ -0,0 +1,46 @@
+//! Measure the round trip of a Nop through the ring, both when the Op has
+//! already completed by the time that it is first polled, and when it is
+//! awaited and woken by the listen loop.
+//!
+//! Run with `cargo run --release --example nop_round_trip [iterations]`.
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use io_uring::opcode::Nop;
+use io_uring_async::IoUringAsync;
+use send_wrapper::SendWrapper;
+
+fn report(name: &str, iterations: u32, elapsed: Duration) {
+    println!("{:<24} {:>8.0} ns/op", name, elapsed.as_nanos() as f64 / iterations as f64);
+}
+
+fn main() {
+    let iterations = std::env::args().nth(1).map_or(100_000, |arg| arg.parse().expect("iterations must be a number"));
+    let uring = Rc::new(IoUringAsync::new(8).unwrap());
+
+    let uring_clone = SendWrapper::new(uring.clone());
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .on_thread_park(move || { uring_clone.submit().unwrap(); })
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(tokio::task::LocalSet::new().run_until(async {
+        // The Op completes before it is polled, so the first poll resolves it.
+        let start = Instant::now();
+        for _ in 0..iterations {
+            let op = uring.push(Nop::new().build());
+            uring.submit_and_wait_at_least(1).unwrap();
+            assert_eq!(op.await.result(), 0);
+        }
+        report("completed before poll", iterations, start.elapsed());
+
+        // The Op is polled before it completes, and is woken by the listen loop.
+        tokio::task::spawn_local(IoUringAsync::listen(uring.clone()));
+        let start = Instant::now();
+        for _ in 0..iterations {
+            assert_eq!(uring.push(Nop::new().build()).await.result(), 0);
+        }
+        report("awaited", iterations, start.elapsed());
+    }));
+}
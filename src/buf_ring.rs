@@ -0,0 +1,865 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU16, Ordering};
+use io_uring::{opcode, types};
+use io_uring::squeue::Flags;
+use io_uring::types::BufRingEntry;
+use crate::multishot::MultishotOp;
+use crate::{cqueue, squeue, Driver, IoUringAsync, Op};
+
+// IORING_OP_READ_MULTISHOT, which is not exposed by the io_uring crate.
+const READ_MULTISHOT: u8 = 49;
+
+// The constants for incrementally consumed buffer rings, which are not exposed
+// by the io_uring crate.
+const IORING_REGISTER_PBUF_RING: libc::c_uint = 22;
+const IOU_PBUF_RING_INC: u16 = 2;
+const CQE_F_BUF_MORE: u32 = 1 << 4;
+
+// IORING_RECVSEND_BUNDLE, which is not exposed by the io_uring crate.
+const RECVSEND_BUNDLE: u16 = 1 << 4;
+
+// struct io_uring_buf_reg, which is passed to IORING_REGISTER_PBUF_RING.
+#[repr(C)]
+struct BufReg {
+    ring_addr: u64,
+    ring_entries: u32,
+    bgid: u16,
+    flags: u16,
+    resv: [u64; 3],
+}
+
+// The ownership of a buffer in a BufRing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BufState {
+    // The buffer is in the ring, and may be selected by the kernel at any time.
+    InRing,
+    // The buffer was selected by the kernel and is borrowed by a ProvidedBuf.
+    Borrowed,
+    // The buffer was selected by the kernel and is waiting to be recycled.
+    Held,
+    // The buffer was selected by the kernel and released, and is recycled once
+    // the last ProvidedBuf borrowing part of it is dropped.
+    Released,
+}
+
+// The bookkeeping for a buffer in a BufRing.
+#[derive(Debug, Clone, Copy)]
+struct Buf {
+    state: BufState,
+    // The number of bytes of the buffer that the kernel has consumed. This is
+    // only non-zero for incrementally consumed rings.
+    consumed: usize,
+    // The number of ProvidedBufs borrowing part of the buffer.
+    borrows: usize,
+}
+
+/// A ring of provided buffers registered with the kernel under a buffer group
+/// ID. Receives pushed with `IOSQE_BUFFER_SELECT` and the ring's group ID select a
+/// buffer from the ring, which is identified by [`cqueue::buffer_id`] in the
+/// completion queue entry. See [`IoUringAsync::register_buf_ring`].
+///
+/// Selected buffers are not returned to the kernel until they are recycled,
+/// either by dropping the [`ProvidedBuf`] returned by [`BufRing::take`], or with
+/// [`BufRing::recycle`] and [`BufRing::recycle_many`]. The ring is unregistered
+/// when it is dropped.
+///
+/// If the ring is incrementally consumed (see
+/// [`IoUringAsync::register_buf_ring_incremental`]), a buffer may be shared by
+/// several receives, each of which fills the part of the buffer after the
+/// previous one. Such buffers return to the ring once they have been fully
+/// consumed and every [`ProvidedBuf`] borrowing part of them has been dropped.
+pub struct BufRing<C: cqueue::Entry = io_uring::cqueue::Entry> {
+    uring: Rc<dyn Driver<C>>,
+    // The page-aligned ring shared with the kernel.
+    ring: *mut BufRingEntry,
+    entries: u16,
+    bgid: u16,
+    buf_len: usize,
+    bufs: Box<[u8]>,
+    // The tail of the ring, which is only ever written by this process. The
+    // kernel consumes entries from the head, which it does not share.
+    tail: Cell<u16>,
+    bufs_state: RefCell<Vec<Buf>>,
+    // The IDs of the buffers in the ring, in the order that they were written
+    // to it. The kernel consumes buffers in this order, which is what allows
+    // the buffers of a bundle to be recovered from the ID of the first.
+    order: RefCell<VecDeque<u16>>,
+    incremental: bool,
+}
+
+impl<C: cqueue::Entry> BufRing<C> {
+    /// Returns the buffer group ID that the ring is registered under.
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    /// Returns the number of buffers in the ring.
+    pub fn entries(&self) -> u16 {
+        self.entries
+    }
+
+    /// Returns the length of each buffer in the ring.
+    pub fn buf_len(&self) -> usize {
+        self.buf_len
+    }
+
+    /// Returns whether the ring is incrementally consumed.
+    pub fn is_incremental(&self) -> bool {
+        self.incremental
+    }
+
+    /// Returns the number of buffers that are in the ring, and may be selected
+    /// by the kernel. Receives from a ring with no buffers fail with `ENOBUFS`.
+    pub fn available(&self) -> usize {
+        self.bufs_state.borrow().iter().filter(|buf| buf.state == BufState::InRing).count()
+    }
+
+    /// Take the buffer that the kernel selected for `cqe`, which holds the bytes
+    /// received by the operation. Returns `None` if the entry did not select a
+    /// buffer, failed, or selected a buffer that is not currently in the ring.
+    ///
+    /// For an incrementally consumed ring, the returned buffer only covers the
+    /// part of the buffer that was filled by `cqe`. If the kernel has not fully
+    /// consumed the buffer, it stays in the ring for subsequent receives.
+    pub fn take(&self, cqe: &C) -> Option<ProvidedBuf<'_, C>> {
+        let id = cqueue::buffer_id(cqe)?;
+        let len = usize::try_from(cqe.result()).ok()?;
+        let mut bufs = self.bufs_state.borrow_mut();
+        let buf = bufs.get_mut(id as usize)?;
+        if buf.state != BufState::InRing || buf.consumed + len > self.buf_len {
+            return None;
+        }
+        let start = buf.consumed;
+        let partial = self.incremental && cqe.flags() & CQE_F_BUF_MORE != 0;
+        if self.incremental {
+            buf.consumed += len;
+        }
+        if !partial {
+            buf.state = BufState::Borrowed;
+            let mut order = self.order.borrow_mut();
+            if let Some(position) = order.iter().position(|in_ring| *in_ring == id) {
+                order.remove(position);
+            }
+        }
+        buf.borrows += 1;
+        Some(ProvidedBuf { ring: self, id, start, len, partial })
+    }
+
+    /// Take every buffer that the kernel selected for a bundled `cqe`, such as
+    /// one yielded by a [`RecvMultishot`] with bundling enabled, in the order
+    /// that they were filled. A bundle fills consecutive buffers of the ring
+    /// starting at the one identified by [`cqueue::buffer_id`], and every buffer
+    /// but the last is full. Returns an empty `Vec` if the entry did not select
+    /// a buffer, failed, or selected a buffer that is not currently in the ring.
+    ///
+    /// An entry that is not bundled is taken as a bundle of one buffer. Bundles
+    /// are not supported by incrementally consumed rings, for which this is
+    /// equivalent to [`BufRing::take`].
+    pub fn take_bundle(&self, cqe: &C) -> Vec<ProvidedBuf<'_, C>> {
+        if self.incremental {
+            return self.take(cqe).into_iter().collect();
+        }
+        let (Some(id), Ok(mut remaining)) = (cqueue::buffer_id(cqe), usize::try_from(cqe.result())) else {
+            return Vec::new();
+        };
+        let mut order = self.order.borrow_mut();
+        // An empty receive may still have selected a buffer.
+        let count = remaining.div_ceil(self.buf_len).max(1);
+        let Some(first) = order.iter().position(|in_ring| *in_ring == id).filter(|first| first + count <= order.len()) else {
+            return Vec::new();
+        };
+        let mut bufs = self.bufs_state.borrow_mut();
+        order.drain(first..first + count).map(|id| {
+            let len = remaining.min(self.buf_len);
+            remaining -= len;
+            let buf = &mut bufs[id as usize];
+            buf.state = BufState::Borrowed;
+            buf.borrows += 1;
+            ProvidedBuf { ring: self, id, start: 0, len, partial: false }
+        }).collect()
+    }
+
+    /// Return a buffer that was released with [`ProvidedBuf::into_id`] to the
+    /// ring, so that the kernel may select it again.
+    pub fn recycle(&self, id: u16) -> io::Result<()> {
+        self.recycle_many(std::slice::from_ref(&id))
+    }
+
+    /// Return a batch of buffers that were released with [`ProvidedBuf::into_id`]
+    /// to the ring. The tail of the ring is advanced once for the whole batch, so
+    /// that the kernel observes every buffer with a single release barrier.
+    ///
+    /// The batch is rejected as a whole if any buffer is already in the ring,
+    /// is still borrowed, or appears more than once, since recycling it would
+    /// overflow the ring.
+    pub fn recycle_many(&self, ids: &[u16]) -> io::Result<()> {
+        let mut bufs = self.bufs_state.borrow_mut();
+        for (i, id) in ids.iter().enumerate() {
+            match bufs.get(*id as usize) {
+                Some(buf) if buf.state == BufState::Held && buf.borrows > 0 => return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is still borrowed")),
+                Some(buf) if buf.state == BufState::Held && !ids[..i].contains(id) => {},
+                Some(buf) if buf.state == BufState::Held => return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is recycled more than once")),
+                Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is not held")),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer id is out of range")),
+            }
+        }
+        let in_ring = bufs.iter().filter(|buf| buf.state == BufState::InRing).count();
+        if in_ring + ids.len() > self.entries as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer ring is full"));
+        }
+
+        let tail = self.tail.get();
+        for (i, id) in ids.iter().enumerate() {
+            let buf = &mut bufs[*id as usize];
+            buf.state = BufState::InRing;
+            buf.consumed = 0;
+            unsafe { self.write_entry(tail.wrapping_add(i as u16), *id) };
+        }
+        self.order.borrow_mut().extend(ids);
+        self.publish(tail.wrapping_add(ids.len() as u16));
+        Ok(())
+    }
+
+    // Write the descriptor of buffer `id` into the ring at position `tail`,
+    // without making it visible to the kernel.
+    unsafe fn write_entry(&self, tail: u16, id: u16) {
+        let entry = &mut *self.ring.add((tail & (self.entries - 1)) as usize);
+        entry.set_addr(self.bufs.as_ptr().add(id as usize * self.buf_len) as u64);
+        entry.set_len(self.buf_len as u32);
+        entry.set_bid(id);
+    }
+
+    /// Issue a full memory barrier and store the tail of the ring again, so that
+    /// every buffer recycled so far is visible to the kernel before anything
+    /// that follows, such as a submission. Recycling already publishes buffers
+    /// with a release store, which the kernel pairs with an acquire load when it
+    /// selects a buffer, so this is only needed to order recycles against
+    /// accesses to memory that the kernel does not load with acquire semantics.
+    /// See [`IoUringAsync::sync_buf_ring`] to also wait for the kernel.
+    pub fn sync(&self) {
+        std::sync::atomic::fence(Ordering::SeqCst);
+        self.publish(self.tail.get());
+    }
+
+    // Make every entry written before `tail` visible to the kernel.
+    fn publish(&self, tail: u16) {
+        self.tail.set(tail);
+        let shared = unsafe { &*(BufRingEntry::tail(self.ring) as *const AtomicU16) };
+        shared.store(tail, Ordering::Release);
+    }
+}
+
+impl<C: cqueue::Entry> Drop for BufRing<C> {
+    fn drop(&mut self) {
+        if self.uring.submitter().unregister_buf_ring(self.bgid).is_err() {
+            // The kernel may still select buffers from the ring, so its memory
+            // is leaked rather than freed.
+            std::mem::forget(std::mem::take(&mut self.bufs));
+            return;
+        }
+        unsafe { libc::munmap(self.ring.cast(), ring_size(self.entries)) };
+    }
+}
+
+/// A set of [`BufRing`]s registered on the same ring under distinct buffer group
+/// IDs, such as a group of small buffers for control messages and a group of
+/// large buffers for bulk transfers. A receive selects buffers from a single
+/// group, which is picked when the receive is pushed, and fails with `ENOBUFS`
+/// once that group is empty, regardless of the other groups. The rings are
+/// unregistered when they are removed, or when the registry is dropped.
+pub struct BufRingRegistry<C: cqueue::Entry = io_uring::cqueue::Entry> {
+    rings: BTreeMap<u16, BufRing<C>>,
+}
+
+impl<C: cqueue::Entry> BufRingRegistry<C> {
+    pub fn new() -> Self {
+        Self { rings: BTreeMap::new() }
+    }
+
+    /// Register a ring of buffers under `bgid` with
+    /// [`IoUringAsync::register_buf_ring`], and add it to the registry. Fails
+    /// with `AlreadyExists` if the registry already has a ring under `bgid`.
+    pub fn register<S: squeue::Entry>(&mut self, uring: &IoUringAsync<S, C>, bgid: u16, entries: u16, buf_len: usize) -> io::Result<&BufRing<C>> {
+        self.insert_with(bgid, || uring.register_buf_ring(bgid, entries, buf_len))
+    }
+
+    /// Register an incrementally consumed ring of buffers under `bgid` with
+    /// [`IoUringAsync::register_buf_ring_incremental`], and add it to the
+    /// registry. Fails with `AlreadyExists` if the registry already has a ring
+    /// under `bgid`.
+    pub fn register_incremental<S: squeue::Entry>(&mut self, uring: &IoUringAsync<S, C>, bgid: u16, entries: u16, buf_len: usize) -> io::Result<&BufRing<C>> {
+        self.insert_with(bgid, || uring.register_buf_ring_incremental(bgid, entries, buf_len))
+    }
+
+    fn insert_with(&mut self, bgid: u16, register: impl FnOnce() -> io::Result<BufRing<C>>) -> io::Result<&BufRing<C>> {
+        match self.rings.entry(bgid) {
+            std::collections::btree_map::Entry::Occupied(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, "buffer group is already registered")),
+            std::collections::btree_map::Entry::Vacant(entry) => Ok(entry.insert(register()?)),
+        }
+    }
+
+    /// Returns the ring registered under `bgid`.
+    pub fn get(&self, bgid: u16) -> Option<&BufRing<C>> {
+        self.rings.get(&bgid)
+    }
+
+    /// Remove the ring registered under `bgid` from the registry, handing it to
+    /// the caller. The ring is unregistered once it is dropped.
+    pub fn remove(&mut self, bgid: u16) -> Option<BufRing<C>> {
+        self.rings.remove(&bgid)
+    }
+
+    /// Returns the ring with the shortest buffers of at least `len` bytes that
+    /// still has buffers available, so that a receive of up to `len` bytes
+    /// neither truncates nor fails with `ENOBUFS` while a larger group has
+    /// room. Returns `None` if every such ring is empty.
+    pub fn select(&self, len: usize) -> Option<&BufRing<C>> {
+        self.rings.values()
+            .filter(|ring| ring.buf_len >= len && ring.available() > 0)
+            .min_by_key(|ring| ring.buf_len)
+    }
+
+    /// Returns the rings in the registry, in order of buffer group ID.
+    pub fn iter(&self) -> impl Iterator<Item = &BufRing<C>> {
+        self.rings.values()
+    }
+}
+
+impl<C: cqueue::Entry> Default for BufRingRegistry<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Register the ring at `ring` with the kernel, incrementally consumed if
+// `incremental` is set.
+fn register(fd: RawFd, ring: *mut libc::c_void, entries: u16, bgid: u16, incremental: bool) -> io::Result<()> {
+    let reg = BufReg {
+        ring_addr: ring as u64,
+        ring_entries: entries as u32,
+        bgid,
+        flags: if incremental { IOU_PBUF_RING_INC } else { 0 },
+        resv: [0; 3],
+    };
+    let ret = unsafe { libc::syscall(libc::SYS_io_uring_register, fd, IORING_REGISTER_PBUF_RING, &reg as *const BufReg, 1) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The size of the memory shared with the kernel for a ring of `entries` buffers.
+fn ring_size(entries: u16) -> usize {
+    entries as usize * std::mem::size_of::<BufRingEntry>()
+}
+
+/// A buffer that the kernel selected from a [`BufRing`], dereferencing to the
+/// bytes that were received into it. The buffer is recycled when it is dropped.
+pub struct ProvidedBuf<'a, C: cqueue::Entry> {
+    ring: &'a BufRing<C>,
+    id: u16,
+    start: usize,
+    len: usize,
+    // The kernel has not fully consumed the buffer, which is still in the ring.
+    partial: bool,
+}
+
+impl<'a, C: cqueue::Entry> ProvidedBuf<'a, C> {
+    /// Returns the ID of the buffer within its ring.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Returns whether the buffer was only partially consumed by the kernel, in
+    /// which case the rest of it remains in an incrementally consumed ring.
+    pub fn is_partial(&self) -> bool {
+        self.partial
+    }
+
+    /// Release the buffer without recycling it, returning its ID. The buffer is
+    /// not selected by the kernel again until it is recycled with
+    /// [`BufRing::recycle`] or [`BufRing::recycle_many`], which also requires
+    /// every other `ProvidedBuf` borrowing part of it to have been dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer is [partial](ProvidedBuf::is_partial), since it is
+    /// still in the ring.
+    pub fn into_id(self) -> u16 {
+        assert!(!self.partial, "a partially consumed buffer is still in the ring");
+        let mut bufs = self.ring.bufs_state.borrow_mut();
+        let buf = &mut bufs[self.id as usize];
+        buf.state = BufState::Held;
+        buf.borrows -= 1;
+        drop(bufs);
+        let id = self.id;
+        std::mem::forget(self);
+        id
+    }
+}
+
+impl<'a, C: cqueue::Entry> std::ops::Deref for ProvidedBuf<'a, C> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let start = self.id as usize * self.ring.buf_len + self.start;
+        &self.ring.bufs[start..start + self.len]
+    }
+}
+
+impl<'a, C: cqueue::Entry> Drop for ProvidedBuf<'a, C> {
+    fn drop(&mut self) {
+        let mut bufs = self.ring.bufs_state.borrow_mut();
+        let buf = &mut bufs[self.id as usize];
+        buf.borrows -= 1;
+        if !self.partial {
+            buf.state = BufState::Released;
+        }
+        if buf.state != BufState::Released || buf.borrows > 0 {
+            return;
+        }
+        buf.state = BufState::Held;
+        drop(bufs);
+        // The buffer was in the ring before it was borrowed, so there is always
+        // room to recycle it.
+        self.ring.recycle(self.id).unwrap();
+    }
+}
+
+/// A stream of the buffers filled by a multishot read, created with
+/// [`IoUringAsync::read_multishot`]. Dropping the stream cancels the read.
+pub struct ReadMultishot<'a, C: cqueue::Entry> {
+    ring: &'a BufRing<C>,
+    op: MultishotOp<C>,
+}
+
+impl<'a, C: cqueue::Entry> ReadMultishot<'a, C> {
+    /// Wait for the next buffer of data. Returns `None` once the read has
+    /// reached end-of-file, or after the error that terminated it, such as
+    /// `ENOBUFS` if every buffer in the ring was held.
+    pub async fn next(&mut self) -> Option<io::Result<ProvidedBuf<'a, C>>> {
+        let cqe = self.op.next().await?;
+        if cqe.result() < 0 {
+            return Some(Err(io::Error::from_raw_os_error(-cqe.result())));
+        }
+        // An empty read may still have selected a buffer, which is recycled
+        // as soon as it is dropped.
+        let buf = self.ring.take(&cqe);
+        if cqe.result() == 0 {
+            return None;
+        }
+        Some(buf.ok_or_else(|| io::Error::other("read did not select a buffer from the ring")))
+    }
+}
+
+/// A stream of the buffers filled by a multishot receive, created with
+/// [`IoUringAsync::recv_multishot`]. Dropping the stream cancels the receive.
+pub struct RecvMultishot<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    fd: RawFd,
+    ring: &'a BufRing<C>,
+    op: MultishotOp<C>,
+    bundle: bool,
+    // A completion has been yielded, so a rejected bundle flag no longer
+    // needs to be handled.
+    received: bool,
+}
+
+impl<'a, S: squeue::Entry, C: cqueue::Entry> RecvMultishot<'a, S, C> {
+    /// Wait for the buffers filled by the next completion, in the order that they
+    /// were filled. Without bundling, each completion fills a single buffer.
+    /// Returns `None` once the peer has shut down the connection, or after the
+    /// error that terminated the receive, such as `ENOBUFS` if every buffer in
+    /// the ring was held.
+    pub async fn next(&mut self) -> Option<io::Result<Vec<ProvidedBuf<'a, C>>>> {
+        loop {
+            let cqe = self.op.next().await?;
+            if self.bundle && !self.received && cqe.result() == -libc::EINVAL {
+                // The kernel predates bundles, so receive one buffer at a time.
+                self.bundle = false;
+                self.op = self.uring.push_multishot(recv_multishot_entry(self.fd, self.ring.bgid, false));
+                continue;
+            }
+            self.received = true;
+            if cqe.result() < 0 {
+                return Some(Err(io::Error::from_raw_os_error(-cqe.result())));
+            }
+            let bufs = self.ring.take_bundle(&cqe);
+            if cqe.result() == 0 {
+                return None;
+            }
+            if bufs.is_empty() {
+                return Some(Err(io::Error::other("receive did not select a buffer from the ring")));
+            }
+            return Some(Ok(bufs));
+        }
+    }
+
+    /// Returns whether completions are bundled. This is `false` if bundling was
+    /// not requested, or once the kernel has rejected it.
+    pub fn is_bundled(&self) -> bool {
+        self.bundle
+    }
+}
+
+fn recv_multishot_entry(fd: RawFd, bgid: u16, bundle: bool) -> io_uring::squeue::Entry {
+    let mut entry = opcode::RecvMulti::new(types::Fd(fd), bgid).build();
+    if bundle {
+        squeue::raw_mut(&mut entry).ioprio |= RECVSEND_BUNDLE;
+    }
+    entry
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Continuously receive from the socket `fd` into buffers selected from
+    /// `ring`, without re-arming the receive after every completion. Requires
+    /// Linux 6.0.
+    ///
+    /// If `bundle` is set, the receive is pushed with `IORING_RECVSEND_BUNDLE`,
+    /// so that a single completion may fill several buffers with the data that
+    /// is available, which reduces completion overhead for high packet rates.
+    /// Bundles require Linux 6.10. Older kernels reject the flag, in which case
+    /// the receive is pushed again without it, which can be checked with
+    /// [`RecvMultishot::is_bundled`]. Bundles are not used with incrementally
+    /// consumed rings.
+    pub fn recv_multishot<'a>(&'a self, fd: RawFd, ring: &'a BufRing<C>, bundle: bool) -> RecvMultishot<'a, S, C> {
+        let bundle = bundle && !ring.incremental;
+        let op = self.push_multishot(recv_multishot_entry(fd, ring.bgid, bundle));
+        RecvMultishot { uring: self, fd, ring, op, bundle, received: false }
+    }
+
+    /// Receive once from the socket `fd` into a buffer selected from `ring`,
+    /// which may be any group of a [`BufRingRegistry`]. Fails with `ENOBUFS` if
+    /// `ring` has no buffers available, in which case the receive may be retried
+    /// with another group. Resolves to `None` once the peer has shut down the
+    /// connection.
+    pub fn recv_buf<'a>(&self, fd: RawFd, ring: &'a BufRing<C>) -> impl std::future::Future<Output = io::Result<Option<ProvidedBuf<'a, C>>>> {
+        let entry = opcode::Recv::new(types::Fd(fd), std::ptr::null_mut(), ring.buf_len.try_into().unwrap_or(u32::MAX))
+            .buf_group(ring.bgid)
+            .build()
+            .flags(Flags::BUFFER_SELECT);
+        let op = self.push(entry);
+        async move {
+            let cqe = op.await;
+            // An empty receive may still have selected a buffer, which is
+            // recycled as soon as it is dropped.
+            let buf = ring.take(&cqe);
+            if cqueue::result_to_io(cqe.result())? == 0 {
+                return Ok(None);
+            }
+            buf.map(Some).ok_or_else(|| io::Error::other("receive did not select a buffer from the ring"))
+        }
+    }
+
+    /// Continuously read from `fd` into buffers selected from `ring`, without
+    /// re-arming the read after every completion (`IORING_OP_READ_MULTISHOT`).
+    /// `fd` must be pollable, such as a pipe, socket or tty, and is read at its
+    /// current position. Requires Linux 6.7.
+    pub fn read_multishot<'a>(&self, fd: RawFd, ring: &'a BufRing<C>) -> ReadMultishot<'a, C> {
+        let mut entry = opcode::Read::new(types::Fd(fd), std::ptr::null_mut(), 0)
+            .buf_group(ring.bgid)
+            .build()
+            .flags(Flags::BUFFER_SELECT);
+        squeue::raw_mut(&mut entry).opcode = READ_MULTISHOT;
+        ReadMultishot { ring, op: self.push_multishot(entry) }
+    }
+
+    /// Make every buffer recycled to `ring` visible to the kernel with
+    /// [`BufRing::sync`], and push a Nop that completes once the kernel has
+    /// processed it. Entries submitted after the Nop completes, such as the
+    /// next receive, are guaranteed to observe the recycled buffers rather than
+    /// failing with `ENOBUFS` because of a stale tail, even on a ring whose
+    /// submission queue is polled by a kernel thread.
+    pub fn sync_buf_ring(&self, ring: &BufRing<C>) -> Op<C> {
+        ring.sync();
+        self.push(opcode::Nop::new().build())
+    }
+
+    /// Register a ring of `entries` provided buffers of `buf_len` bytes each under
+    /// the buffer group ID `bgid`. Every buffer starts out in the ring. `entries`
+    /// must be a power of two no larger than 32768. Requires Linux 5.19.
+    pub fn register_buf_ring(&self, bgid: u16, entries: u16, buf_len: usize) -> io::Result<BufRing<C>> {
+        self.register_ring(bgid, entries, buf_len, false)
+    }
+
+    /// Register a ring of provided buffers like [`IoUringAsync::register_buf_ring`],
+    /// but incrementally consumed (`IOU_PBUF_RING_INC`): rather than using a whole
+    /// buffer for each receive, the kernel fills a buffer across multiple
+    /// receives, which suits large buffers for streaming receives. Incremental
+    /// consumption requires Linux 6.12. On older kernels, the ring is registered
+    /// without it, which can be checked with [`BufRing::is_incremental`].
+    pub fn register_buf_ring_incremental(&self, bgid: u16, entries: u16, buf_len: usize) -> io::Result<BufRing<C>> {
+        match self.register_ring(bgid, entries, buf_len, true) {
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => self.register_ring(bgid, entries, buf_len, false),
+            ring => ring,
+        }
+    }
+
+    fn register_ring(&self, bgid: u16, entries: u16, buf_len: usize, incremental: bool) -> io::Result<BufRing<C>> {
+        if !entries.is_power_of_two() || entries > 32768 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer ring entries must be a power of two no larger than 32768"));
+        }
+        if buf_len > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer length exceeds u32::MAX"));
+        }
+        let ring = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                ring_size(entries),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_ANONYMOUS | libc::MAP_PRIVATE,
+                -1,
+                0,
+            )
+        };
+        if ring == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        if let Err(err) = register(self.uring.inner.as_raw_fd(), ring, entries, bgid, incremental) {
+            unsafe { libc::munmap(ring, ring_size(entries)) };
+            return Err(err);
+        }
+
+        let ring = BufRing {
+            uring: self.uring.clone(),
+            ring: ring.cast(),
+            entries,
+            bgid,
+            buf_len,
+            bufs: vec![0u8; entries as usize * buf_len].into_boxed_slice(),
+            tail: Cell::new(0),
+            bufs_state: RefCell::new(vec![Buf { state: BufState::InRing, consumed: 0, borrows: 0 }; entries as usize]),
+            order: RefCell::new((0..entries).collect()),
+            incremental,
+        };
+        for id in 0..entries {
+            unsafe { ring.write_entry(id, id) };
+        }
+        ring.publish(entries);
+        Ok(ring)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use io_uring::{opcode, types};
+    use io_uring::squeue::Flags;
+    use crate::{cqueue, fd, IoUringAsync};
+    use super::BufRingRegistry;
+
+    fn recv(fd: &UnixStream, bgid: u16) -> io_uring::squeue::Entry {
+        opcode::Recv::new(types::Fd(fd.as_raw_fd()), std::ptr::null_mut(), 16)
+            .buf_group(bgid)
+            .build()
+            .flags(Flags::BUFFER_SELECT)
+    }
+
+    #[test]
+    fn buf_ring_recv() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let ring = uring.register_buf_ring(7, 2, 16).unwrap();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let mut held = Vec::new();
+            for msg in [b"one", b"two"] {
+                b.write_all(msg).unwrap();
+                let cqe = uring.push(recv(&a, 7)).await;
+                let buf = ring.take(&cqe).unwrap();
+                assert_eq!(&buf[..], msg);
+                held.push(buf.into_id());
+            }
+
+            // Both buffers are held, so the ring is empty.
+            b.write_all(b"three").unwrap();
+            assert_eq!(uring.push(recv(&a, 7)).await.result(), -libc::ENOBUFS);
+
+            ring.recycle_many(&held).unwrap();
+            let cqe = uring.push(recv(&a, 7)).await;
+            assert!(cqueue::buffer_id(&cqe).is_some());
+            assert_eq!(&ring.take(&cqe).unwrap()[..], b"three");
+        });
+    }
+
+    #[test]
+    fn sync_buf_ring() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let ring = uring.register_buf_ring(2, 1, 16).unwrap();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            b.write_all(b"one").unwrap();
+            let cqe = uring.push(recv(&a, 2)).await;
+            let id = ring.take(&cqe).unwrap().into_id();
+            b.write_all(b"two").unwrap();
+            assert_eq!(uring.push(recv(&a, 2)).await.result(), -libc::ENOBUFS);
+
+            ring.recycle(id).unwrap();
+            assert_eq!(uring.sync_buf_ring(&ring).await.result(), 0);
+            let cqe = uring.push(recv(&a, 2)).await;
+            assert_eq!(&ring.take(&cqe).unwrap()[..], b"two");
+        });
+    }
+
+    #[test]
+    fn recycle_many_rejects_overflow() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let ring = uring.register_buf_ring(1, 4, 16).unwrap();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            // Buffers that were never taken from the ring cannot be recycled.
+            let err = ring.recycle_many(&[0, 1]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+            b.write_all(b"x").unwrap();
+            let cqe = uring.push(recv(&a, 1)).await;
+            let id = ring.take(&cqe).unwrap().into_id();
+            assert!(ring.recycle_many(&[id, id]).is_err());
+            assert!(ring.recycle_many(&[id, 4]).is_err());
+            ring.recycle_many(&[id]).unwrap();
+            assert!(ring.recycle(id).is_err());
+        });
+    }
+
+    #[test]
+    fn register_buf_ring_entries() {
+        let uring = IoUringAsync::new(8).unwrap();
+        assert!(uring.register_buf_ring(0, 3, 16).is_err());
+        assert!(uring.register_buf_ring(0, 0, 16).is_err());
+    }
+
+    #[test]
+    fn read_multishot() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let ring = uring.register_buf_ring(3, 2, 16).unwrap();
+        let (rx, tx) = fd::pipe().unwrap();
+        let mut tx = std::fs::File::from(std::os::fd::OwnedFd::from(tx));
+        crate::tests::run(&uring, async {
+            let mut reads = uring.read_multishot(rx.as_raw_fd(), &ring);
+            for msg in [b"abc", b"def"] {
+                tx.write_all(msg).unwrap();
+                let buf = reads.next().await.unwrap().unwrap();
+                assert_eq!(&buf[..], msg);
+            }
+            drop(tx);
+            assert!(reads.next().await.is_none());
+            drop(reads);
+            uring.drain().await;
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn incremental() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let ring = uring.register_buf_ring_incremental(5, 1, 16).unwrap();
+        assert!(ring.is_incremental());
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let mut partial = Vec::new();
+            for msg in [&b"abc"[..], b"defg"] {
+                b.write_all(msg).unwrap();
+                let cqe = uring.push(recv(&a, 5)).await;
+                let buf = ring.take(&cqe).unwrap();
+                assert!(buf.is_partial());
+                assert_eq!(&buf[..], msg);
+                partial.push(buf);
+            }
+
+            // The rest of the buffer is consumed by the next receive, which
+            // takes the buffer out of the ring.
+            b.write_all(b"hijklmnopqrs").unwrap();
+            let cqe = uring.push(recv(&a, 5)).await;
+            let last = ring.take(&cqe).unwrap();
+            assert!(!last.is_partial());
+            assert_eq!(&last[..], b"hijklmnop");
+            drop(last);
+            assert_eq!(uring.push(recv(&a, 5)).await.result(), -libc::ENOBUFS);
+
+            // The buffer is recycled once every part of it has been dropped.
+            drop(partial);
+            let cqe = uring.push(recv(&a, 5)).await;
+            assert_eq!(&ring.take(&cqe).unwrap()[..], b"qrs");
+        });
+    }
+
+    #[test]
+    fn recv_multishot() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let ring = uring.register_buf_ring(9, 8, 4).unwrap();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let mut recvs = uring.recv_multishot(a.as_raw_fd(), &ring, false);
+            b.write_all(b"abc").unwrap();
+            let bufs = recvs.next().await.unwrap().unwrap();
+            assert_eq!(bufs.len(), 1);
+            assert_eq!(&bufs[0][..], b"abc");
+            drop(bufs);
+            drop(recvs);
+            uring.drain().await;
+
+            // The data is written before the receive is pushed, so that it is
+            // all available to the first completion.
+            b.write_all(b"hello world!!").unwrap();
+            let mut recvs = uring.recv_multishot(a.as_raw_fd(), &ring, true);
+            let mut received = Vec::new();
+            let mut ids = Vec::new();
+            while received.len() < 13 {
+                for buf in recvs.next().await.unwrap().unwrap() {
+                    received.extend_from_slice(&buf);
+                    ids.push(buf.id());
+                }
+            }
+            assert_eq!(received, b"hello world!!");
+            if recvs.is_bundled() {
+                // Bundles span consecutive buffers of the ring.
+                assert_eq!(ids.len(), 4);
+                assert!(ids.windows(2).all(|pair| pair[1] == (pair[0] + 1) % 8));
+            }
+            drop(b);
+            assert!(recvs.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn registry() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut registry = BufRingRegistry::new();
+        registry.register(&uring, 1, 1, 8).unwrap();
+        registry.register(&uring, 2, 2, 64).unwrap();
+        let err = registry.register(&uring, 2, 2, 64).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(registry.iter().map(|ring| ring.bgid()).collect::<Vec<_>>(), [1, 2]);
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let small = registry.select(4).unwrap();
+            assert_eq!(small.bgid(), 1);
+            b.write_all(b"ping").unwrap();
+            let buf = uring.recv_buf(a.as_raw_fd(), small).await.unwrap().unwrap();
+            assert_eq!(&buf[..], b"ping");
+
+            // An empty group fails on its own, while the others are selected.
+            assert_eq!(small.available(), 0);
+            b.write_all(b"pong").unwrap();
+            let err = uring.recv_buf(a.as_raw_fd(), small).await.err().unwrap();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOBUFS));
+            let large = registry.select(4).unwrap();
+            assert_eq!(large.bgid(), 2);
+            assert_eq!(&uring.recv_buf(a.as_raw_fd(), large).await.unwrap().unwrap()[..], b"pong");
+            drop(buf);
+            assert_eq!(registry.select(4).unwrap().bgid(), 1);
+            assert!(registry.select(128).is_none());
+            drop(b);
+            assert!(uring.recv_buf(a.as_raw_fd(), small).await.unwrap().is_none());
+        });
+        assert_eq!(registry.remove(1).unwrap().bgid(), 1);
+        assert!(registry.get(1).is_none());
+        registry.register(&uring, 1, 1, 8).unwrap();
+    }
+}
@@ -0,0 +1,561 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::os::unix::prelude::RawFd;
+use std::rc::Rc;
+use io_uring::{opcode, types};
+use crate::{cqueue, squeue, IoUringAsync, Op, OwnedOp};
+
+// Memory handed to the kernel with ProvideBuffers, holding the `nr` buffers of
+// `len` bytes with IDs starting at `bid`. It is owned by the Ring rather than
+// the IoUringAsync so that it outlives every operation that may select one of
+// its buffers.
+pub(crate) struct ProvidedMemory {
+    bgid: u16,
+    bid: u16,
+    len: usize,
+    mem: *mut [u8],
+}
+
+impl ProvidedMemory {
+    // Returns a pointer to the buffer `bid` of group `bgid`, if it belongs to the
+    // memory.
+    fn buffer(&self, bgid: u16, bid: u16) -> Option<*mut u8> {
+        let nr = self.mem.len() / self.len;
+        if bgid != self.bgid || bid < self.bid || (bid - self.bid) as usize >= nr {
+            return None;
+        }
+        Some(unsafe { self.mem.cast::<u8>().add((bid - self.bid) as usize * self.len) })
+    }
+}
+
+impl Drop for ProvidedMemory {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.mem) });
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Provide the buffers in `mem` to the kernel as buffer group `bgid`, using the
+    /// classic `ProvideBuffers` opcode. `mem` is split into buffers of `len`
+    /// bytes, which are given consecutive IDs starting at `bid`. A receive pushed
+    /// with `IOSQE_BUFFER_SELECT` and the group ID selects one of the buffers,
+    /// which is identified by [`cqueue::buffer_id`] in its completion queue entry.
+    ///
+    /// The memory is owned by the ring until the ring is dropped, even after the
+    /// buffers are removed with [`IoUringAsync::remove_buffers`], since the
+    /// kernel may still be writing to a selected buffer. A selected buffer is
+    /// not returned to the group until it is provided again with
+    /// [`IoUringAsync::reprovide_buffer`]. For most uses, [`BufRing`](crate::buf_ring::BufRing)
+    /// is more efficient.
+    pub fn provide_buffers(&self, mem: Box<[u8]>, len: usize, bgid: u16, bid: u16) -> io::Result<Op<C>> {
+        if len == 0 || len > i32::MAX as usize || !mem.len().is_multiple_of(len) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "memory is not a whole number of buffers"));
+        }
+        let nr = u16::try_from(mem.len() / len)
+            .ok()
+            .filter(|nr| bid.checked_add(*nr).is_some())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "too many buffers"))?;
+        let memory = ProvidedMemory { bgid, bid, len, mem: Box::into_raw(mem) };
+        let entry = opcode::ProvideBuffers::new(memory.mem.cast(), len as i32, nr, bgid, bid).build();
+        self.uring.provided_buffers.borrow_mut().push(memory);
+        Ok(self.push(entry))
+    }
+
+    /// Return the buffer `bid` of group `bgid`, which must have been provided
+    /// with [`IoUringAsync::provide_buffers`], to the group after it has been
+    /// selected by an operation.
+    pub fn reprovide_buffer(&self, bgid: u16, bid: u16) -> io::Result<Op<C>> {
+        let provided = self.uring.provided_buffers.borrow();
+        let (memory, addr) = provided.iter().rev()
+            .find_map(|memory| Some((memory, memory.buffer(bgid, bid)?)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "buffer was not provided"))?;
+        let entry = opcode::ProvideBuffers::new(addr, memory.len as i32, 1, bgid, bid).build();
+        drop(provided);
+        Ok(self.push(entry))
+    }
+
+    /// Returns the contents of the buffer `bid` of group `bgid`, which must have
+    /// been provided with [`IoUringAsync::provide_buffers`].
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been selected by an operation that has completed, and
+    /// must not have been provided to the kernel again while the returned slice
+    /// is alive.
+    pub unsafe fn provided_buffer(&self, bgid: u16, bid: u16) -> Option<&[u8]> {
+        let provided = self.uring.provided_buffers.borrow();
+        provided.iter().rev().find_map(|memory| {
+            let addr = memory.buffer(bgid, bid)?;
+            Some(std::slice::from_raw_parts(addr, memory.len))
+        })
+    }
+
+    /// Remove up to `nr` buffers from group `bgid`. The completion queue entry's
+    /// result is the number of buffers that were removed. The memory of the
+    /// removed buffers remains owned by the ring.
+    pub fn remove_buffers(&self, nr: u16, bgid: u16) -> Op<C> {
+        self.push(opcode::RemoveBuffers::new(nr, bgid).build())
+    }
+
+    /// Register `bufs` with the kernel for use with fixed-buffer operations such
+    /// as [`IoUringAsync::read_fixed_fixed`]. Buffers are referred to by their
+    /// index in `bufs`.
+    ///
+    /// # Safety
+    ///
+    /// The memory described by `bufs` must remain valid until the buffers are
+    /// unregistered and every operation using them has completed.
+    pub unsafe fn register_buffers(&self, bufs: &[libc::iovec]) -> io::Result<()> {
+        self.uring.inner.submitter().register_buffers(bufs)?;
+        *self.fixed_buffers.borrow_mut() = bufs.to_vec();
+        Ok(())
+    }
+
+    /// Unregister the buffers that were registered with [`IoUringAsync::register_buffers`].
+    pub fn unregister_buffers(&self) -> io::Result<()> {
+        self.uring.inner.submitter().unregister_buffers()?;
+        self.fixed_buffers.borrow_mut().clear();
+        Ok(())
+    }
+
+    // Returns the registered buffer at `index` if it can hold `len` bytes.
+    pub(crate) fn fixed_buffer(&self, index: u16, len: u32) -> io::Result<libc::iovec> {
+        match self.fixed_buffers.borrow().get(index as usize) {
+            Some(iovec) if len as usize <= iovec.iov_len => Ok(*iovec),
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "length exceeds the registered buffer")),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "buffer is not registered")),
+        }
+    }
+
+    /// Read `len` bytes at `offset` from the registered file in `file_slot` into
+    /// the start of the registered buffer `buf_index`. This uses both a fixed file
+    /// and a fixed buffer, avoiding the per-operation cost of looking up the file
+    /// and mapping the buffer.
+    ///
+    /// Returns an error without pushing anything if the file slot or buffer is not
+    /// registered, or if the buffer is smaller than `len`.
+    pub fn read_fixed_fixed(&self, file_slot: u32, buf_index: u16, offset: u64, len: u32) -> io::Result<Op<C>> {
+        if !self.file_slots.borrow().contains(file_slot) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "file slot is not registered"));
+        }
+        let buf = self.fixed_buffer(buf_index, len)?;
+        let entry = opcode::ReadFixed::new(types::Fixed(file_slot), buf.iov_base.cast(), len, buf_index)
+            .offset64(offset as libc::off64_t)
+            .rw_flags(self.rw_flags)
+            .build();
+        Ok(self.push(entry))
+    }
+}
+
+// The memory of a FixedBufferPool, which is shared with its PoolBufs so that it
+// outlives any operation using one of its buffers.
+struct PoolMemory {
+    mem: *mut [u8],
+    len: usize,
+    free: RefCell<Vec<u16>>,
+}
+
+impl Drop for PoolMemory {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.mem) });
+    }
+}
+
+/// A pool of equal-sized buffers that are registered with the kernel for use
+/// with fixed-buffer operations. Buffers are handed out as [`PoolBuf`] guards,
+/// which return their index to the pool when dropped.
+///
+/// The pool registers its buffers with [`IoUringAsync::register_buffers`], so a
+/// ring can only have one pool, and the buffers are unregistered when the pool
+/// is dropped.
+pub struct FixedBufferPool<S: squeue::Entry = io_uring::squeue::Entry, C: cqueue::Entry = io_uring::cqueue::Entry> {
+    uring: Rc<IoUringAsync<S, C>>,
+    memory: Rc<PoolMemory>,
+    ioprio: Option<squeue::IoPriority>,
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> FixedBufferPool<S, C> {
+    /// Allocate `nr` buffers of `len` bytes and register them with the kernel.
+    pub fn new(uring: Rc<IoUringAsync<S, C>>, nr: u16, len: usize) -> io::Result<Self> {
+        let mem = Box::into_raw(vec![0u8; nr as usize * len].into_boxed_slice());
+        let memory = Rc::new(PoolMemory { mem, len, free: RefCell::new((0..nr).rev().collect()) });
+        let iovecs: Vec<_> = (0..nr as usize).map(|index| libc::iovec {
+            iov_base: unsafe { mem.cast::<u8>().add(index * len) }.cast(),
+            iov_len: len,
+        }).collect();
+        // The memory is owned by the pool and its PoolBufs, and the buffers are
+        // unregistered when the pool is dropped.
+        unsafe { uring.register_buffers(&iovecs)? };
+        Ok(Self { uring, memory, ioprio: None })
+    }
+
+    /// Set the I/O priority of the pool's reads and writes. See [`squeue::ioprio`].
+    pub fn set_ioprio(&mut self, priority: Option<squeue::IoPriority>) {
+        self.ioprio = priority;
+    }
+
+    // Apply the pool's options to a read or write entry.
+    fn prepare(&self, entry: io_uring::squeue::Entry) -> io_uring::squeue::Entry {
+        match self.ioprio {
+            Some(priority) => squeue::ioprio(entry, priority),
+            None => entry,
+        }
+    }
+
+    /// Take a free buffer from the pool, or `None` if every buffer is in use.
+    pub fn get(&self) -> Option<PoolBuf> {
+        let index = self.memory.free.borrow_mut().pop()?;
+        Some(PoolBuf { memory: self.memory.clone(), index })
+    }
+
+    /// Returns the number of free buffers in the pool.
+    pub fn available(&self) -> usize {
+        self.memory.free.borrow().len()
+    }
+
+    /// Read into `buf` from `fd` at `offset`, using the registered buffer. Returns
+    /// the number of bytes read, handing the buffer back to the caller.
+    pub fn read_fixed(&self, fd: RawFd, mut buf: PoolBuf, offset: u64) -> impl Future<Output = (io::Result<usize>, PoolBuf)> {
+        let entry = opcode::ReadFixed::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32, buf.index)
+            .offset64(offset as libc::off64_t)
+            .rw_flags(self.uring.rw_flags)
+            .build();
+        let op = OwnedOp::new(self.uring.push(self.prepare(entry)), buf);
+        async move {
+            let (cqe, buf) = op.await;
+            (cqueue::result_to_io(cqe.result()).map(|n| n as usize), buf)
+        }
+    }
+
+    /// Write the first `len` bytes of `buf` to `fd` at `offset`, using the
+    /// registered buffer. Returns the number of bytes written, handing the buffer
+    /// back to the caller.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is larger than the buffer.
+    pub fn write_fixed(&self, fd: RawFd, buf: PoolBuf, offset: u64, len: usize) -> impl Future<Output = (io::Result<usize>, PoolBuf)> {
+        assert!(len <= buf.len(), "length exceeds the pool buffer");
+        let entry = opcode::WriteFixed::new(types::Fd(fd), buf.as_ptr(), len as u32, buf.index)
+            .offset64(offset as libc::off64_t)
+            .rw_flags(self.uring.rw_flags)
+            .build();
+        let op = OwnedOp::new(self.uring.push(self.prepare(entry)), buf);
+        async move {
+            let (cqe, buf) = op.await;
+            (cqueue::result_to_io(cqe.result()).map(|n| n as usize), buf)
+        }
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Drop for FixedBufferPool<S, C> {
+    fn drop(&mut self) {
+        self.uring.unregister_buffers().ok();
+    }
+}
+
+/// A registered buffer taken from a [`FixedBufferPool`], which dereferences to
+/// the whole buffer. The buffer is returned to the pool when it is dropped.
+pub struct PoolBuf {
+    memory: Rc<PoolMemory>,
+    index: u16,
+}
+
+impl PoolBuf {
+    /// Returns the index of the registered buffer, for use as the `buf_index` of
+    /// a fixed-buffer operation.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+}
+
+impl std::ops::Deref for PoolBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let start = self.index as usize * self.memory.len;
+        unsafe { std::slice::from_raw_parts(self.memory.mem.cast::<u8>().add(start), self.memory.len) }
+    }
+}
+
+impl std::ops::DerefMut for PoolBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let start = self.index as usize * self.memory.len;
+        unsafe { std::slice::from_raw_parts_mut(self.memory.mem.cast::<u8>().add(start), self.memory.len) }
+    }
+}
+
+impl Drop for PoolBuf {
+    fn drop(&mut self) {
+        self.memory.free.borrow_mut().push(self.index);
+    }
+}
+
+/// Returns the default huge page size of the system, as reported by
+/// `/proc/meminfo`.
+pub fn huge_page_size() -> io::Result<usize> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo")?;
+    meminfo.lines()
+        .find_map(|line| line.strip_prefix("Hugepagesize:"))
+        .and_then(|size| size.trim().strip_suffix("kB")?.trim().parse::<usize>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "the kernel does not support huge pages"))
+}
+
+/// Buffers backed by huge pages (`MAP_HUGETLB`) that are registered with the
+/// kernel for use with fixed-buffer operations, which reduces TLB misses for
+/// bulk transfers. Buffers are referred to by their index, from 0 up to
+/// [`HugePageBuffers::nr`], as the `buf_index` of an operation such as
+/// `ReadFixed`.
+///
+/// Like [`FixedBufferPool`], the buffers are registered with
+/// [`IoUringAsync::register_buffers`], so a ring can only have one set of
+/// registered buffers, and they are unregistered when this is dropped.
+pub struct HugePageBuffers<S: squeue::Entry = io_uring::squeue::Entry, C: cqueue::Entry = io_uring::cqueue::Entry> {
+    uring: Rc<IoUringAsync<S, C>>,
+    mem: *mut u8,
+    map_len: usize,
+    len: usize,
+    nr: u16,
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> HugePageBuffers<S, C> {
+    /// Map `nr` buffers of `len` bytes from huge pages and register them with the
+    /// kernel. So that no buffer straddles two huge pages, `len` must either be
+    /// a multiple of [`huge_page_size`] or divide it, or an `InvalidInput` error
+    /// is returned. If not enough huge pages are reserved, such as with the
+    /// `vm.nr_hugepages` sysctl, an `OutOfMemory` error is returned.
+    pub fn new(uring: Rc<IoUringAsync<S, C>>, nr: u16, len: usize) -> io::Result<Self> {
+        let page_size = huge_page_size()?;
+        if len == 0 || !(len.is_multiple_of(page_size) || page_size.is_multiple_of(len)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("buffer length {} must be a multiple or divisor of the huge page size of {} bytes", len, page_size),
+            ));
+        }
+        let map_len = (nr as usize).checked_mul(len)
+            .filter(|map_len| *map_len > 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid number of buffers"))?
+            .next_multiple_of(page_size);
+        let mem = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB,
+                -1,
+                0,
+            )
+        };
+        if mem == libc::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENOMEM) {
+                return Err(io::Error::new(io::ErrorKind::OutOfMemory, "not enough huge pages are reserved, see vm.nr_hugepages"));
+            }
+            return Err(err);
+        }
+        let mem = mem.cast::<u8>();
+        let iovecs: Vec<_> = (0..nr as usize).map(|index| libc::iovec {
+            iov_base: unsafe { mem.add(index * len) }.cast(),
+            iov_len: len,
+        }).collect();
+        // The buffers are only built once they are registered, since dropping
+        // them unregisters the ring's buffers, which may belong to another
+        // owner if registration fails, such as with EBUSY.
+        if let Err(err) = unsafe { uring.register_buffers(&iovecs) } {
+            unsafe { libc::munmap(mem.cast(), map_len) };
+            return Err(err);
+        }
+        Ok(Self { uring, mem, map_len, len, nr })
+    }
+
+    /// Returns the number of buffers.
+    pub fn nr(&self) -> u16 {
+        self.nr
+    }
+
+    /// Returns the length of each buffer.
+    pub fn buf_len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the buffer at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of range.
+    pub fn buf(&self, index: u16) -> &[u8] {
+        assert!(index < self.nr, "buffer index out of range");
+        unsafe { std::slice::from_raw_parts(self.mem.add(index as usize * self.len), self.len) }
+    }
+
+    /// Returns the buffer at `index` mutably. The buffer must not be in use by
+    /// an operation in flight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of range.
+    pub fn buf_mut(&mut self, index: u16) -> &mut [u8] {
+        assert!(index < self.nr, "buffer index out of range");
+        unsafe { std::slice::from_raw_parts_mut(self.mem.add(index as usize * self.len), self.len) }
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Drop for HugePageBuffers<S, C> {
+    fn drop(&mut self) {
+        self.uring.unregister_buffers().ok();
+        unsafe { libc::munmap(self.mem.cast(), self.map_len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use io_uring::{opcode, types};
+    use io_uring::squeue::Flags;
+    use crate::{cqueue, IoUringAsync};
+    use crate::squeue::IoPriority;
+    use super::{huge_page_size, FixedBufferPool, HugePageBuffers};
+
+    #[test]
+    fn read_fixed_fixed() {
+        let path = std::env::temp_dir().join(format!("io-uring-async-read-fixed-{}", std::process::id()));
+        std::fs::write(&path, b"hello world").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut buf = vec![0u8; 64];
+        let err = uring.read_fixed_fixed(0, 0, 0, 5).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        uring.register_files_sparse(1).unwrap();
+        uring.uring.inner.submitter().register_files_update(0, &[file.as_raw_fd()]).unwrap();
+        let err = uring.read_fixed_fixed(0, 0, 0, 5).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let iovec = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+        unsafe { uring.register_buffers(&[iovec]).unwrap() };
+        let err = uring.read_fixed_fixed(0, 0, 0, 128).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let op = uring.read_fixed_fixed(0, 0, 6, 5).unwrap();
+        let cqe = crate::tests::run(&uring, op);
+        assert_eq!(cqe.result(), 5);
+        assert_eq!(&buf[..5], b"world");
+    }
+
+    #[test]
+    fn provide_buffers() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, mut b) = UnixStream::pair().unwrap();
+        let recv = || {
+            opcode::Recv::new(types::Fd(a.as_raw_fd()), std::ptr::null_mut(), 16)
+                .buf_group(3)
+                .build()
+                .flags(Flags::BUFFER_SELECT)
+        };
+        crate::tests::run(&uring, async {
+            let err = uring.provide_buffers(vec![0u8; 20].into_boxed_slice(), 16, 3, 0).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let op = uring.provide_buffers(vec![0u8; 32].into_boxed_slice(), 16, 3, 0).unwrap();
+            assert_eq!(op.await.result(), 0);
+
+            b.write_all(b"hello").unwrap();
+            let cqe = uring.push(recv()).await;
+            assert_eq!(cqe.result(), 5);
+            let bid = cqueue::buffer_id(&cqe).unwrap();
+            assert_eq!(&unsafe { uring.provided_buffer(3, bid) }.unwrap()[..5], b"hello");
+
+            assert_eq!(uring.reprovide_buffer(3, bid).unwrap().await.result(), 0);
+            assert_eq!(uring.remove_buffers(8, 3).await.result(), 2);
+            b.write_all(b"world").unwrap();
+            assert_eq!(uring.push(recv()).await.result(), -libc::ENOBUFS);
+        });
+    }
+
+    #[test]
+    fn fixed_buffer_pool() {
+        let path = std::env::temp_dir().join(format!("io-uring-async-buffer-pool-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut pool = FixedBufferPool::new(uring.clone(), 2, 16).unwrap();
+        pool.set_ioprio(Some(IoPriority::BestEffort(0)));
+        let fd = file.as_raw_fd();
+        crate::tests::run(&uring, async {
+            let mut a = pool.get().unwrap();
+            let b = pool.get().unwrap();
+            assert_ne!(a.index(), b.index());
+            assert!(pool.get().is_none());
+            drop(b);
+            assert_eq!(pool.available(), 1);
+
+            a[..5].copy_from_slice(b"hello");
+            let (written, a) = pool.write_fixed(fd, a, 0, 5).await;
+            assert_eq!(written.unwrap(), 5);
+            drop(a);
+
+            let (read, buf) = pool.read_fixed(fd, pool.get().unwrap(), 0).await;
+            assert_eq!(read.unwrap(), 5);
+            assert_eq!(&buf[..5], b"hello");
+        });
+        assert_eq!(pool.available(), 2);
+        drop(pool);
+        assert!(uring.fixed_buffers.borrow().is_empty());
+    }
+
+    #[test]
+    fn huge_page_buffers() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let page_size = huge_page_size().unwrap();
+        let err = HugePageBuffers::new(uring.clone(), 2, 3000).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let mut buffers = match HugePageBuffers::new(uring.clone(), 4, page_size / 4) {
+            Ok(buffers) => buffers,
+            // No huge pages are reserved on this machine.
+            Err(err) if err.kind() == io::ErrorKind::OutOfMemory => return,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(buffers.nr(), 4);
+
+        let (a, mut b) = UnixStream::pair().unwrap();
+        buffers.buf_mut(3)[..5].copy_from_slice(b"hello");
+        let entry = opcode::WriteFixed::new(types::Fd(a.as_raw_fd()), buffers.buf(3).as_ptr(), 5, 3).build();
+        assert_eq!(crate::tests::run(&uring, uring.push(entry)).result(), 5);
+        let mut buf = [0; 5];
+        std::io::Read::read_exact(&mut b, &mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        drop(buffers);
+        assert!(uring.fixed_buffers.borrow().is_empty());
+    }
+
+    #[test]
+    fn huge_page_buffers_busy() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let pool = FixedBufferPool::new(uring.clone(), 2, 16).unwrap();
+        match HugePageBuffers::new(uring.clone(), 1, huge_page_size().unwrap()) {
+            Err(err) if err.raw_os_error() == Some(libc::EBUSY) => {}
+            // No huge pages are reserved on this machine.
+            Err(err) if err.kind() == io::ErrorKind::OutOfMemory => return,
+            Err(err) => panic!("{}", err),
+            Ok(_) => panic!("registered buffers on a ring that already has them"),
+        }
+
+        // The pool's registration is left in place.
+        assert_eq!(uring.fixed_buffers.borrow().len(), 2);
+        let (a, mut b) = UnixStream::pair().unwrap();
+        let mut buf = pool.get().unwrap();
+        buf[..5].copy_from_slice(b"hello");
+        let (written, _) = crate::tests::run(&uring, pool.write_fixed(a.as_raw_fd(), buf, 0, 5));
+        assert_eq!(written.unwrap(), 5);
+        let mut buf = [0; 5];
+        std::io::Read::read_exact(&mut b, &mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
@@ -0,0 +1,497 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+use crate::{cqueue, squeue, IoUringAsync, Ring};
+
+/// A builder for an [`IoUringAsync`] with a custom configuration.
+pub struct Builder<S: squeue::Entry = io_uring::squeue::Entry, C: cqueue::Entry = io_uring::cqueue::Entry> {
+    builder: io_uring::Builder<S, C>,
+    readiness_fallback: bool,
+    completion_coalesce: Duration,
+    on_completion: Option<Rc<crate::trace::CompletionHook>>,
+    on_unexpected_completion: Option<Rc<crate::trace::UnexpectedHook>>,
+    setup_flags: Vec<SetupFlag>,
+    max_in_flight: Option<usize>,
+    latency_stats: bool,
+    single_task: bool,
+    rw_flags: io_uring::types::RwFlags,
+}
+
+// A setup flag requested through the Builder. The kernel rejects flags that it
+// does not know with EINVAL, and reports the flags that it honored once the
+// ring is set up, both of which are checked by Builder::build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SetupFlag {
+    Sqpoll(u32),
+    SingleIssuer,
+    // Also applies SingleIssuer, which the kernel requires.
+    DeferTaskrun,
+    // Also applies COOP_TASKRUN, without which the flag is rarely observed.
+    TaskrunFlag,
+}
+
+impl SetupFlag {
+    fn name(self) -> &'static str {
+        match self {
+            SetupFlag::Sqpoll(_) => "IORING_SETUP_SQPOLL",
+            SetupFlag::SingleIssuer => "IORING_SETUP_SINGLE_ISSUER",
+            SetupFlag::DeferTaskrun => "IORING_SETUP_DEFER_TASKRUN",
+            SetupFlag::TaskrunFlag => "IORING_SETUP_TASKRUN_FLAG",
+        }
+    }
+
+    // The first kernel version that supports the flag.
+    fn since(self) -> &'static str {
+        match self {
+            SetupFlag::Sqpoll(_) => "5.1",
+            SetupFlag::SingleIssuer => "6.0",
+            SetupFlag::DeferTaskrun => "6.1",
+            SetupFlag::TaskrunFlag => "5.19",
+        }
+    }
+
+    fn apply<S: squeue::Entry, C: cqueue::Entry>(self, builder: &mut io_uring::Builder<S, C>) {
+        match self {
+            SetupFlag::Sqpoll(idle) => builder.setup_sqpoll(idle),
+            SetupFlag::SingleIssuer => builder.setup_single_issuer(),
+            SetupFlag::DeferTaskrun => builder.setup_single_issuer().setup_defer_taskrun(),
+            SetupFlag::TaskrunFlag => builder.setup_coop_taskrun().setup_taskrun_flag(),
+        };
+    }
+
+    fn is_honored(self, params: &io_uring::Parameters) -> bool {
+        match self {
+            SetupFlag::Sqpoll(_) => params.is_setup_sqpoll(),
+            SetupFlag::SingleIssuer | SetupFlag::DeferTaskrun => params.is_setup_single_issuer(),
+            // The parameters do not report the flag, which the kernel either
+            // honors or rejects.
+            SetupFlag::TaskrunFlag => true,
+        }
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Builder<S, C> {
+    pub(crate) fn new() -> Self {
+        Self {
+            builder: io_uring::IoUring::generic_builder(),
+            readiness_fallback: true,
+            completion_coalesce: Duration::ZERO,
+            on_completion: None,
+            on_unexpected_completion: None,
+            setup_flags: Vec::new(),
+            max_in_flight: None,
+            latency_stats: false,
+            single_task: false,
+            rw_flags: 0,
+        }
+    }
+
+    /// Enable or disable the readiness-based fallback for opcodes that the kernel
+    /// does not support. When enabled, which is the default, helpers such as
+    /// [`IoUringAsync::accept`] wait for readiness with a `PollAdd` and then
+    /// perform a non-blocking syscall if the kernel lacks the corresponding
+    /// opcode. Users that require every operation to be performed by the io_uring
+    /// can disable the fallback, in which case the kernel's error is returned.
+    pub fn readiness_fallback(&mut self, enabled: bool) -> &mut Self {
+        self.readiness_fallback = enabled;
+        self
+    }
+
+    /// Delay the processing of completions by [`IoUringAsync::listen`] for `delay`
+    /// after the ring becomes readable, so that a burst of completions is
+    /// processed together with fewer task wakeups. This trades a little latency
+    /// for throughput. Every completion is still processed, since the delay only
+    /// postpones when the completion queue is drained. The default of zero
+    /// processes completions as soon as the ring becomes readable.
+    ///
+    /// A non-zero delay requires the tokio runtime to have the time driver enabled.
+    pub fn completion_coalesce(&mut self, delay: Duration) -> &mut Self {
+        self.completion_coalesce = delay;
+        self
+    }
+
+    /// Install a hook that is called with a [`CompletionEvent`](crate::trace::CompletionEvent)
+    /// for every completion of an Op pushed with [`IoUringAsync::push`] or
+    /// [`IoUringAsync::push_tagged`], reporting the Op's opcode, result and
+    /// latency. This can be used to emit tracing spans or record latency
+    /// histograms. When no hook is installed, no timestamps are taken.
+    ///
+    /// The hook is called before the Op is woken, and may push further Ops.
+    pub fn on_completion(&mut self, hook: impl Fn(&crate::trace::CompletionEvent) + 'static) -> &mut Self {
+        self.on_completion = Some(Rc::new(hook));
+        self
+    }
+
+    /// Record how long each Op spends in the submission queue and in the kernel,
+    /// as [`RingStats::sq_time`](crate::stats::RingStats::sq_time) and
+    /// [`RingStats::kernel_time`](crate::stats::RingStats::kernel_time). This
+    /// tells apart latency caused by batching submissions from latency caused by
+    /// the kernel or io-wq. Two timestamps are taken per Op, so this is off by
+    /// default.
+    pub fn latency_stats(&mut self) -> &mut Self {
+        self.latency_stats = true;
+        self
+    }
+
+    /// Optimize for Ops that are all awaited from the same task, as is common
+    /// when a single task drives the ring. The Ops then share one registered
+    /// waker rather than each cloning the task's waker, and the task is woken
+    /// once for a batch of completions rather than once per Op.
+    ///
+    /// This only affects performance. An Op that is awaited from another task
+    /// while Ops of the first task are waiting, such as one moved into a
+    /// spawned task, keeps a waker of its own as without this option.
+    pub fn single_task(&mut self) -> &mut Self {
+        self.single_task = true;
+        self
+    }
+
+    /// Install a hook that is called with an
+    /// [`UnexpectedCompletion`](crate::trace::UnexpectedCompletion) whenever a
+    /// completion queue entry arrives for an Op that has already completed,
+    /// for example because an entry that posts multiple completions was pushed
+    /// with [`IoUringAsync::push`]. Such entries are discarded and counted in
+    /// [`RingStats::unexpected_completions`](crate::stats::RingStats::unexpected_completions).
+    /// The hook is also called for entries that do not belong to any Op, which
+    /// are counted in [`RingStats::orphan_completions`](crate::stats::RingStats::orphan_completions).
+    ///
+    /// The hook may push further Ops.
+    pub fn on_unexpected_completion(&mut self, hook: impl Fn(&crate::trace::UnexpectedCompletion) + 'static) -> &mut Self {
+        self.on_unexpected_completion = Some(Rc::new(hook));
+        self
+    }
+
+    /// Poll the submission queue from a kernel thread (`IORING_SETUP_SQPOLL`),
+    /// which goes to sleep after `idle` without new entries. Submitting then
+    /// rarely needs a syscall.
+    pub fn sqpoll(&mut self, idle: Duration) -> &mut Self {
+        self.setup_flag(SetupFlag::Sqpoll(idle.as_millis().try_into().unwrap_or(u32::MAX)))
+    }
+
+    /// Promise the kernel that only a single thread submits to the ring
+    /// (`IORING_SETUP_SINGLE_ISSUER`), which enables some optimizations. An
+    /// `IoUringAsync` is not `Send`, so this always holds unless the ring's file
+    /// descriptor is shared. Requires Linux 6.0.
+    pub fn single_issuer(&mut self) -> &mut Self {
+        self.setup_flag(SetupFlag::SingleIssuer)
+    }
+
+    /// Defer the kernel's completion work until the ring is entered to wait for
+    /// completions (`IORING_SETUP_DEFER_TASKRUN`), rather than interrupting the
+    /// thread to run it. This reduces context switches under load. The deferred
+    /// work is run by [`IoUringAsync::listen`] and
+    /// [`IoUringAsync::process_completions`] before processing completions.
+    /// Implies [`Builder::single_issuer`]. Requires Linux 6.1.
+    pub fn defer_taskrun(&mut self) -> &mut Self {
+        self.setup_flag(SetupFlag::DeferTaskrun)
+    }
+
+    /// Have the kernel set `IORING_SQ_TASKRUN` in the submission queue flags
+    /// whenever completion work is pending (`IORING_SETUP_TASKRUN_FLAG`), so that
+    /// [`IoUringAsync::needs_completion_processing`] can tell without a syscall
+    /// whether the ring must be entered to post completions.
+    ///
+    /// Implies `IORING_SETUP_COOP_TASKRUN`, which stops the kernel from
+    /// interrupting the thread to run its completion work. That work is instead
+    /// run the next time that the thread enters the kernel, which may be a while
+    /// on a busy thread, and the flag is how the listen loop and
+    /// [`IoUringAsync::process_completions`] notice it without entering the ring
+    /// on every pass. Has no effect together with [`Builder::defer_taskrun`],
+    /// which always enters the ring. Requires Linux 5.19.
+    pub fn taskrun_flag(&mut self) -> &mut Self {
+        self.setup_flag(SetupFlag::TaskrunFlag)
+    }
+
+    fn setup_flag(&mut self, flag: SetupFlag) -> &mut Self {
+        flag.apply(&mut self.builder);
+        self.setup_flags.push(flag);
+        self
+    }
+
+    /// Limit the number of Ops in flight that [`IoUringAsync::try_push`] admits.
+    /// On kernels without `IORING_FEAT_NODROP` (older than 5.5), this defaults to
+    /// the size of the completion queue, which cannot overflow while every Op is
+    /// pushed with `try_push`, since such kernels drop completions that do not
+    /// fit. Kernels with the feature back up those completions until the queue
+    /// has room instead, so no limit is applied by default.
+    pub fn max_in_flight(&mut self, limit: usize) -> &mut Self {
+        self.max_in_flight = Some(limit);
+        self
+    }
+
+    /// Set the `RWF_*` flags, such as `RWF_HIPRI`, that are applied to every read
+    /// and write pushed by the helpers of this crate, such as
+    /// [`IoUringAsync::read`] and [`FixedBufferPool::read_fixed`](crate::buffers::FixedBufferPool::read_fixed),
+    /// unless they are overridden for an operation with
+    /// [`Read::rw_flags`](crate::ops::Read::rw_flags). Defaults to none.
+    pub fn rw_flags(&mut self, rw_flags: io_uring::types::RwFlags) -> &mut Self {
+        self.rw_flags = rw_flags;
+        self
+    }
+
+    /// Size the completion queue for `entries` entries (`IORING_SETUP_CQSIZE`),
+    /// rather than twice the number of submission queue entries, so that more
+    /// completions can be buffered before the queue overflows. The kernel rounds
+    /// `entries` up to a power of two. On kernels that drop completions that
+    /// do not fit, the default limit of [`Builder::max_in_flight`] follows the
+    /// size of the completion queue.
+    ///
+    /// The completion queue cannot be grown with [`IoUringAsync::resize_cq`]
+    /// once the ring is set up, since the io_uring crate maps the queues only
+    /// once, so it should be sized for the peak load here.
+    pub fn cq_entries(&mut self, entries: u32) -> &mut Self {
+        self.builder.setup_cqsize(entries);
+        self
+    }
+
+    /// Clamp the number of entries to the maximum supported by the kernel
+    /// (`IORING_SETUP_CLAMP`), rather than failing if `entries` is too large.
+    pub fn clamp(&mut self) -> &mut Self {
+        self.builder.setup_clamp();
+        self
+    }
+
+    /// Build an [`IoUringAsync`] with `entries` submission queue entries, which
+    /// are rounded up to a power of two as the kernel requires.
+    ///
+    /// Returns an `InvalidInput` error if `entries` is zero. If the kernel
+    /// refuses to set up the ring with `EPERM` or `ENOSYS`, or does not
+    /// support a requested setup flag such as [`Builder::single_issuer`], the
+    /// error wraps a [`SetupError`] explaining the likely cause. A requested
+    /// setup flag that the kernel accepted but did not honor is reported as an
+    /// `Unsupported` error, rather than silently running without it.
+    pub fn build(&self, entries: u32) -> std::io::Result<IoUringAsync<S, C>> {
+        if entries == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "io_uring entries must not be zero"));
+        }
+        // Counts too large to round up exceed the kernel's maximum, which the
+        // kernel rejects or clamps.
+        let entries = entries.checked_next_power_of_two().unwrap_or(entries);
+        let uring = self.builder.build(entries).map_err(|err| self.unsupported_flag(err))?;
+        if let Some(flag) = self.setup_flags.iter().find(|flag| !flag.is_honored(uring.params())) {
+            let source = std::io::Error::from(std::io::ErrorKind::Unsupported);
+            let hint = format!("the kernel accepted {} but did not enable it", flag.name());
+            return Err(std::io::Error::new(source.kind(), SetupError { source, hint }));
+        }
+
+        // Probing is not supported by kernels older than 5.6, which also lack
+        // most opcodes. Such kernels are treated as supporting no opcodes.
+        let mut probe = io_uring::Probe::new();
+        let probe = uring.submitter().register_probe(&mut probe).ok().map(|_| probe);
+        // Completions that overflow the completion queue are only lost on
+        // kernels without IORING_FEAT_NODROP, so only those need a limit.
+        let max_in_flight_follows_cq = self.max_in_flight.is_none() && !uring.params().is_feature_nodrop();
+        let max_in_flight = match self.max_in_flight {
+            Some(limit) => limit,
+            None if max_in_flight_follows_cq => uring.params().cq_entries() as usize,
+            None => usize::MAX,
+        };
+
+        Ok(IoUringAsync {
+            uring: Rc::new(Ring {
+                on_completion: self.on_completion.clone(),
+                on_unexpected_completion: self.on_unexpected_completion.clone(),
+                submit_times: self.latency_stats.then(RefCell::default),
+                task_waker: self.single_task.then(RefCell::default),
+                ..Ring::new(uring)
+            }),
+            slab: Rc::new(RefCell::default()),
+            probe,
+            readiness_fallback: self.readiness_fallback,
+            file_slots: Rc::default(),
+            fixed_buffers: RefCell::default(),
+            completion_coalesce: self.completion_coalesce,
+            defer_taskrun: self.setup_flags.contains(&SetupFlag::DeferTaskrun),
+            taskrun_flag: self.setup_flags.contains(&SetupFlag::TaskrunFlag),
+            max_in_flight: Cell::new(max_in_flight),
+            max_in_flight_follows_cq,
+            rw_flags: self.rw_flags,
+            #[cfg(feature = "op-sequence")]
+            next_seq: std::cell::Cell::new(0),
+        })
+    }
+}
+
+/// The error returned when the kernel refuses to set up an io_uring for a
+/// reason that is usually caused by the environment rather than the caller,
+/// such as a sysctl, a seccomp filter, or a container runtime blocking
+/// `io_uring_setup`. It is returned as the inner error of an `io::Error` with
+/// the same kind as the underlying OS error.
+#[derive(Debug)]
+pub struct SetupError {
+    source: std::io::Error,
+    hint: String,
+}
+
+impl SetupError {
+    /// Returns the OS error reported by `io_uring_setup`.
+    pub fn os_error(&self) -> &std::io::Error {
+        &self.source
+    }
+}
+
+impl std::fmt::Display for SetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "io_uring_setup failed: {}: {}", self.source, self.hint)
+    }
+}
+
+impl std::error::Error for SetupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Builder<S, C> {
+    // Explain which requested setup flag caused `io_uring_setup` to fail with
+    // EINVAL, by setting up a ring with each flag on its own. Other errors are
+    // explained by setup_error.
+    fn unsupported_flag(&self, err: std::io::Error) -> std::io::Error {
+        if err.raw_os_error() != Some(libc::EINVAL) {
+            return setup_error(err);
+        }
+        let unsupported = self.setup_flags.iter().find(|flag| {
+            let mut builder = io_uring::IoUring::<S, C>::generic_builder();
+            flag.apply(&mut builder);
+            builder.build(1).is_err_and(|err| err.raw_os_error() == Some(libc::EINVAL))
+        });
+        let Some(flag) = unsupported else {
+            return err;
+        };
+        let hint = format!("the kernel does not support {}, which requires Linux {}", flag.name(), flag.since());
+        std::io::Error::new(err.kind(), SetupError { source: err, hint })
+    }
+}
+
+// Explain the likely cause of `io_uring_setup` failing with EPERM or ENOSYS.
+// Other errors are returned unchanged.
+fn setup_error(err: std::io::Error) -> std::io::Error {
+    let hint = match err.raw_os_error() {
+        Some(libc::EPERM) => match std::fs::read_to_string("/proc/sys/kernel/io_uring_disabled") {
+            Ok(disabled) if disabled.trim() != "0" => format!(
+                "io_uring is restricted by kernel.io_uring_disabled = {}, check /proc/sys/kernel/io_uring_disabled",
+                disabled.trim(),
+            ),
+            _ => "io_uring_setup is blocked, check for a seccomp filter or container runtime that denies io_uring".to_string(),
+        },
+        Some(libc::ENOSYS) => "io_uring is not available, the kernel is older than 5.1, was built without io_uring, or a seccomp filter denies the syscall".to_string(),
+        _ => return err,
+    };
+    std::io::Error::new(err.kind(), SetupError { source: err, hint })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::Duration;
+    use io_uring::opcode::Nop;
+    use crate::IoUringAsync;
+    use super::{setup_error, SetupError};
+
+    #[test]
+    fn completion_coalesce() {
+        let uring = IoUringAsync::builder()
+            .completion_coalesce(Duration::from_millis(5))
+            .build(8)
+            .unwrap();
+        let uring = Rc::new(uring);
+        crate::tests::run(&uring, async {
+            let ops: Vec<_> = (0..16).map(|_| uring.push(Nop::new().build())).collect();
+            for op in ops {
+                assert_eq!(op.await.result(), 0);
+            }
+        });
+    }
+
+    #[test]
+    fn entries() {
+        let err = IoUringAsync::new(0).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(IoUringAsync::new(10).unwrap().params().sq_entries(), 16);
+
+        let uring = IoUringAsync::builder().clamp().build(1 << 20).unwrap();
+        assert!(uring.uring.inner.params().sq_entries() < 1 << 20);
+
+        let uring = IoUringAsync::builder().cq_entries(100).build(8).unwrap();
+        assert_eq!(uring.params().cq_entries(), 128);
+    }
+
+    #[test]
+    fn setup_flags() {
+        let uring = IoUringAsync::builder()
+            .single_issuer()
+            .sqpoll(Duration::from_millis(10))
+            .build(8)
+            .unwrap();
+        assert!(uring.params().is_setup_single_issuer());
+        assert!(uring.params().is_setup_sqpoll());
+        let uring = Rc::new(uring);
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.push(Nop::new().build()).await.result(), 0);
+        });
+
+        // An error that is not caused by a requested flag is returned unchanged.
+        let err = IoUringAsync::builder().single_issuer().unsupported_flag(std::io::Error::from_raw_os_error(libc::EINVAL));
+        assert!(err.get_ref().is_none());
+    }
+
+    #[test]
+    fn defer_taskrun() {
+        let uring = Rc::new(IoUringAsync::builder().defer_taskrun().build(8).unwrap());
+        let (a, mut b) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut buf = [0u8; 4];
+        crate::tests::run(&uring, async {
+            let timespec = crate::time::timespec(Duration::from_millis(5));
+            assert_eq!(uring.push(io_uring::opcode::Timeout::new(&timespec).build()).await.result(), -libc::ETIME);
+
+            let fd = io_uring::types::Fd(std::os::unix::prelude::AsRawFd::as_raw_fd(&a));
+            let recv = uring.push(io_uring::opcode::Recv::new(fd, buf.as_mut_ptr(), 4).build());
+            uring.submit().unwrap();
+            std::io::Write::write_all(&mut b, b"ping").unwrap();
+            assert_eq!(recv.await.result(), 4);
+        });
+        assert_eq!(&buf, b"ping");
+
+        // The eventfd is unregistered when the listen loop is dropped, so that
+        // a later loop can register its own, and a stopped loop returns at once.
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.push(Nop::new().build()).await.result(), 0);
+            uring.shutdown(Duration::from_millis(10)).await.unwrap();
+            tokio::task::spawn_local(IoUringAsync::listen(uring.clone())).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn taskrun_flag() {
+        let uring = Rc::new(IoUringAsync::builder().taskrun_flag().build(8).unwrap());
+        assert!(!uring.needs_completion_processing());
+        let (a, mut b) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut buf = [0u8; 4];
+        crate::tests::run(&uring, async {
+            let fd = io_uring::types::Fd(std::os::unix::prelude::AsRawFd::as_raw_fd(&a));
+            let recv = uring.push(io_uring::opcode::Recv::new(fd, buf.as_mut_ptr(), 4).build());
+            uring.submit().unwrap();
+            std::io::Write::write_all(&mut b, b"ping").unwrap();
+            assert_eq!(recv.await.result(), 4);
+            assert_eq!(uring.push(Nop::new().build()).await.result(), 0);
+            assert!(!uring.needs_completion_processing());
+        });
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[test]
+    fn setup_errors() {
+        let err = setup_error(std::io::Error::from_raw_os_error(libc::ENOSYS));
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        let inner = err.get_ref().unwrap().downcast_ref::<SetupError>().unwrap();
+        assert_eq!(inner.os_error().raw_os_error(), Some(libc::ENOSYS));
+        assert!(err.to_string().contains("seccomp"));
+
+        let err = setup_error(std::io::Error::from_raw_os_error(libc::EPERM));
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("io_uring"));
+
+        let err = setup_error(std::io::Error::from_raw_os_error(libc::EINVAL));
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    }
+}
@@ -0,0 +1,195 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+use crate::{cqueue, Op};
+
+/// Wait for the first of two Ops to complete. Resolves to the index of the
+/// winner (0 for `a`, 1 for `b`) and its completion queue entry.
+///
+/// The losing Op is canceled, and `race` does not resolve until both the loser
+/// and the cancel request have completed. This guarantees that the kernel is
+/// done with any resources used by the loser once `race` returns.
+pub async fn race<C: cqueue::Entry>(mut a: Op<C>, mut b: Op<C>) -> (usize, C) {
+    let (winner, cqe) = std::future::poll_fn(|cx| {
+        if let Poll::Ready(cqe) = Pin::new(&mut a).poll(cx) {
+            return Poll::Ready((0, cqe));
+        }
+        if let Poll::Ready(cqe) = Pin::new(&mut b).poll(cx) {
+            return Poll::Ready((1, cqe));
+        }
+        Poll::Pending
+    }).await;
+
+    let loser = if winner == 0 { b } else { a };
+    loser.cancel_and_wait().await;
+    (winner, cqe)
+}
+
+/// Wait for every Op to complete successfully, resolving to their results in
+/// order. If any Op completes with a negative result, the remaining Ops are
+/// canceled and the error is returned as soon as they have all been reaped, so
+/// the kernel is done with every Op once `try_join_all` returns.
+pub async fn try_join_all<C: cqueue::Entry>(ops: Vec<Op<C>>) -> std::io::Result<Vec<u32>> {
+    let mut ops: Vec<Option<Op<C>>> = ops.into_iter().map(Some).collect();
+    let mut results = vec![0; ops.len()];
+    let error = std::future::poll_fn(|cx| {
+        for (op, result) in ops.iter_mut().zip(results.iter_mut()) {
+            let Some(pending) = op else { continue };
+            if let Poll::Ready(cqe) = Pin::new(pending).poll(cx) {
+                *op = None;
+                match cqueue::result_to_io(cqe.result()) {
+                    Ok(n) => *result = n,
+                    Err(err) => return Poll::Ready(Some(err)),
+                }
+            }
+        }
+        if ops.iter().all(Option::is_none) {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }).await;
+
+    let Some(error) = error else {
+        return Ok(results);
+    };
+    let mut cancels: Vec<_> = ops.into_iter().flatten().map(|op| Box::pin(op.cancel_and_wait())).collect();
+    std::future::poll_fn(|cx| {
+        cancels.retain_mut(|cancel| cancel.as_mut().poll(cx).is_pending());
+        if cancels.is_empty() { Poll::Ready(()) } else { Poll::Pending }
+    }).await;
+    Err(error)
+}
+
+/// How [`retry_eagain`] retries an operation that completes with `-EAGAIN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of times the operation is retried. Once exhausted, the
+    /// `-EAGAIN` completion queue entry is returned to the caller.
+    pub max_retries: u32,
+    /// The delay before the first retry, which doubles with every subsequent
+    /// retry. If `None`, the operation is retried immediately. Backing off
+    /// requires the tokio runtime to have the time driver enabled.
+    pub backoff: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_retries: 3, backoff: None }
+    }
+}
+
+/// Push an Op with `push`, pushing a fresh Op each time it completes with
+/// `-EAGAIN`, up to the limits of `policy`. Resolves to the completion queue
+/// entry of the last attempt. This is useful for file descriptors that the
+/// kernel cannot poll, where an operation may fail with `-EAGAIN` rather than
+/// waiting for readiness.
+///
+/// The first Op is pushed immediately, before the returned future is polled.
+pub fn retry_eagain<C: cqueue::Entry, F: FnMut() -> Op<C>>(policy: RetryPolicy, mut push: F) -> impl Future<Output = C> {
+    let mut op = push();
+    async move {
+        let mut backoff = policy.backoff;
+        for _ in 0..policy.max_retries {
+            let cqe = op.await;
+            if cqe.result() != -libc::EAGAIN {
+                return cqe;
+            }
+            if let Some(delay) = backoff {
+                tokio::time::sleep(delay).await;
+                backoff = Some(delay.saturating_mul(2));
+            }
+            op = push();
+        }
+        op.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+    use io_uring::{opcode, types};
+    use crate::IoUringAsync;
+    use super::{race, retry_eagain, try_join_all, RetryPolicy};
+
+    #[test]
+    fn race_cancels_loser() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut buf = [0u8; 16];
+        crate::tests::run(&uring, async {
+            let recv = uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), buf.as_mut_ptr(), 16).build());
+            let nop = uring.push(opcode::Nop::new().build());
+            let (winner, cqe) = race(recv, nop).await;
+            assert_eq!(winner, 1);
+            assert_eq!(cqe.result(), 0);
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn retry_eagain_until_success() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        if !uring.supports_nop_result() {
+            return;
+        }
+        crate::tests::run(&uring, async {
+            let mut attempts = 0;
+            let cqe = retry_eagain(RetryPolicy::default(), || {
+                attempts += 1;
+                uring.nop_with_result(if attempts < 3 { -libc::EAGAIN } else { 7 }).unwrap()
+            }).await;
+            assert_eq!(cqe.result(), 7);
+            assert_eq!(attempts, 3);
+        });
+    }
+
+    #[test]
+    fn retry_eagain_exhausted() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        if !uring.supports_nop_result() {
+            return;
+        }
+        crate::tests::run(&uring, async {
+            let policy = RetryPolicy { max_retries: 2, backoff: Some(Duration::from_millis(5)) };
+            let mut attempts = 0;
+            let start = Instant::now();
+            let cqe = retry_eagain(policy, || {
+                attempts += 1;
+                uring.nop_with_result(-libc::EAGAIN).unwrap()
+            }).await;
+            assert_eq!(cqe.result(), -libc::EAGAIN);
+            assert_eq!(attempts, 3);
+            // The retries back off by 5ms and then 10ms.
+            assert!(start.elapsed() >= Duration::from_millis(15));
+        });
+    }
+
+    #[test]
+    fn try_join_all_fails_fast() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        if !uring.supports_nop_result() {
+            return;
+        }
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut bufs = [[0u8; 16]; 2];
+        crate::tests::run(&uring, async {
+            let ops = vec![uring.nop_with_result(1).unwrap(), uring.nop_with_result(2).unwrap()];
+            assert_eq!(try_join_all(ops).await.unwrap(), [1, 2]);
+
+            let [x, y] = &mut bufs;
+            let ops = vec![
+                uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), x.as_mut_ptr(), 16).build()),
+                uring.nop_with_result(-libc::EBADF).unwrap(),
+                uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), y.as_mut_ptr(), 16).build()),
+            ];
+            let err = try_join_all(ops).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+}
@@ -2,6 +2,13 @@ pub trait Entry: io_uring::cqueue::EntryMarker + Unpin + 'static {
     fn user_data(&self) -> u64;
     fn result(&self) -> i32;
     fn flags(&self) -> u32;
+
+    /// Returns the extra 16 bytes of a 32-byte completion queue entry, which
+    /// some opcodes such as `UringCmd80` use to return data, or `None` for a
+    /// 16-byte entry.
+    fn big_cqe(&self) -> Option<&[u64; 2]> {
+        None
+    }
 }
 
 impl Entry for io_uring::cqueue::Entry {
@@ -36,4 +43,70 @@ impl Entry for io_uring::cqueue::Entry32 {
     fn flags(&self) -> u32 {
         self.flags()
     }
+
+    #[inline(always)]
+    fn big_cqe(&self) -> Option<&[u64; 2]> {
+        Some(self.big_cqe())
+    }
+}
+
+/// Convert the result of a completion queue entry into an `io::Result`. A negative
+/// result is interpreted as a negated `errno` value.
+pub fn result_to_io(result: i32) -> std::io::Result<u32> {
+    if result < 0 {
+        Err(std::io::Error::from_raw_os_error(-result))
+    } else {
+        Ok(result as u32)
+    }
+}
+
+// IORING_CQE_F_NOTIF, which is not exposed by the io_uring crate.
+const CQE_F_NOTIF: u32 = 1 << 3;
+
+/// Returns the ID of the provided buffer that the kernel selected for the
+/// operation, if the operation used buffer selection.
+pub fn buffer_id<C: Entry>(cqe: &C) -> Option<u16> {
+    io_uring::cqueue::buffer_select(cqe.flags())
+}
+
+/// Returns whether the kernel will post further completion queue entries for the
+/// multishot operation that produced `cqe`.
+pub fn has_more<C: Entry>(cqe: &C) -> bool {
+    io_uring::cqueue::more(cqe.flags())
+}
+
+/// Returns whether the socket had more data available when the kernel
+/// completed the receive that produced `cqe`.
+pub fn sock_nonempty<C: Entry>(cqe: &C) -> bool {
+    io_uring::cqueue::sock_nonempty(cqe.flags())
+}
+
+/// Returns whether `cqe` is a zero-copy send notification, which signals that
+/// the kernel is done with the buffers of a previous send, rather than the
+/// result of an operation.
+pub fn is_notification<C: Entry>(cqe: &C) -> bool {
+    cqe.flags() & CQE_F_NOTIF != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use io_uring::opcode::Nop;
+    use crate::IoUringAsync;
+    use super::Entry;
+
+    #[test]
+    fn big_cqe() {
+        // The whole 32-byte entry is handed to the Op.
+        let uring = IoUringAsync::<io_uring::squeue::Entry, io_uring::cqueue::Entry32>::generic_new(8).unwrap();
+        let op = uring.push(Nop::new().build());
+        uring.reap_blocking().unwrap();
+        let cqe = op.peek_result().unwrap();
+        assert_eq!(Entry::result(&cqe), 0);
+        assert_eq!(Entry::big_cqe(&cqe), Some(&[0, 0]));
+
+        let uring = IoUringAsync::new(8).unwrap();
+        let op = uring.push(Nop::new().build());
+        uring.reap_blocking().unwrap();
+        assert_eq!(Entry::big_cqe(&op.peek_result().unwrap()), None);
+    }
 }
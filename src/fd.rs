@@ -0,0 +1,278 @@
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::prelude::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use io_uring::{opcode, types};
+use crate::{cqueue, squeue, IoUringAsync, Op, OwnedOp};
+
+// IORING_OP_EPOLL_WAIT, which is not exposed by the io_uring crate.
+const OP_EPOLL_WAIT: u8 = 59;
+
+/// An owned file descriptor for use with the helpers of an [`IoUringAsync`].
+/// The file descriptor is closed when the `OwnedUringFd` is dropped.
+#[derive(Debug)]
+pub struct OwnedUringFd {
+    fd: OwnedFd,
+}
+
+impl OwnedUringFd {
+    /// Returns the capacity of the pipe referred to by this file descriptor.
+    pub fn pipe_size(&self) -> io::Result<usize> {
+        let size = unsafe { libc::fcntl(self.as_raw_fd(), libc::F_GETPIPE_SZ) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size as usize)
+    }
+
+    /// Set the capacity of the pipe referred to by this file descriptor with
+    /// `fcntl(F_SETPIPE_SZ)`. The kernel rounds the size up to a power-of-two
+    /// number of pages, so the actual capacity is returned. Unprivileged users
+    /// may not exceed `/proc/sys/fs/pipe-max-size`. A larger pipe lets each
+    /// splice move more data, which reduces the number of operations needed
+    /// to proxy a stream.
+    pub fn set_pipe_size(&self, size: usize) -> io::Result<usize> {
+        let size = unsafe { libc::fcntl(self.as_raw_fd(), libc::F_SETPIPE_SZ, size as libc::c_int) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(size as usize)
+    }
+}
+
+impl AsRawFd for OwnedUringFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for OwnedUringFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl IntoRawFd for OwnedUringFd {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl FromRawFd for OwnedUringFd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Self { fd: OwnedFd::from_raw_fd(fd) }
+    }
+}
+
+impl From<OwnedFd> for OwnedUringFd {
+    fn from(fd: OwnedFd) -> Self {
+        Self { fd }
+    }
+}
+
+impl From<OwnedUringFd> for OwnedFd {
+    fn from(fd: OwnedUringFd) -> Self {
+        fd.fd
+    }
+}
+
+/// Create a pipe, returning its read and write ends. Both ends are close-on-exec.
+/// The pipe is intended for use with [`IoUringAsync::splice`] and
+/// [`IoUringAsync::tee`]. Its capacity can be changed with
+/// [`OwnedUringFd::set_pipe_size`].
+pub fn pipe() -> io::Result<(OwnedUringFd, OwnedUringFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedUringFd::from_raw_fd(fds[0]), OwnedUringFd::from_raw_fd(fds[1])) })
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Take ownership of a socket or file that was opened elsewhere, such as a
+    /// `std::net::TcpStream`, a `UnixStream`, or a tokio stream converted with
+    /// `into_std`, for use with the helpers of this crate, such as
+    /// [`IoUringAsync::read`] and [`IoUringAsync::write`].
+    ///
+    /// The descriptor is switched to blocking mode by clearing `O_NONBLOCK`,
+    /// which tokio and other reactors set. io_uring never blocks the thread on
+    /// a descriptor either way, but some kernels complete reads and writes of a
+    /// non-blocking descriptor with `EAGAIN` rather than waiting for it to be
+    /// ready. Blocking calls made on the descriptor outside of the ring, such
+    /// as through a `dup` of it, then block the calling thread.
+    pub fn from_std(&self, stream: impl Into<OwnedFd>) -> io::Result<OwnedUringFd> {
+        let fd = stream.into();
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if flags & libc::O_NONBLOCK != 0 && unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(OwnedUringFd::from(fd))
+    }
+
+    /// Wait for events on the epoll instance `epfd`, resolving to up to
+    /// `max_events` ready events as `epoll_wait(2)` would, but without blocking
+    /// the thread. This lets a subsystem built on epoll be driven by the ring.
+    /// Returns an `Unsupported` error if the kernel does not support
+    /// `IORING_OP_EPOLL_WAIT` (Linux 6.15), as reported by the probe.
+    pub fn epoll_wait(&self, epfd: RawFd, max_events: u32) -> impl Future<Output = io::Result<Vec<libc::epoll_event>>> {
+        let op = if self.is_supported(OP_EPOLL_WAIT) {
+            let mut events = Vec::<libc::epoll_event>::with_capacity(max_events as usize);
+            let mut entry = opcode::Nop::new().build();
+            let raw = squeue::raw_mut(&mut entry);
+            raw.opcode = OP_EPOLL_WAIT;
+            raw.fd = epfd;
+            raw.addr = events.as_mut_ptr() as u64;
+            raw.len = max_events;
+            Ok(OwnedOp::new(self.push(entry), events))
+        } else {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "the kernel does not support IORING_OP_EPOLL_WAIT"))
+        };
+        async move {
+            let (cqe, mut events) = op?.await;
+            let n = cqueue::result_to_io(cqe.result())?;
+            unsafe { events.set_len(n as usize) };
+            Ok(events)
+        }
+    }
+
+    /// Close the file descriptor `fd`.
+    pub fn close(&self, fd: RawFd) -> impl Future<Output = io::Result<()>> {
+        let op = self.push(opcode::Close::new(types::Fd(fd)).build());
+        async move {
+            cqueue::result_to_io(op.await.result()).map(|_| ())
+        }
+    }
+
+    /// Move up to `len` bytes from `fd_in` to `fd_out` without copying them through
+    /// userspace. At least one of the descriptors must refer to a pipe. An
+    /// offset of `None` reads or writes at the current file position, and must be
+    /// used for pipes. Returns the number of bytes moved.
+    pub fn splice(&self, fd_in: RawFd, off_in: Option<u64>, fd_out: RawFd, off_out: Option<u64>, len: u32) -> impl Future<Output = io::Result<usize>> {
+        let off_in = off_in.map_or(-1, |off| off as i64);
+        let off_out = off_out.map_or(-1, |off| off as i64);
+        let op = self.push(opcode::Splice::new(types::Fd(fd_in), off_in, types::Fd(fd_out), off_out, len).build());
+        async move {
+            cqueue::result_to_io(op.await.result()).map(|n| n as usize)
+        }
+    }
+
+    /// Duplicate up to `len` bytes from the pipe `fd_in` to the pipe `fd_out`
+    /// without consuming them. Returns the number of bytes duplicated.
+    pub fn tee(&self, fd_in: RawFd, fd_out: RawFd, len: u32) -> impl Future<Output = io::Result<usize>> {
+        let op = self.push(opcode::Tee::new(types::Fd(fd_in), types::Fd(fd_out), len).build());
+        async move {
+            cqueue::result_to_io(op.await.result()).map(|n| n as usize)
+        }
+    }
+
+    /// Close every file descriptor in `fds`. The close operations are pushed as a
+    /// batch and submitted together, amortizing the cost of the submit syscall.
+    /// The result of closing each descriptor is reported independently, in the
+    /// same order as `fds`.
+    ///
+    /// If there are more descriptors than fit in the submission queue, the
+    /// batch is split across as few submissions as possible.
+    pub fn close_all(&self, fds: Vec<RawFd>) -> impl Future<Output = Vec<io::Result<()>>> {
+        let ops: Vec<Op<C>> = fds.into_iter()
+            .map(|fd| self.push(opcode::Close::new(types::Fd(fd)).build()))
+            .collect();
+        // If the submit fails, the entries remain on the submission queue and are
+        // submitted by the next call to submit.
+        self.submit().ok();
+        async move {
+            let mut results = Vec::with_capacity(ops.len());
+            for op in ops {
+                results.push(cqueue::result_to_io(op.await.result()).map(|_| ()));
+            }
+            results
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd};
+    use std::rc::Rc;
+    use crate::IoUringAsync;
+    use super::pipe;
+
+    #[test]
+    fn close_all() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut fds: Vec<_> = (0..3).map(|_| File::open("/dev/null").unwrap().into_raw_fd()).collect();
+        fds.push(-1);
+        let results = crate::tests::run(&uring, uring.close_all(fds));
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|result| result.is_ok()));
+        assert_eq!(results[3].as_ref().unwrap_err().raw_os_error(), Some(libc::EBADF));
+    }
+
+    #[test]
+    fn from_std() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, mut b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        let fd = uring.from_std(a).unwrap();
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        assert_eq!(flags & libc::O_NONBLOCK, 0);
+        crate::tests::run(&uring, async {
+            // The read waits for data that is written after it is pushed.
+            let read = uring.read(fd.as_raw_fd(), vec![0; 16]).push();
+            b.write_all(b"hello").unwrap();
+            let (n, buf) = read.await;
+            assert_eq!(&buf[..n.unwrap()], b"hello");
+        });
+    }
+
+    #[test]
+    fn epoll_wait() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        if !uring.is_supported(super::OP_EPOLL_WAIT) {
+            let err = crate::tests::run(&uring, uring.epoll_wait(-1, 1)).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+            return;
+        }
+        let epfd = unsafe { OwnedFd::from_raw_fd(libc::epoll_create1(libc::EPOLL_CLOEXEC)) };
+        let (r, w) = pipe().unwrap();
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: 7 };
+        assert_eq!(unsafe { libc::epoll_ctl(epfd.as_raw_fd(), libc::EPOLL_CTL_ADD, r.as_raw_fd(), &mut event) }, 0);
+        crate::tests::run(&uring, async {
+            let wait = uring.epoll_wait(epfd.as_raw_fd(), 4);
+            uring.submit().unwrap();
+            File::from(OwnedFd::from(w)).write_all(b"x").unwrap();
+            let events = wait.await.unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!({ events[0].u64 }, 7);
+            assert_ne!({ events[0].events } & libc::EPOLLIN as u32, 0);
+        });
+    }
+
+    #[test]
+    fn pipe_splice_tee() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (r1, w1) = pipe().unwrap();
+        let (r2, w2) = pipe().unwrap();
+        let (r3, w3) = pipe().unwrap();
+        assert!(r1.set_pipe_size(1 << 16).unwrap() >= 1 << 16);
+        assert!(r1.pipe_size().unwrap() >= 1 << 16);
+
+        File::from(OwnedFd::from(w1)).write_all(b"hello").unwrap();
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.tee(r1.as_raw_fd(), w3.as_raw_fd(), 5).await.unwrap(), 5);
+            assert_eq!(uring.splice(r1.as_raw_fd(), None, w2.as_raw_fd(), None, 5).await.unwrap(), 5);
+        });
+        drop((w2, w3));
+
+        for r in [r2, r3] {
+            let mut buf = String::new();
+            File::from(OwnedFd::from(r)).read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, "hello");
+        }
+    }
+}
@@ -0,0 +1,618 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::os::unix::prelude::{FromRawFd, RawFd};
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+use io_uring::{opcode, types};
+use crate::fd::OwnedUringFd;
+use crate::{cqueue, squeue, IoUringAsync, OwnedOp};
+
+// The bit set in the user_data of the completion queue entries posted to a ring
+// that received a direct descriptor with msg_ring_fd. The low bits hold the
+// destination slot. Slab indices never have this bit set.
+pub(crate) const MSG_RING_FD: u64 = 1 << 63;
+
+// IORING_OP_FIXED_FD_INSTALL, which is not exposed by the io_uring crate.
+const OP_FIXED_FD_INSTALL: u8 = 54;
+
+/// A flag for [`IoUringAsync::fixed_fd_install`] that installs the descriptor
+/// without close-on-exec (`IORING_FIXED_FD_NO_CLOEXEC`).
+pub const FIXED_FD_NO_CLOEXEC: u32 = 1 << 0;
+
+// The direct descriptors that other rings have installed into this ring's file
+// table, and the tasks waiting for them.
+#[derive(Default)]
+pub(crate) struct ReceivedFds {
+    slots: VecDeque<u32>,
+    wakers: Vec<Waker>,
+}
+
+impl ReceivedFds {
+    // Record a received descriptor, returning the wakers of the waiting tasks.
+    pub(crate) fn receive<C: cqueue::Entry>(&mut self, cqe: &C) -> Vec<Waker> {
+        self.slots.push_back(cqe.user_data() as u32);
+        std::mem::take(&mut self.wakers)
+    }
+}
+
+// Tracks which slots of a registered file table are free.
+#[derive(Default)]
+pub(crate) struct FileSlots {
+    // The number of slots in the registered file table, or None if no file
+    // table is registered.
+    len: Option<u32>,
+    // The free slots, with the lowest slot at the end.
+    free: Vec<u32>,
+    // Whether each slot is in `free`, indexed by slot, so that a slot that is
+    // freed twice is detected without searching the free list.
+    is_free: Vec<bool>,
+}
+
+impl FileSlots {
+    fn register(&mut self, len: u32) {
+        self.len = Some(len);
+        self.free = (0..len).rev().collect();
+        self.is_free = vec![true; len as usize];
+    }
+
+    fn unregister(&mut self) {
+        self.len = None;
+        self.free.clear();
+        self.is_free.clear();
+    }
+
+    // Returns whether a file table is registered.
+    pub(crate) fn is_registered(&self) -> bool {
+        self.len.is_some()
+    }
+
+    // Returns whether `slot` is part of the registered file table.
+    pub(crate) fn contains(&self, slot: u32) -> bool {
+        self.len.is_some_and(|len| slot < len)
+    }
+
+    pub(crate) fn alloc(&mut self) -> io::Result<u32> {
+        if self.len.is_none() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no file table is registered"));
+        }
+        let slot = self.free.pop().ok_or_else(|| io::Error::new(io::ErrorKind::OutOfMemory, "registered file table is exhausted"))?;
+        self.is_free[slot as usize] = false;
+        Ok(slot)
+    }
+
+    pub(crate) fn free(&mut self, slot: u32) {
+        if self.contains(slot) && !std::mem::replace(&mut self.is_free[slot as usize], true) {
+            self.free.push(slot);
+        }
+    }
+}
+
+// Returns a slot of the registered file table to the allocator when dropped,
+// unless it is kept. It is owned by the Op that fills or empties the slot,
+// through an OwnedOp, so that the slot is freed once the Op completes even if
+// its future is dropped first.
+pub(crate) struct SlotRelease {
+    file_slots: Rc<RefCell<FileSlots>>,
+    slot: Option<u32>,
+}
+
+impl SlotRelease {
+    pub(crate) fn new(file_slots: &Rc<RefCell<FileSlots>>, slot: u32) -> Self {
+        Self { file_slots: file_slots.clone(), slot: Some(slot) }
+    }
+
+    // Keep the slot allocated, since the Op has installed a descriptor in it.
+    pub(crate) fn keep(mut self) -> u32 {
+        self.slot.take().unwrap()
+    }
+}
+
+impl Drop for SlotRelease {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            self.file_slots.borrow_mut().free(slot);
+        }
+    }
+}
+
+/// A slot of the registered file table that holds a direct descriptor, such as
+/// one created by [`IoUringAsync::socket_fixed`]. It derefs to the slot as a
+/// `types::Fixed`, and converts into the target of helpers such as
+/// [`IoUringAsync::read`] and [`IoUringAsync::write`].
+///
+/// Dropping a `FixedFile` closes its descriptor without waiting for the close
+/// to complete, after which the slot is returned to the allocator. Ops that
+/// are still in flight on the descriptor are unaffected, since they hold their
+/// own reference to the file.
+///
+/// A [`Read`](crate::ops::Read) or [`Write`](crate::ops::Write) builder created
+/// from a `&FixedFile` borrows it, so the descriptor cannot be closed before the
+/// operation is pushed:
+///
+/// ```compile_fail
+/// # async fn f(uring: &io_uring_async::IoUringAsync) {
+/// let file = uring.socket_fixed(2, 1, 0).await.unwrap();
+/// let write = uring.write(&file, b"hello".to_vec());
+/// drop(file);
+/// write.push().await;
+/// # }
+/// ```
+#[must_use = "dropping a FixedFile closes its descriptor"]
+pub struct FixedFile<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    slot: types::Fixed,
+}
+
+impl<'a, S: squeue::Entry, C: cqueue::Entry> FixedFile<'a, S, C> {
+    /// Returns the slot of the registered file table that holds the descriptor.
+    pub fn slot(&self) -> u32 {
+        self.slot.0
+    }
+
+    /// Close the descriptor, waiting for the close to complete. See
+    /// [`IoUringAsync::close_direct`].
+    pub fn close(self) -> impl Future<Output = io::Result<()>> + 'a {
+        let uring = self.uring;
+        uring.close_direct(self.into_slot())
+    }
+
+    /// Release ownership of the slot without closing its descriptor, which is
+    /// then left to [`IoUringAsync::close_direct`].
+    pub fn into_slot(self) -> u32 {
+        let slot = self.slot.0;
+        std::mem::forget(self);
+        slot
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Deref for FixedFile<'_, S, C> {
+    type Target = types::Fixed;
+
+    fn deref(&self) -> &types::Fixed {
+        &self.slot
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> std::fmt::Debug for FixedFile<'_, S, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("FixedFile").field(&self.slot.0).finish()
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Drop for FixedFile<'_, S, C> {
+    fn drop(&mut self) {
+        // The slot is empty once the close has completed, even if it failed,
+        // such as because the file table was unregistered.
+        let release = SlotRelease::new(&self.uring.file_slots, self.slot.0);
+        self.uring.push_with(opcode::Close::new(self.slot).build(), move |_: C| drop(release));
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Register a sparse file table with `nr` empty slots. Slots are allocated
+    /// from the table by direct descriptor helpers such as
+    /// [`IoUringAsync::accept_direct`] and [`IoUringAsync::socket_direct`].
+    pub fn register_files_sparse(&self, nr: u32) -> io::Result<()> {
+        self.uring.inner.submitter().register_files_sparse(nr)?;
+        self.file_slots.borrow_mut().register(nr);
+        Ok(())
+    }
+
+    /// Unregister the file table. Any slots that are still allocated are invalidated.
+    pub fn unregister_files(&self) -> io::Result<()> {
+        self.uring.inner.submitter().unregister_files()?;
+        self.file_slots.borrow_mut().unregister();
+        Ok(())
+    }
+
+    // Perform an operation that installs a direct descriptor into a freshly
+    // allocated slot, returning the slot to the allocator on failure. If the
+    // future is dropped first, the slot is returned once the operation
+    // completes, and a descriptor that it installed is replaced by the next
+    // one installed into the slot.
+    async fn with_file_slot(&self, build: impl FnOnce(types::DestinationSlot) -> io_uring::squeue::Entry) -> io::Result<u32> {
+        let slot = self.file_slots.borrow_mut().alloc()?;
+        let entry = build(types::DestinationSlot::try_from_slot_target(slot).unwrap());
+        let (cqe, release) = OwnedOp::new(self.push(entry), SlotRelease::new(&self.file_slots, slot)).await;
+        cqueue::result_to_io(cqe.result()).map(|_| release.keep())
+    }
+
+    /// Accept a new connection on the listening socket `fd`, installing it as a
+    /// direct descriptor in a free slot of the registered file table. Returns the
+    /// slot index, or an error if the file table is exhausted.
+    pub async fn accept_direct(&self, fd: RawFd) -> io::Result<u32> {
+        self.with_file_slot(|slot| {
+            opcode::Accept::new(types::Fd(fd), std::ptr::null_mut(), std::ptr::null_mut())
+                .file_index(Some(slot))
+                .build()
+        }).await
+    }
+
+    /// Close the direct descriptor in `slot` of the registered file table. Once
+    /// the close completes, the slot is returned to the allocator used by
+    /// helpers such as [`IoUringAsync::accept_direct`], even if the future is
+    /// dropped first. This is also the case if the close fails, since the
+    /// kernel leaves the slot empty either way. Closing an empty slot fails with
+    /// `EBADF`.
+    pub fn close_direct(&self, slot: u32) -> impl Future<Output = io::Result<()>> {
+        let op = self.push(opcode::Close::new(types::Fixed(slot)).build());
+        let op = OwnedOp::new(op, SlotRelease::new(&self.file_slots, slot));
+        async move {
+            cqueue::result_to_io(op.await.0.result()).map(|_| ())
+        }
+    }
+
+    /// Install the direct descriptor in `slot` of the registered file table into
+    /// the process's file table as a regular file descriptor, so that it can be
+    /// handed to code that needs one, such as a library that performs its own
+    /// I/O. The new descriptor is close-on-exec unless `flags` contains
+    /// [`FIXED_FD_NO_CLOEXEC`]. The direct descriptor is left in `slot`, and is
+    /// closed independently with [`IoUringAsync::close_direct`]. Returns an
+    /// `Unsupported` error if the kernel does not support
+    /// `IORING_OP_FIXED_FD_INSTALL` (Linux 6.8), as reported by the probe.
+    pub fn fixed_fd_install(&self, slot: u32, flags: u32) -> impl Future<Output = io::Result<OwnedUringFd>> {
+        let op = if self.is_supported(OP_FIXED_FD_INSTALL) {
+            let mut entry = opcode::Nop::new().build();
+            let raw = squeue::raw_mut(&mut entry);
+            raw.opcode = OP_FIXED_FD_INSTALL;
+            // IOSQE_FIXED_FILE, which the kernel requires.
+            raw.flags |= 1 << 0;
+            raw.fd = slot as i32;
+            raw.op_flags = flags;
+            Ok(self.push(entry))
+        } else {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "the kernel does not support IORING_OP_FIXED_FD_INSTALL"))
+        };
+        async move {
+            let fd = cqueue::result_to_io(op?.await.result())?;
+            Ok(unsafe { OwnedUringFd::from_raw_fd(fd as RawFd) })
+        }
+    }
+
+    /// Allocate a free slot of the registered file table without installing a
+    /// descriptor in it, for example to receive a direct descriptor from another
+    /// ring with [`IoUringAsync::msg_ring_fd`]. The slot is returned to the
+    /// allocator once the descriptor in it is closed with
+    /// [`IoUringAsync::close_direct`].
+    pub fn reserve_file_slot(&self) -> io::Result<u32> {
+        self.file_slots.borrow_mut().alloc()
+    }
+
+    /// Install a duplicate of the direct descriptor in `src_slot` of this ring's
+    /// file table into `dst_slot` of the file table of the ring `target`
+    /// (`IORING_MSG_SEND_FD`). This lets an acceptor ring hand connections to
+    /// worker rings without sharing a listening socket. The target ring should
+    /// reserve `dst_slot` with [`IoUringAsync::reserve_file_slot`], and learns
+    /// that the descriptor has arrived from
+    /// [`IoUringAsync::recv_msg_ring_fd`]. The descriptor in `src_slot` is left
+    /// open, and can be closed with [`IoUringAsync::close_direct`] once sent.
+    /// Requires Linux 6.0.
+    pub fn msg_ring_fd(&self, target: RawFd, src_slot: u32, dst_slot: u32) -> impl Future<Output = io::Result<()>> {
+        let entry = opcode::MsgRingSendFd::new(
+            types::Fd(target),
+            types::Fixed(src_slot),
+            types::DestinationSlot::try_from_slot_target(dst_slot).unwrap(),
+            0,
+            MSG_RING_FD | dst_slot as u64,
+        ).build();
+        let op = self.push(entry);
+        async move {
+            cqueue::result_to_io(op.await.result()).map(|_| ())
+        }
+    }
+
+    /// Wait for another ring to install a direct descriptor into this ring's
+    /// file table with [`IoUringAsync::msg_ring_fd`], resolving to the slot that
+    /// it was installed into. Descriptors are received in the order that they
+    /// arrived. Completions must be processed while waiting, for example by
+    /// [`IoUringAsync::listen`].
+    pub async fn recv_msg_ring_fd(&self) -> u32 {
+        std::future::poll_fn(|cx| {
+            let mut received = self.uring.received_fds.borrow_mut();
+            if let Some(slot) = received.slots.pop_front() {
+                return Poll::Ready(slot);
+            }
+            // A task that is polled again is only registered once.
+            if !received.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                received.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }).await
+    }
+
+    /// Create a socket, installing it as a direct descriptor in a free slot of the
+    /// registered file table. Returns the slot index, or an error if the file
+    /// table is exhausted.
+    pub async fn socket_direct(&self, domain: i32, socket_type: i32, protocol: i32) -> io::Result<u32> {
+        self.with_file_slot(|slot| {
+            opcode::Socket::new(domain, socket_type, protocol)
+                .file_index(Some(slot))
+                .build()
+        }).await
+    }
+
+    /// Take ownership of `slot` of the registered file table, which must hold a
+    /// direct descriptor, such as one installed by [`IoUringAsync::accept_direct`]
+    /// or received with [`IoUringAsync::recv_msg_ring_fd`]. The descriptor is
+    /// closed when the returned guard is dropped.
+    pub fn fixed_file(&self, slot: u32) -> FixedFile<'_, S, C> {
+        FixedFile { uring: self, slot: types::Fixed(slot) }
+    }
+
+    /// Like [`IoUringAsync::socket_direct`], but returns a guard that closes the
+    /// socket when dropped.
+    pub async fn socket_fixed(&self, domain: i32, socket_type: i32, protocol: i32) -> io::Result<FixedFile<'_, S, C>> {
+        self.socket_direct(domain, socket_type, protocol).await.map(|slot| self.fixed_file(slot))
+    }
+
+    /// Like [`IoUringAsync::accept_direct`], but returns a guard that closes the
+    /// connection when dropped.
+    pub async fn accept_fixed(&self, fd: RawFd) -> io::Result<FixedFile<'_, S, C>> {
+        self.accept_direct(fd).await.map(|slot| self.fixed_file(slot))
+    }
+
+    /// Create a TCP socket in `slot` of the registered file table, connect it to
+    /// `addr`, and send `buf` over it, as a single chain of linked entries that
+    /// is submitted at once. This works because the connect and the send refer
+    /// to the socket by its slot, which is chosen before the socket exists,
+    /// rather than by a file descriptor that is only known once it completes.
+    /// The slot should be reserved with [`IoUringAsync::reserve_file_slot`].
+    ///
+    /// Returns the number of bytes sent, handing the buffer back to the caller.
+    /// On success the connected socket is left in `slot`, to be closed with
+    /// [`IoUringAsync::close_direct`]. If any entry fails, the rest of the chain
+    /// is canceled, the socket is closed if it was created, and the slot is
+    /// returned to the allocator.
+    pub async fn socket_connect_send(&self, addr: SocketAddr, slot: u32, buf: Vec<u8>) -> (io::Result<usize>, Vec<u8>) {
+        let domain = if addr.is_ipv4() { libc::AF_INET } else { libc::AF_INET6 };
+        let socket = opcode::Socket::new(domain, libc::SOCK_STREAM, 0)
+            .file_index(Some(types::DestinationSlot::try_from_slot_target(slot).unwrap()))
+            .build();
+        let sockaddr = Box::new(crate::net::to_sockaddr(&addr));
+        let connect = opcode::Connect::new(types::Fixed(slot), (&sockaddr.0 as *const libc::sockaddr_storage).cast(), sockaddr.1).build();
+        let send = opcode::Send::new(types::Fixed(slot), buf.as_ptr(), buf.len().try_into().unwrap_or(u32::MAX)).build();
+        let mut ops = self.link()
+            .push(socket)
+            .push(connect)
+            .owning(sockaddr)
+            .push(send)
+            .owning(Box::new(buf))
+            .finish();
+
+        let mut results = Vec::with_capacity(ops.len());
+        for op in ops.iter_mut() {
+            results.push(op.await.result());
+        }
+        let buf = *ops[2].take_resources().unwrap().downcast::<Vec<u8>>().unwrap();
+        // Once an entry fails, every later entry is canceled, so the first
+        // failure is the cause.
+        let created = results[0] >= 0;
+        let result = results.into_iter().map(cqueue::result_to_io).collect::<io::Result<Vec<_>>>();
+        if result.is_err() {
+            // close_direct frees the slot itself once the socket is closed.
+            if !created || self.close_direct(slot).await.is_err() {
+                self.file_slots.borrow_mut().free(slot);
+            }
+        }
+        (result.map(|n| n[2] as usize), buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use crate::IoUringAsync;
+    use super::FileSlots;
+
+    #[test]
+    fn file_slots() {
+        let mut slots = FileSlots::default();
+        assert_eq!(slots.alloc().unwrap_err().kind(), io::ErrorKind::NotFound);
+        slots.register(2);
+        assert_eq!(slots.alloc().unwrap(), 0);
+        assert_eq!(slots.alloc().unwrap(), 1);
+        assert_eq!(slots.alloc().unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+
+        // Freeing a slot twice, or one outside the table, is ignored.
+        slots.free(1);
+        slots.free(1);
+        slots.free(2);
+        assert_eq!(slots.alloc().unwrap(), 1);
+        assert!(slots.alloc().is_err());
+        slots.unregister();
+        slots.free(0);
+        assert!(slots.alloc().is_err());
+    }
+
+    #[test]
+    fn direct_descriptors() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        crate::tests::run(&uring, async {
+            let err = uring.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+            uring.register_files_sparse(3).unwrap();
+            assert_eq!(uring.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap(), 0);
+            assert_eq!(uring.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap(), 1);
+
+            let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            assert_eq!(uring.accept_direct(listener.as_raw_fd()).await.unwrap(), 2);
+
+            let err = uring.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+
+            uring.close_direct(1).await.unwrap();
+            let err = uring.close_direct(1).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+            assert_eq!(uring.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn fixed_file() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        uring.register_files_sparse(1).unwrap();
+        let (rx, tx) = crate::fd::pipe().unwrap();
+        crate::tests::run(&uring, async {
+            // Reads and writes accept the guard in place of a file descriptor.
+            let sock = uring.socket_fixed(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap();
+            assert_eq!(sock.slot(), 0);
+            let err = uring.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
+            let err = uring.write(&sock, b"hello".to_vec()).push().await.0.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EPIPE));
+
+            // Dropping the guard closes the descriptor and frees the slot.
+            drop(sock);
+            uring.drain().await;
+            assert_eq!(uring.reserve_file_slot().unwrap(), 0);
+
+            // A slot filled by another helper can be adopted.
+            uring.unregister_files().unwrap();
+            uring.register_files_sparse(1).unwrap();
+            let entry = io_uring::opcode::FilesUpdate::new(&rx.as_raw_fd(), 1).offset(0).build();
+            assert_eq!(uring.push(entry).await.result(), 1);
+            let file = uring.fixed_file(uring.reserve_file_slot().unwrap());
+            uring.write(tx.as_raw_fd(), b"hi".to_vec()).push().await.0.unwrap();
+            let (n, buf) = uring.read(*file, vec![0; 2]).push().await;
+            assert_eq!(n.unwrap(), 2);
+            assert_eq!(buf, b"hi");
+            file.close().await.unwrap();
+            let err = uring.close_direct(0).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+
+            // The slot is freed once the close completes, even if its future
+            // is dropped first.
+            let slot = uring.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap();
+            drop(uring.close_direct(slot));
+            uring.drain().await;
+            assert_eq!(uring.reserve_file_slot().unwrap(), slot);
+
+            // The slot of a guard whose close fails is freed all the same.
+            drop(uring.fixed_file(slot));
+            uring.drain().await;
+            assert_eq!(uring.reserve_file_slot().unwrap(), slot);
+        });
+    }
+
+    #[test]
+    fn with_file_slot_dropped() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        uring.register_files_sparse(1).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        crate::tests::run(&uring, async {
+            // An accept that is dropped while in flight frees its slot once it
+            // completes.
+            let mut accept = Box::pin(uring.accept_direct(listener.as_raw_fd()));
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            assert!(std::future::Future::poll(accept.as_mut(), &mut cx).is_pending());
+            uring.submit().unwrap();
+            assert_eq!(uring.reserve_file_slot().unwrap_err().kind(), io::ErrorKind::OutOfMemory);
+            drop(accept);
+            let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            uring.drain().await;
+            assert_eq!(uring.reserve_file_slot().unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn fixed_fd_install() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        if !uring.is_supported(super::OP_FIXED_FD_INSTALL) {
+            return;
+        }
+        uring.register_files_sparse(1).unwrap();
+        let (mut rx, tx) = std::os::unix::net::UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let entry = io_uring::opcode::FilesUpdate::new(&tx.as_raw_fd(), 1).offset(0).build();
+            assert_eq!(uring.push(entry).await.result(), 1);
+            drop(tx);
+
+            let fd = uring.fixed_fd_install(0, 0).await.unwrap();
+            assert_eq!(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) }, libc::FD_CLOEXEC);
+            let fd2 = uring.fixed_fd_install(0, super::FIXED_FD_NO_CLOEXEC).await.unwrap();
+            assert_eq!(unsafe { libc::fcntl(fd2.as_raw_fd(), libc::F_GETFD) }, 0);
+
+            // The installed descriptor refers to the same socket as the slot.
+            let mut stream = std::os::unix::net::UnixStream::from(std::os::fd::OwnedFd::from(fd));
+            std::io::Write::write_all(&mut stream, b"hi").unwrap();
+            let mut buf = [0; 2];
+            rx.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hi");
+            uring.close_direct(0).await.unwrap();
+
+            let err = uring.fixed_fd_install(0, 0).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+        });
+    }
+
+    #[test]
+    fn msg_ring_fd() {
+        let acceptor = Rc::new(IoUringAsync::new(8).unwrap());
+        let worker = Rc::new(IoUringAsync::new(8).unwrap());
+        acceptor.register_files_sparse(1).unwrap();
+        worker.register_files_sparse(2).unwrap();
+        assert_eq!(worker.reserve_file_slot().unwrap(), 0);
+        let dst = worker.reserve_file_slot().unwrap();
+
+        // Polling the receive repeatedly registers its waker once.
+        let mut recv = std::pin::pin!(worker.recv_msg_ring_fd());
+        struct Noop;
+        impl std::task::Wake for Noop {
+            fn wake(self: std::sync::Arc<Self>) {}
+        }
+        let waker = std::task::Waker::from(std::sync::Arc::new(Noop));
+        let mut cx = std::task::Context::from_waker(&waker);
+        for _ in 0..3 {
+            assert!(std::future::Future::poll(recv.as_mut(), &mut cx).is_pending());
+        }
+        assert_eq!(worker.uring.received_fds.borrow().wakers.len(), 1);
+
+        let target = worker.as_raw_fd();
+        crate::tests::run(&acceptor, async {
+            let src = acceptor.socket_direct(libc::AF_INET, libc::SOCK_STREAM, 0).await.unwrap();
+            acceptor.msg_ring_fd(target, src, dst).await.unwrap();
+            acceptor.close_direct(src).await.unwrap();
+        });
+        crate::tests::run(&worker, async {
+            assert_eq!(recv.await, dst);
+            // The slot holds the sent socket, so it can be closed.
+            worker.close_direct(dst).await.unwrap();
+        });
+        assert_eq!(worker.stats().orphan_completions, 0);
+    }
+
+    #[test]
+    fn socket_connect_send() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        uring.register_files_sparse(1).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        crate::tests::run(&uring, async {
+            let slot = uring.reserve_file_slot().unwrap();
+            let (n, buf) = uring.socket_connect_send(listener.local_addr().unwrap(), slot, b"hello".to_vec()).await;
+            assert_eq!(n.unwrap(), 5);
+            assert_eq!(buf, b"hello");
+            let mut received = [0; 5];
+            listener.accept().unwrap().0.read_exact(&mut received).unwrap();
+            assert_eq!(&received, b"hello");
+            uring.close_direct(slot).await.unwrap();
+
+            // A failed connect closes the socket and frees the slot.
+            let slot = uring.reserve_file_slot().unwrap();
+            let (n, _) = uring.socket_connect_send(closed, slot, b"hello".to_vec()).await;
+            assert_eq!(n.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+            assert_eq!(uring.reserve_file_slot().unwrap(), slot);
+            let err = uring.close_direct(slot).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+        });
+    }
+}
@@ -0,0 +1,532 @@
+use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::prelude::RawFd;
+use std::rc::Rc;
+use io_uring::{opcode, types};
+use io_uring::squeue::Flags;
+use crate::files::SlotRelease;
+use crate::{cqueue, squeue, IoUringAsync, OwnedOp};
+
+// The size of the buffer used to read a file whose size is not yet known.
+const READ_CHUNK: usize = 64 * 1024;
+
+// The xattr opcodes, which are not exposed by the io_uring crate.
+const OP_FSETXATTR: u8 = 41;
+const OP_SETXATTR: u8 = 42;
+const OP_FGETXATTR: u8 = 43;
+const OP_GETXATTR: u8 = 44;
+// The ftruncate opcode, which is not exposed by the io_uring crate.
+const OP_FTRUNCATE: u8 = 55;
+
+/// The file targeted by a helper that can operate on either a path or an open
+/// file, such as [`IoUringAsync::statx`]. The helper picks the opcode and flags
+/// that match the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A path, relative to the current working directory if it is not absolute.
+    Path(CString),
+    /// An open file descriptor.
+    Fd(RawFd),
+    /// A direct descriptor in the registered file table.
+    Fixed(u32),
+}
+
+bitflags::bitflags! {
+    /// The flags of [`IoUringAsync::renameat`], which match those of
+    /// `renameat2(2)`.
+    pub struct RenameFlags: u32 {
+        /// Fail with `EEXIST` rather than replace the new path.
+        const NOREPLACE = libc::RENAME_NOREPLACE;
+        /// Atomically exchange the old and new paths, which must both exist.
+        const EXCHANGE = libc::RENAME_EXCHANGE;
+        /// Leave a whiteout at the old path, for overlay filesystems.
+        const WHITEOUT = libc::RENAME_WHITEOUT;
+    }
+}
+
+/// How a range of a file or of memory is expected to be accessed, as advised
+/// with [`IoUringAsync::fadvise`] or [`IoUringAsync::madvise`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// No particular access pattern, which is the default.
+    Normal,
+    /// Random access, so read-ahead is not useful.
+    Random,
+    /// Sequential access, so more aggressive read-ahead is useful.
+    Sequential,
+    /// Access in the near future, so the range should be read into memory.
+    WillNeed,
+    /// No access in the near future, so the range may be dropped from memory.
+    DontNeed,
+}
+
+impl Advice {
+    fn fadvise(self) -> i32 {
+        match self {
+            Advice::Normal => libc::POSIX_FADV_NORMAL,
+            Advice::Random => libc::POSIX_FADV_RANDOM,
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+            Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+        }
+    }
+
+    fn madvise(self) -> i32 {
+        match self {
+            Advice::Normal => libc::MADV_NORMAL,
+            Advice::Random => libc::MADV_RANDOM,
+            Advice::Sequential => libc::MADV_SEQUENTIAL,
+            Advice::WillNeed => libc::MADV_WILLNEED,
+            Advice::DontNeed => libc::MADV_DONTNEED,
+        }
+    }
+}
+
+impl Target {
+    // Build an xattr entry, using the path variant of the opcode for a path and
+    // the fd variant otherwise. `name` is the attribute name and `value` is the
+    // attribute value buffer of `len` bytes.
+    fn xattr_entry(&self, path_opcode: u8, fd_opcode: u8, name: &CString, value: *const u8, len: usize, flags: u32) -> io::Result<io_uring::squeue::Entry> {
+        let len = u32::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "xattr value is too large"))?;
+        let mut entry = opcode::Nop::new().build();
+        let raw = squeue::raw_mut(&mut entry);
+        raw.addr = name.as_ptr() as u64;
+        raw.off = value as u64;
+        raw.len = len;
+        raw.op_flags = flags;
+        match self {
+            Target::Path(path) => {
+                raw.opcode = path_opcode;
+                raw.addr3 = path.as_ptr() as u64;
+            }
+            Target::Fd(fd) => {
+                raw.opcode = fd_opcode;
+                raw.fd = *fd;
+            }
+            Target::Fixed(slot) => {
+                raw.opcode = fd_opcode;
+                raw.fd = *slot as i32;
+                raw.flags |= Flags::FIXED_FILE.bits();
+            }
+        }
+        Ok(entry)
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Read the whole file at `path`, relative to the directory `dir`, such as
+    /// `libc::AT_FDCWD`. The file is opened, its size is queried, it is read, and
+    /// it is closed by a single chain of linked entries, so small files are read
+    /// with one submission. Files larger than 64 KiB are read in full by a
+    /// second chain once their size is known.
+    ///
+    /// The file is opened as a direct descriptor, so a file table must have been
+    /// registered with [`IoUringAsync::register_files_sparse`] with a free slot.
+    /// Otherwise, this fails with an `InvalidInput` error, which is distinct
+    /// from the `NotFound` error of a missing file.
+    pub async fn read_file_contents(&self, dir: RawFd, path: CString) -> io::Result<Vec<u8>> {
+        let path = Rc::new(path);
+        let (mut contents, size) = self.read_file_chain(dir, path.clone(), 0, READ_CHUNK, true).await?;
+        let size = size.unwrap();
+        while (contents.len() as u64) < size {
+            let len = (size - contents.len() as u64).try_into().unwrap_or(usize::MAX);
+            let (chunk, _) = self.read_file_chain(dir, path.clone(), contents.len() as u64, len, false).await?;
+            if chunk.is_empty() {
+                // The file was truncated after its size was queried.
+                break;
+            }
+            contents.extend_from_slice(&chunk);
+        }
+        Ok(contents)
+    }
+
+    /// Query the metadata of `target` with the fields in `mask`, such as
+    /// `libc::STATX_SIZE`. An open file is queried with `AT_EMPTY_PATH`. The kernel
+    /// does not support querying a direct descriptor, so [`Target::Fixed`] is
+    /// rejected with an `Unsupported` error.
+    pub fn statx(&self, target: Target, mask: u32) -> impl std::future::Future<Output = io::Result<libc::statx>> {
+        let mut statx: Box<libc::statx> = Box::new(unsafe { MaybeUninit::zeroed().assume_init() });
+        let empty = CString::default();
+        let statxbuf = (&mut *statx as *mut libc::statx).cast();
+        let entry = match &target {
+            Target::Path(path) => Ok(opcode::Statx::new(types::Fd(libc::AT_FDCWD), path.as_ptr(), statxbuf)),
+            Target::Fd(fd) => Ok(opcode::Statx::new(types::Fd(*fd), empty.as_ptr(), statxbuf).flags(libc::AT_EMPTY_PATH)),
+            Target::Fixed(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "statx does not support direct descriptors")),
+        };
+        let op = entry.map(|entry| OwnedOp::new(self.push(entry.mask(mask).build()), (target, empty, statx)));
+        async move {
+            let (cqe, (_, _, statx)) = op?.await;
+            cqueue::result_to_io(cqe.result())?;
+            Ok(*statx)
+        }
+    }
+
+    /// Read the value of the extended attribute `name` of `target`, reading at
+    /// most `len` bytes.
+    pub fn getxattr(&self, target: Target, name: CString, len: usize) -> impl std::future::Future<Output = io::Result<Vec<u8>>> {
+        let mut value = Vec::<u8>::with_capacity(len);
+        let entry = target.xattr_entry(OP_GETXATTR, OP_FGETXATTR, &name, value.as_mut_ptr(), len, 0);
+        let op = entry.map(|entry| OwnedOp::new(self.push(entry), (target, name, value)));
+        async move {
+            let (cqe, (_, _, mut value)) = op?.await;
+            let n = cqueue::result_to_io(cqe.result())?;
+            unsafe { value.set_len(n as usize) };
+            Ok(value)
+        }
+    }
+
+    /// Set the extended attribute `name` of `target` to `value`. `flags` may be
+    /// `libc::XATTR_CREATE` or `libc::XATTR_REPLACE`.
+    pub fn setxattr(&self, target: Target, name: CString, value: Vec<u8>, flags: i32) -> impl std::future::Future<Output = io::Result<()>> {
+        let entry = target.xattr_entry(OP_SETXATTR, OP_FSETXATTR, &name, value.as_ptr(), value.len(), flags as u32);
+        let op = entry.map(|entry| OwnedOp::new(self.push(entry), (target, name, value)));
+        async move {
+            let (cqe, _) = op?.await;
+            cqueue::result_to_io(cqe.result()).map(drop)
+        }
+    }
+
+    /// Truncate or extend the file `fd` to `len` bytes. If the kernel does not
+    /// support `IORING_OP_FTRUNCATE` (Linux 6.9), as reported by the probe, this
+    /// falls back to a blocking `ftruncate(2)` when it is called.
+    pub fn ftruncate(&self, fd: RawFd, len: u64) -> impl std::future::Future<Output = io::Result<()>> {
+        let op = if self.is_supported(OP_FTRUNCATE) {
+            let mut entry = opcode::Nop::new().build();
+            let raw = squeue::raw_mut(&mut entry);
+            raw.opcode = OP_FTRUNCATE;
+            raw.fd = fd;
+            raw.off = len;
+            Ok(self.push(entry))
+        } else {
+            Err(ftruncate_blocking(fd, len))
+        };
+        async move {
+            match op {
+                Ok(op) => cqueue::result_to_io(op.await.result()).map(drop),
+                Err(result) => result,
+            }
+        }
+    }
+
+    /// Advise the kernel how the `len` bytes of the file `fd` at `offset` will be
+    /// accessed, such as to warm the page cache with [`Advice::WillNeed`]. A
+    /// `len` of zero extends to the end of the file. If the kernel does not
+    /// support `IORING_OP_FADVISE` (Linux 5.6), as reported by the probe, this
+    /// falls back to a blocking `posix_fadvise(2)` when it is called.
+    pub fn fadvise(&self, fd: RawFd, offset: u64, len: u32, advice: Advice) -> impl std::future::Future<Output = io::Result<()>> {
+        let op = if self.is_supported(opcode::Fadvise::CODE) {
+            let entry = opcode::Fadvise::new(types::Fd(fd), len as libc::off_t, advice.fadvise())
+                .offset(offset as libc::off_t)
+                .build();
+            Ok(self.push(entry))
+        } else {
+            Err(fadvise_blocking(fd, offset, len, advice))
+        };
+        async move {
+            match op {
+                Ok(op) => cqueue::result_to_io(op.await.result()).map(drop),
+                Err(result) => result,
+            }
+        }
+    }
+
+    /// Advise the kernel how the `len` bytes of memory at `addr` will be
+    /// accessed. If the kernel does not support `IORING_OP_MADVISE` (Linux 5.6),
+    /// as reported by the probe, this falls back to a blocking `madvise(2)` when
+    /// it is called.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid for the advice, as for `madvise(2)`. In particular,
+    /// [`Advice::DontNeed`] discards the contents of private anonymous memory,
+    /// which must not be in use.
+    pub unsafe fn madvise(&self, addr: *mut libc::c_void, len: u32, advice: Advice) -> impl std::future::Future<Output = io::Result<()>> {
+        let op = if self.is_supported(opcode::Madvise::CODE) {
+            Ok(self.push(opcode::Madvise::new(addr, len as libc::off_t, advice.madvise()).build()))
+        } else {
+            Err(madvise_blocking(addr, len, advice))
+        };
+        async move {
+            match op {
+                Ok(op) => cqueue::result_to_io(op.await.result()).map(drop),
+                Err(result) => result,
+            }
+        }
+    }
+
+    /// Rename `old_path`, relative to the directory `old_dir`, to `new_path`,
+    /// relative to `new_dir`. Either directory may be `libc::AT_FDCWD`. The
+    /// rename is atomic, and with [`RenameFlags::EXCHANGE`] the two paths are
+    /// swapped atomically. A rename that could not be performed as requested
+    /// fails rather than falling back: `EEXIST` if the new path exists with
+    /// [`RenameFlags::NOREPLACE`], and `EINVAL` if the filesystem does not
+    /// support the flags.
+    pub fn renameat(&self, old_dir: RawFd, old_path: CString, new_dir: RawFd, new_path: CString, flags: RenameFlags) -> impl std::future::Future<Output = io::Result<()>> {
+        let entry = opcode::RenameAt::new(types::Fd(old_dir), old_path.as_ptr(), types::Fd(new_dir), new_path.as_ptr())
+            .flags(flags.bits())
+            .build();
+        let op = OwnedOp::new(self.push(entry), (old_path, new_path));
+        async move {
+            let (cqe, _) = op.await;
+            cqueue::result_to_io(cqe.result()).map(drop)
+        }
+    }
+
+    /// Create a hard link at `new_path`, relative to the directory `new_dir`,
+    /// to the file at `old_path`, relative to `old_dir`. `flags` may be
+    /// `libc::AT_SYMLINK_FOLLOW` to link to the target of a symbolic link.
+    pub fn linkat(&self, old_dir: RawFd, old_path: CString, new_dir: RawFd, new_path: CString, flags: i32) -> impl std::future::Future<Output = io::Result<()>> {
+        let entry = opcode::LinkAt::new(types::Fd(old_dir), old_path.as_ptr(), types::Fd(new_dir), new_path.as_ptr())
+            .flags(flags)
+            .build();
+        let op = OwnedOp::new(self.push(entry), (old_path, new_path));
+        async move {
+            let (cqe, _) = op.await;
+            cqueue::result_to_io(cqe.result()).map(drop)
+        }
+    }
+
+    // Open `path` into a direct descriptor, optionally query its size, read up
+    // to `len` bytes at `offset`, and close it, all as a single link chain.
+    // The query and the read are hard-linked, so the file is closed even if
+    // either of them fails. If the open fails, the rest of the chain is
+    // canceled by the kernel, and every Op still completes. The slot is freed
+    // once the close completes, even if the future is dropped first.
+    async fn read_file_chain(&self, dir: RawFd, path: Rc<CString>, offset: u64, len: usize, stat: bool) -> io::Result<(Vec<u8>, Option<u64>)> {
+        if !self.file_slots.borrow().is_registered() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "reading a file requires a registered file table"));
+        }
+        let slot = self.file_slots.borrow_mut().alloc()?;
+        let open = opcode::OpenAt::new(types::Fd(dir), path.as_ptr())
+            // O_CLOEXEC is meaningless for a direct descriptor, and rejected.
+            .flags(libc::O_RDONLY)
+            .file_index(Some(types::DestinationSlot::try_from_slot_target(slot).unwrap()))
+            .build();
+        let mut link = self.link().push(open).owning(Box::new(path.clone()));
+        if stat {
+            let mut statx: Box<libc::statx> = Box::new(unsafe { MaybeUninit::zeroed().assume_init() });
+            let entry = opcode::Statx::new(types::Fd(dir), path.as_ptr(), (&mut *statx as *mut libc::statx).cast())
+                .mask(libc::STATX_SIZE)
+                .build()
+                .flags(Flags::IO_HARDLINK);
+            link = link.push(entry).owning(statx);
+        }
+        let mut buf = Vec::<u8>::with_capacity(len);
+        let read = opcode::Read::new(types::Fixed(slot), buf.as_mut_ptr(), len.try_into().unwrap_or(u32::MAX))
+            .offset(offset as i64)
+            .rw_flags(self.rw_flags)
+            .build()
+            .flags(Flags::IO_HARDLINK);
+        let mut ops = link.push(read)
+            .owning(Box::new(buf))
+            .push(opcode::Close::new(types::Fixed(slot)).build())
+            // The slot is empty once the open has failed or the close has run.
+            .owning(Box::new(SlotRelease::new(&self.file_slots, slot)))
+            .finish();
+
+        let mut results = Vec::with_capacity(ops.len());
+        for op in ops.iter_mut() {
+            results.push(op.await.result());
+        }
+
+        cqueue::result_to_io(results[0])?;
+        let size = if stat {
+            cqueue::result_to_io(results[1])?;
+            let statx = ops[1].take_resources().unwrap().downcast::<libc::statx>().unwrap();
+            Some(statx.stx_size)
+        } else {
+            None
+        };
+        let read = ops.len() - 2;
+        let n = cqueue::result_to_io(results[read])?;
+        let mut buf = ops[read].take_resources().unwrap().downcast::<Vec<u8>>().unwrap();
+        unsafe { buf.set_len(n as usize) };
+        Ok((*buf, size))
+    }
+}
+
+fn ftruncate_blocking(fd: RawFd, len: u64) -> io::Result<()> {
+    let len = libc::off_t::try_from(len).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+    if unsafe { libc::ftruncate(fd, len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn fadvise_blocking(fd: RawFd, offset: u64, len: u32, advice: Advice) -> io::Result<()> {
+    let offset = libc::off_t::try_from(offset).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+    // posix_fadvise returns the error number rather than setting errno.
+    match unsafe { libc::posix_fadvise(fd, offset, len as libc::off_t, advice.fadvise()) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+fn madvise_blocking(addr: *mut libc::c_void, len: u32, advice: Advice) -> io::Result<()> {
+    if unsafe { libc::madvise(addr, len as usize, advice.madvise()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use crate::IoUringAsync;
+    use super::{fadvise_blocking, ftruncate_blocking, madvise_blocking, Advice, RenameFlags, Target};
+
+    #[test]
+    fn read_file_contents() {
+        let dir = std::env::temp_dir();
+        let name = format!("io-uring-async-read-file-{}", std::process::id());
+        let contents: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        std::fs::write(dir.join(&name), &contents).unwrap();
+        let path = CString::new(dir.join(&name).as_os_str().as_bytes()).unwrap();
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let err = crate::tests::run(&uring, uring.read_file_contents(libc::AT_FDCWD, path.clone())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        uring.register_files_sparse(1).unwrap();
+        let read = crate::tests::run(&uring, uring.read_file_contents(libc::AT_FDCWD, path.clone())).unwrap();
+        std::fs::remove_file(dir.join(&name)).unwrap();
+        assert_eq!(read, contents);
+
+        // A failed open cancels the rest of the chain without leaking the slot.
+        let err = crate::tests::run(&uring, uring.read_file_contents(libc::AT_FDCWD, path)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(uring.slab.borrow().is_empty());
+        assert_eq!(uring.file_slots.borrow_mut().alloc().unwrap(), 0);
+        uring.file_slots.borrow_mut().free(0);
+
+        // A read that is dropped while in flight frees the slot once its chain
+        // completes.
+        crate::tests::run(&uring, async {
+            let mut read = Box::pin(uring.read_file_contents(libc::AT_FDCWD, CString::new("/dev/null").unwrap()));
+            let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+            assert!(std::future::Future::poll(read.as_mut(), &mut cx).is_pending());
+            drop(read);
+            uring.drain().await;
+        });
+        assert_eq!(uring.file_slots.borrow_mut().alloc().unwrap(), 0);
+    }
+
+    #[test]
+    fn targets() {
+        let dir = std::env::temp_dir();
+        let name = format!("io-uring-async-targets-{}", std::process::id());
+        std::fs::write(dir.join(&name), b"hello").unwrap();
+        let file = std::fs::File::open(dir.join(&name)).unwrap();
+        let path = CString::new(dir.join(&name).as_os_str().as_bytes()).unwrap();
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        uring.register_files_sparse(1).unwrap();
+        uring.uring.inner.submitter().register_files_update(0, &[file.as_raw_fd()]).unwrap();
+        let attr = CString::new("user.io-uring-async").unwrap();
+        crate::tests::run(&uring, async {
+            let statx = uring.statx(Target::Path(path.clone()), libc::STATX_SIZE).await.unwrap();
+            assert_eq!(statx.stx_size, 5);
+            let statx = uring.statx(Target::Fd(file.as_raw_fd()), libc::STATX_SIZE).await.unwrap();
+            assert_eq!(statx.stx_size, 5);
+            let err = uring.statx(Target::Fixed(0), libc::STATX_SIZE).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+            uring.setxattr(Target::Path(path.clone()), attr.clone(), b"path".to_vec(), 0).await.unwrap();
+            let value = uring.getxattr(Target::Fd(file.as_raw_fd()), attr.clone(), 64).await.unwrap();
+            assert_eq!(value, b"path");
+            uring.setxattr(Target::Fixed(0), attr.clone(), b"fixed".to_vec(), libc::XATTR_REPLACE).await.unwrap();
+            let value = uring.getxattr(Target::Path(path.clone()), attr.clone(), 64).await.unwrap();
+            assert_eq!(value, b"fixed");
+            let err = uring.setxattr(Target::Fd(file.as_raw_fd()), attr.clone(), b"x".to_vec(), libc::XATTR_CREATE).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+        });
+        std::fs::remove_file(dir.join(&name)).unwrap();
+    }
+
+    #[test]
+    fn ftruncate() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("io-uring-async-ftruncate-{}", std::process::id()));
+        std::fs::write(&path, b"hello world").unwrap();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let fd = file.as_raw_fd();
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            uring.ftruncate(fd, 5).await.unwrap();
+            assert_eq!(file.metadata().unwrap().len(), 5);
+            uring.ftruncate(fd, 4096).await.unwrap();
+            assert_eq!(file.metadata().unwrap().len(), 4096);
+            let err = uring.ftruncate(-1, 0).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+        });
+
+        ftruncate_blocking(fd, 1).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 1);
+        let err = ftruncate_blocking(fd, u64::MAX).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    }
+
+    #[test]
+    fn advise() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("io-uring-async-advise-{}", std::process::id()));
+        std::fs::write(&path, vec![0; 8192]).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let fd = file.as_raw_fd();
+        let mut mem = vec![0u8; 8192];
+        let addr = ((mem.as_mut_ptr() as usize + 4095) & !4095) as *mut libc::c_void;
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            uring.fadvise(fd, 0, 0, Advice::WillNeed).await.unwrap();
+            uring.fadvise(fd, 4096, 4096, Advice::Sequential).await.unwrap();
+            let err = uring.fadvise(-1, 0, 0, Advice::Normal).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+            unsafe { uring.madvise(addr, 4096, Advice::Random) }.await.unwrap();
+        });
+
+        fadvise_blocking(fd, 0, 0, Advice::DontNeed).unwrap();
+        let err = fadvise_blocking(-1, 0, 0, Advice::Normal).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+        madvise_blocking(addr, 4096, Advice::WillNeed).unwrap();
+        let err = madvise_blocking(addr.wrapping_add(1), 4096, Advice::Normal).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    }
+
+    #[test]
+    fn rename_link() {
+        let dir = std::env::temp_dir().join(format!("io-uring-async-rename-{}", std::process::id()));
+        std::fs::create_dir(&dir).unwrap();
+        let path = |name: &str| CString::new(dir.join(name).as_os_str().as_bytes()).unwrap();
+        std::fs::write(dir.join("a"), b"a").unwrap();
+        std::fs::write(dir.join("b"), b"b").unwrap();
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let cwd = libc::AT_FDCWD;
+            let err = uring.renameat(cwd, path("a"), cwd, path("b"), RenameFlags::NOREPLACE).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+            uring.renameat(cwd, path("a"), cwd, path("b"), RenameFlags::EXCHANGE).await.unwrap();
+            assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"b");
+            assert_eq!(std::fs::read(dir.join("b")).unwrap(), b"a");
+            let err = uring.renameat(cwd, path("a"), cwd, path("c"), RenameFlags::EXCHANGE).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+            uring.renameat(cwd, path("a"), cwd, path("b"), RenameFlags::empty()).await.unwrap();
+            assert_eq!(std::fs::read(dir.join("b")).unwrap(), b"b");
+
+            uring.linkat(cwd, path("b"), cwd, path("c"), 0).await.unwrap();
+            assert_eq!(std::fs::read(dir.join("c")).unwrap(), b"b");
+            let err = uring.linkat(cwd, path("b"), cwd, path("c"), 0).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
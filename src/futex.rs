@@ -0,0 +1,175 @@
+//! Futex operations performed by the ring, and a semaphore built on them.
+
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use io_uring::opcode;
+use crate::{cqueue, squeue, IoUringAsync, Op};
+
+// The futex opcodes and flags, which are not exposed by the io_uring crate.
+const FUTEX_WAIT: u8 = 51;
+const FUTEX_WAKE: u8 = 52;
+const FUTEX2_SIZE_U32: i32 = 0x02;
+const FUTEX2_PRIVATE: i32 = 0x80;
+const FUTEX_BITSET_MATCH_ANY: u64 = u32::MAX as u64;
+
+// Build a futex entry for the 32-bit futex at `futex`. The value is the
+// expected value of a wait, or the number of waiters to wake.
+fn futex_entry(opcode: u8, futex: &AtomicU32, val: u64) -> io_uring::squeue::Entry {
+    let mut entry = opcode::Nop::new().build();
+    let raw = squeue::raw_mut(&mut entry);
+    raw.opcode = opcode;
+    raw.fd = FUTEX2_SIZE_U32 | FUTEX2_PRIVATE;
+    raw.addr = futex.as_ptr() as u64;
+    raw.off = val;
+    raw.addr3 = FUTEX_BITSET_MATCH_ANY;
+    entry
+}
+
+// Cancels a wait that is dropped before it completes, since the kernel would
+// otherwise keep it until the futex is woken.
+struct Waiting<C: cqueue::Entry>(Option<Op<C>>);
+
+impl<C: cqueue::Entry> Drop for Waiting<C> {
+    fn drop(&mut self) {
+        if let Some(op) = self.0.take() {
+            op.cancel_detached();
+        }
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Wait until `futex` is woken, for example with [`IoUringAsync::futex_wake`]
+    /// or `FUTEX_WAKE`, provided that it still holds `expected` when the wait
+    /// starts. Returns a `WouldBlock` error (`EAGAIN`) if it does not. The futex
+    /// is private to the process. Dropping the returned future cancels the wait.
+    /// Requires Linux 6.7.
+    pub fn futex_wait(&self, futex: &AtomicU32, expected: u32) -> impl Future<Output = io::Result<()>> {
+        let mut waiting = Waiting(Some(self.push(futex_entry(FUTEX_WAIT, futex, expected as u64))));
+        async move {
+            let cqe = waiting.0.as_mut().unwrap().await;
+            waiting.0.take();
+            cqueue::result_to_io(cqe.result()).map(drop)
+        }
+    }
+
+    /// Wake up to `n` tasks or threads waiting on `futex`, resolving to the
+    /// number that were woken. Requires Linux 6.7.
+    pub fn futex_wake(&self, futex: &AtomicU32, n: u32) -> impl Future<Output = io::Result<u32>> {
+        let op = self.push(futex_entry(FUTEX_WAKE, futex, n.min(i32::MAX as u32) as u64));
+        async move {
+            cqueue::result_to_io(op.await.result())
+        }
+    }
+}
+
+/// A counting semaphore whose waiters sleep on a futex through the ring, rather
+/// than on a tokio primitive. Clones share the same permits, and may be sent to
+/// other threads, each of which acquires and releases permits with its own ring.
+///
+/// Releasing a permit wakes every waiter, of which all but the one that takes
+/// the permit go back to sleep. This keeps wakeups from being lost when a
+/// waiter is dropped, at the cost of spurious wakeups under contention.
+#[derive(Debug, Clone)]
+pub struct UringSemaphore {
+    permits: Arc<AtomicU32>,
+}
+
+impl UringSemaphore {
+    /// Create a semaphore with `permits` permits.
+    pub fn new(permits: u32) -> Self {
+        Self { permits: Arc::new(AtomicU32::new(permits)) }
+    }
+
+    /// Returns the number of permits that are currently available.
+    pub fn available(&self) -> u32 {
+        self.permits.load(Ordering::Acquire)
+    }
+
+    /// Take a permit, waiting on `uring` until one is available.
+    pub async fn acquire<S: squeue::Entry, C: cqueue::Entry>(&self, uring: &IoUringAsync<S, C>) -> io::Result<()> {
+        loop {
+            let permits = self.permits.load(Ordering::Acquire);
+            if permits > 0 {
+                if self.permits.compare_exchange(permits, permits - 1, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                    return Ok(());
+                }
+                continue;
+            }
+            match uring.futex_wait(&self.permits, 0).await {
+                Ok(()) => {},
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {},
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Return a permit, and wake the waiters through `uring`. The permit is
+    /// available as soon as this is called, but waiters are only woken once
+    /// the wake is submitted.
+    pub fn release<S: squeue::Entry, C: cqueue::Entry>(&self, uring: &IoUringAsync<S, C>) -> impl Future<Output = io::Result<()>> {
+        self.permits.fetch_add(1, Ordering::Release);
+        let wake = uring.futex_wake(&self.permits, u32::MAX);
+        async move {
+            wake.await.map(drop)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::rc::Rc;
+    use std::sync::atomic::AtomicU32;
+    use std::task::{Context, Waker};
+    use crate::IoUringAsync;
+    use super::UringSemaphore;
+
+    #[test]
+    fn futex_wait_wake() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let futex = AtomicU32::new(1);
+        crate::tests::run(&uring, async {
+            let err = uring.futex_wait(&futex, 0).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+            assert_eq!(uring.futex_wake(&futex, 1).await.unwrap(), 0);
+
+            // A dropped wait is canceled rather than left for the next wake.
+            let wait = uring.futex_wait(&futex, 1);
+            uring.submit().unwrap();
+            drop(wait);
+            uring.drain().await;
+            assert_eq!(uring.futex_wake(&futex, 1).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn semaphore() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let sem = UringSemaphore::new(1);
+        crate::tests::run(&uring, async {
+            sem.acquire(&uring).await.unwrap();
+            assert_eq!(sem.available(), 0);
+
+            let mut waiter = pin!(sem.acquire(&uring));
+            assert!(waiter.as_mut().poll(&mut Context::from_waker(Waker::noop())).is_pending());
+            uring.submit().unwrap();
+            sem.release(&uring).await.unwrap();
+            waiter.await.unwrap();
+            assert_eq!(sem.available(), 0);
+        });
+
+        // A permit released on one thread is acquired by a waiter on another.
+        let remote = sem.clone();
+        let thread = std::thread::spawn(move || {
+            let uring = Rc::new(IoUringAsync::new(8).unwrap());
+            crate::tests::run(&uring, async { remote.acquire(&uring).await.unwrap() });
+        });
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        crate::tests::run(&uring, async { sem.release(&uring).await.unwrap() });
+        thread.join().unwrap();
+        assert_eq!(sem.available(), 0);
+    }
+}
@@ -0,0 +1,135 @@
+//! Waiting for a batch of Ops to complete with a single waker, see
+//! [`IoUringAsync::completion_group`].
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+use crate::{cqueue, squeue, IoUringAsync};
+
+// The state shared between a CompletionGroup and the completion callbacks of
+// its Ops.
+struct GroupState<C: cqueue::Entry> {
+    // The number of Ops in the group that have not yet completed.
+    remaining: Cell<usize>,
+    // The completion queue entries of the Ops, indexed by push order.
+    results: RefCell<Vec<Option<C>>>,
+    waker: RefCell<Option<Waker>>,
+}
+
+/// A set of Ops whose completions are collected by the ring rather than by a
+/// future per Op, so that a large fan-out, such as hundreds of reads, can be
+/// awaited with a single waker. Ops are added with [`CompletionGroup::push`],
+/// and [`CompletionGroup::wait`] resolves once every one of them has completed,
+/// after which their completion queue entries can be read with
+/// [`CompletionGroup::result`].
+///
+/// Dropping the group does not cancel its Ops, which complete in the
+/// background, as with [`IoUringAsync::push_with`].
+pub struct CompletionGroup<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    state: Rc<GroupState<C>>,
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> CompletionGroup<'_, S, C> {
+    /// Push an entry as part of the group, returning its index in the group,
+    /// which counts up from zero in push order.
+    pub fn push(&self, entry: impl Into<S>) -> usize {
+        let index = {
+            let mut results = self.state.results.borrow_mut();
+            results.push(None);
+            results.len() - 1
+        };
+        self.state.remaining.set(self.state.remaining.get() + 1);
+        let state = self.state.clone();
+        self.uring.push_with(entry, move |cqe: C| {
+            state.results.borrow_mut()[index] = Some(cqe);
+            state.remaining.set(state.remaining.get() - 1);
+            if state.remaining.get() == 0 {
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        index
+    }
+
+    /// Returns the number of Ops pushed as part of the group.
+    pub fn len(&self) -> usize {
+        self.state.results.borrow().len()
+    }
+
+    /// Returns whether no Ops have been pushed as part of the group.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of Ops in the group that have not yet completed.
+    pub fn remaining(&self) -> usize {
+        self.state.remaining.get()
+    }
+
+    /// Wait until every Op pushed as part of the group so far has completed.
+    /// Resolves immediately if the group is empty.
+    pub async fn wait(&self) {
+        std::future::poll_fn(|cx| {
+            if self.state.remaining.get() == 0 {
+                return Poll::Ready(());
+            }
+            *self.state.waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        }).await
+    }
+
+    /// Returns the completion queue entry of the Op with the given index in the
+    /// group, or `None` if it has not completed or no such Op was pushed.
+    pub fn result(&self, index: usize) -> Option<C> {
+        self.state.results.borrow().get(index).cloned().flatten()
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> std::fmt::Debug for CompletionGroup<'_, S, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompletionGroup")
+            .field("len", &self.len())
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Create an empty [`CompletionGroup`] for awaiting a batch of Ops at once.
+    pub fn completion_group(&self) -> CompletionGroup<'_, S, C> {
+        let state = GroupState { remaining: Cell::new(0), results: RefCell::default(), waker: RefCell::default() };
+        CompletionGroup { uring: self, state: Rc::new(state) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use io_uring::opcode::Nop;
+    use crate::IoUringAsync;
+
+    #[test]
+    fn completion_group() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let group = uring.completion_group();
+            assert!(group.is_empty());
+            group.wait().await;
+
+            for _ in 0..100 {
+                group.push(Nop::new().build());
+            }
+            let timespec = crate::time::timespec(std::time::Duration::from_millis(5));
+            let timeout = group.push(io_uring::opcode::Timeout::new(&timespec).build());
+            assert_eq!(group.len(), 101);
+            group.wait().await;
+            assert_eq!(group.remaining(), 0);
+            assert!((0..100).all(|index| group.result(index).unwrap().result() == 0));
+            assert_eq!(group.result(timeout).unwrap().result(), -libc::ETIME);
+            assert!(group.result(101).is_none());
+        });
+        assert!(uring.slab.borrow().is_empty());
+    }
+}
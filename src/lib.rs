@@ -1,8 +1,9 @@
 use std::future::Future;
-use std::os::unix::prelude::{RawFd, AsRawFd};
+use std::os::unix::prelude::{RawFd, AsRawFd, FromRawFd};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use io_uring::{IoUring};
+use io_uring::opcode::AsyncCancel;
 use tokio::io::unix::AsyncFd;
 
 // The IoUring Op state.
@@ -17,9 +18,173 @@ enum Lifecycle<C: cqueue::Entry> {
     // received, the Waker can be used to trigger the Rust async runtime to poll
     // the Op.
     Waiting(std::task::Waker),
+    // The Op has been polled by the task whose waker is shared by the Ops of a
+    // single-task ring, which is woken once for a batch of completions rather
+    // than once per Op. See [`builder::Builder::single_task`].
+    WaitingTask,
     // The Op has received a submission queue entry. The Op will
     // be Ready the next time that it is polled.
-    Completed(C)
+    Completed(C),
+    // The Op is a multishot operation, which may receive any number of
+    // completion queue entries. See [`multishot::MultishotOp`].
+    Multishot(multishot::Shots<C>),
+    // The Op was pushed with a callback instead of being awaited. The callback
+    // is run, and the slot freed, when the completion queue entry is received.
+    Callback(Box<dyn FnOnce(C)>),
+    // The entry is part of a link chain and flagged with CQE_SKIP_SUCCESS, so it
+    // only posts a completion queue entry if it fails, which is then handed to
+    // the Ops at `rest` that follow it in the chain. Otherwise, the slot is freed
+    // once the Op that follows it completes, which also drops its resources.
+    // If an earlier Op of the chain failed, which is recorded in `canceled`,
+    // the kernel instead posts a completion for every later entry, including
+    // this one, whose completion then only frees the slot.
+    // See [`link::Link::skip_success`].
+    Skipped { rest: Vec<usize>, canceled: bool, _resources: Option<Box<dyn std::any::Any>> },
+}
+
+/// The state of an Op in the ring, as reported by [`IoUringAsync::debug_pending`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpState {
+    /// The Op has been pushed, but has not been polled or completed.
+    Submitted,
+    /// The Op has been polled, and is waiting for its completion.
+    Waiting,
+    /// The Op has completed, but has not yet been polled or dropped.
+    Completed,
+    /// The Op is a multishot operation.
+    Multishot,
+    /// The Op was pushed with [`IoUringAsync::push_with`].
+    Callback,
+    /// The entry is part of a link chain and only completes if it fails. See
+    /// [`Link::skip_success`](link::Link::skip_success).
+    Skipped,
+}
+
+impl<C: cqueue::Entry> Lifecycle<C> {
+    fn state(&self) -> OpState {
+        match self {
+            Lifecycle::Submitted => OpState::Submitted,
+            Lifecycle::Waiting(_) | Lifecycle::WaitingTask => OpState::Waiting,
+            Lifecycle::Completed(_) => OpState::Completed,
+            Lifecycle::Multishot(_) => OpState::Multishot,
+            Lifecycle::Callback(_) => OpState::Callback,
+            Lifecycle::Skipped { .. } => OpState::Skipped,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Lifecycle::Submitted => "Submitted",
+            Lifecycle::Waiting(_) | Lifecycle::WaitingTask => "Waiting",
+            Lifecycle::Completed(_) => "Completed",
+            Lifecycle::Multishot(_) => "Multishot",
+            Lifecycle::Callback(_) => "Callback",
+            Lifecycle::Skipped { .. } => "Skipped",
+        }
+    }
+}
+
+// The slab of in-flight Ops, along with the number of its slots whose Ops have
+// completed, so that the number of Ops in flight is known without walking it.
+// Slots must be removed with Slots::remove, and the count bumped whenever an Op
+// completes.
+struct Slots<C: cqueue::Entry> {
+    slab: slab::Slab<Slot<C>>,
+    completed: usize,
+}
+
+impl<C: cqueue::Entry> Default for Slots<C> {
+    fn default() -> Self {
+        Self { slab: slab::Slab::new(), completed: 0 }
+    }
+}
+
+impl<C: cqueue::Entry> Slots<C> {
+    fn remove(&mut self, index: usize) -> Slot<C> {
+        let slot = self.slab.remove(index);
+        if slot.is_completed() {
+            self.completed -= 1;
+        }
+        slot
+    }
+
+    fn in_flight(&self) -> usize {
+        self.slab.len() - self.completed
+    }
+}
+
+impl<C: cqueue::Entry> std::ops::Deref for Slots<C> {
+    type Target = slab::Slab<Slot<C>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.slab
+    }
+}
+
+impl<C: cqueue::Entry> std::ops::DerefMut for Slots<C> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.slab
+    }
+}
+
+// An entry in the slab of in-flight Ops.
+struct Slot<C: cqueue::Entry> {
+    lifecycle: Lifecycle<C>,
+    // An opaque tag associated with the Op by the user. Unlike the slab index,
+    // which is used as the submission queue entry's user_data, the tag has no
+    // meaning to this crate.
+    tag: Option<u64>,
+    // The opcode of the entry and the time that it was pushed, which are only
+    // recorded if a completion hook is installed or latency stats are enabled.
+    pushed: Option<(u8, std::time::Instant)>,
+    // The time that the entry was submitted to the kernel, which is only
+    // recorded if latency stats are enabled.
+    submitted: Option<std::time::Instant>,
+    // The registered resources that the entry used.
+    fixed: squeue::FixedResources,
+    // The skipped entries of a link chain that immediately precede the Op,
+    // which have succeeded once it completes.
+    skipped: Vec<usize>,
+    // The skipped entries of a link chain that follow the Op, which post a
+    // completion of their own if the Op fails.
+    skipped_after: Vec<usize>,
+    // The position of the Op in the order that Ops were pushed onto the ring.
+    #[cfg(feature = "op-sequence")]
+    seq: u64,
+}
+
+impl<C: cqueue::Entry> Slot<C> {
+    fn new(tag: Option<u64>) -> Self {
+        Self {
+            lifecycle: Lifecycle::Submitted,
+            tag,
+            pushed: None,
+            submitted: None,
+            fixed: squeue::FixedResources::default(),
+            skipped: Vec::new(),
+            skipped_after: Vec::new(),
+            #[cfg(feature = "op-sequence")]
+            seq: 0,
+        }
+    }
+
+    // Returns the submit sequence number of the Op, if they are recorded.
+    fn seq(&self) -> Option<u64> {
+        #[cfg(feature = "op-sequence")]
+        return Some(self.seq);
+        #[cfg(not(feature = "op-sequence"))]
+        None
+    }
+
+    // Returns whether the kernel will not post any further completion queue
+    // entries for the Op.
+    fn is_completed(&self) -> bool {
+        match &self.lifecycle {
+            Lifecycle::Completed(_) => true,
+            Lifecycle::Multishot(shots) => shots.done,
+            _ => false,
+        }
+    }
 }
 
 // An Future implementation that represents the current state of an IoUring Op.
@@ -39,25 +204,263 @@ impl<C: cqueue::Entry> Future for Op<C> {
     }
 }
 
+impl<C: cqueue::Entry> Op<C> {
+    /// Returns the tag that the Op was pushed with, if any.
+    pub fn tag(&self) -> Option<u64> {
+        let inner = self.inner.as_ref().unwrap();
+        inner.slab.borrow()[inner.index].tag
+    }
+
+    /// Returns the registered resources that the Op's entry used, such as a
+    /// direct descriptor or a registered buffer, for checking that an Op took
+    /// the fast path. This reflects the entry as it was pushed. The kernel does
+    /// not fall back between the two paths: an entry that refers to a missing
+    /// registered resource fails instead.
+    pub fn fixed_resources(&self) -> squeue::FixedResources {
+        let inner = self.inner.as_ref().unwrap();
+        inner.slab.borrow()[inner.index].fixed
+    }
+
+    /// Returns whether the Op's completion queue entry has been received, in
+    /// which case awaiting the Op resolves immediately. This does not change the
+    /// state of the Op, so schedulers can use it to prioritize ready Ops. It
+    /// returns `false` while completions are being routed to their Ops.
+    pub fn is_completed(&self) -> bool {
+        let inner = self.inner.as_ref().unwrap();
+        inner.slab.try_borrow().is_ok_and(|guard| matches!(guard[inner.index].lifecycle, Lifecycle::Completed(_)))
+    }
+
+    /// Returns a copy of the Op's completion queue entry if it has been
+    /// received, without registering a waker if it has not. Like
+    /// [`Op::is_completed`], this does not change the state of the Op, so the
+    /// entry is returned again by later calls and by awaiting the Op. This
+    /// allows an Op to be driven manually, for example alongside
+    /// [`IoUringAsync::process_completions`].
+    pub fn peek_result(&self) -> Option<C> {
+        let inner = self.inner.as_ref().unwrap();
+        match &inner.slab.try_borrow().ok()?[inner.index].lifecycle {
+            Lifecycle::Completed(cqe) => Some(cqe.clone()),
+            _ => None,
+        }
+    }
+
+    /// Transform the Op's completion queue entry with `f` once it completes,
+    /// for example into an `io::Result`. Dropping the returned future drops the
+    /// Op, with the same semantics as dropping the Op itself.
+    pub async fn map<T, F: FnOnce(C) -> T>(self, f: F) -> T {
+        f(self.await)
+    }
+
+    /// Ask the kernel to cancel the Op, and wait for both the Op and the cancel
+    /// request to complete, after which neither occupies a slot of the ring.
+    /// An Op that completed before it could be canceled is not an error, and
+    /// its completion queue entry is discarded, so Ops whose result owns a
+    /// resource, such as an accepted connection, should not be canceled this
+    /// way. Returns the error of a cancel request that the kernel rejected, in
+    /// which case the Op is left to complete in the background, as if dropped.
+    pub async fn cancel(self) -> std::io::Result<()> {
+        let inner = self.inner.as_ref().unwrap();
+        if !inner.is_completed() {
+            let cancel = Op { inner: Some(inner.push_cancel()?) }.await;
+            match -cancel.result() {
+                // The Op completed before the cancel request found it, or was
+                // already running, in which case it is interrupted if it can be.
+                0 | libc::ENOENT | libc::EALREADY => {}
+                errno => return Err(std::io::Error::from_raw_os_error(errno)),
+            }
+        }
+        self.await;
+        Ok(())
+    }
+
+    // Take the resources attached to the Op, which is only safe once the Op has
+    // completed.
+    pub(crate) fn take_resources(&mut self) -> Option<Box<dyn std::any::Any>> {
+        let inner = self.inner.as_mut().unwrap();
+        assert!(inner.is_completed(), "resources taken from an Op before completion");
+        inner.resources.take()
+    }
+
+    // Ask the kernel to cancel the Op, and wait for both the Op and the cancel
+    // request to complete. Resolves to the Op's completion queue entry, which
+    // may indicate success if the Op completed before it could be canceled.
+    pub(crate) async fn cancel_and_wait(self) -> C {
+        let inner = self.inner.as_ref().unwrap();
+        if !inner.is_completed() {
+            if let Ok(cancel) = inner.push_cancel() {
+                Op { inner: Some(cancel) }.await;
+            }
+        }
+        self.await
+    }
+
+    // Ask the kernel to cancel the Op without waiting for the cancellation to
+    // complete. Both the Op and the cancel request are reaped in the background.
+    pub(crate) fn cancel_detached(self) {
+        let inner = self.inner.as_ref().unwrap();
+        if !inner.is_completed() {
+            if let Ok(cancel) = inner.push_cancel() {
+                drop(Op { inner: Some(cancel) });
+            }
+        }
+    }
+}
+
+impl<C: cqueue::Entry> std::fmt::Debug for Op<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Op");
+        if let Some(inner) = &self.inner {
+            debug.field("index", &inner.index);
+            // The slab may be borrowed if the Op is formatted while completions
+            // are being dispatched, such as from a Waker.
+            match inner.slab.try_borrow() {
+                Ok(guard) => debug.field("lifecycle", &format_args!("{}", guard[inner.index].lifecycle.name())),
+                Err(_) => debug.field("lifecycle", &format_args!("<borrowed>")),
+            };
+        }
+        debug.finish()
+    }
+}
+
 impl<C: cqueue::Entry> Drop for Op<C> {
     fn drop(&mut self) {
         let inner = self.inner.take().unwrap();
         let guard = inner.slab.borrow();
-        match &guard[inner.index] {
+        match &guard[inner.index].lifecycle {
             Lifecycle::Completed(_) => {},
             _ => {
                 drop(guard);
-                tokio::task::spawn_local(async {
-                    inner.await
-                });
+                // Without a runtime, nothing may process completions again, and
+                // a thread that is unwinding may be about to drop the ring.
+                if std::thread::panicking() || tokio::runtime::Handle::try_current().is_err() {
+                    inner.cancel_and_reap();
+                } else {
+                    inner.detach();
+                }
+            }
+        }
+    }
+}
+
+// An Op that owns the resources (buffers, msghdrs, sockaddrs, ...) referenced by
+// its submission queue entry. If the OwnedOp is dropped before completion,
+// ownership of the resources is transferred to the underlying Op so that they
+// are not freed while the kernel may still be using them.
+pub(crate) struct OwnedOp<C: cqueue::Entry, T: 'static> {
+    op: Op<C>,
+    resources: Option<T>,
+}
+
+impl<C: cqueue::Entry, T: 'static> OwnedOp<C, T> {
+    pub(crate) fn new(op: Op<C>, resources: T) -> Self {
+        Self { op, resources: Some(resources) }
+    }
+}
+
+impl<C: cqueue::Entry, T: Unpin + 'static> Future for OwnedOp<C, T> {
+    type Output = (C, T);
+
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        match std::pin::Pin::new(&mut self.op).poll(cx) {
+            std::task::Poll::Ready(cqe) => {
+                std::task::Poll::Ready((cqe, self.resources.take().unwrap()))
             }
+            std::task::Poll::Pending => std::task::Poll::Pending
+        }
+    }
+}
+
+impl<C: cqueue::Entry, T: 'static> Drop for OwnedOp<C, T> {
+    fn drop(&mut self) {
+        if let Some(resources) = self.resources.take() {
+            self.op.inner.as_mut().unwrap().resources = Some(Box::new(resources));
         }
     }
 }
 
 pub struct OpInner<C: cqueue::Entry> {
-    slab: Rc<RefCell<slab::Slab<Lifecycle<C>>>>,
+    uring: Rc<dyn Driver<C>>,
+    slab: Rc<RefCell<Slots<C>>>,
     index: usize,
+    // Resources that the kernel may access until the Op completes. These are
+    // only attached when an OwnedOp is dropped before completion.
+    resources: Option<Box<dyn std::any::Any>>,
+}
+
+impl<C: cqueue::Entry> OpInner<C> {
+    fn is_completed(&self) -> bool {
+        matches!(self.slab.borrow()[self.index].lifecycle, Lifecycle::Completed(_))
+    }
+
+    // Push an AsyncCancel request targeting the Op onto the submission queue.
+    fn push_cancel(&self) -> std::io::Result<OpInner<C>> {
+        push_cancel(&self.uring, &self.slab, self.index)
+    }
+
+    // Hand the slot of an Op that is dropped before it completes over to the
+    // ring, which frees it along with the Op's resources once its completion
+    // is processed, as for an Op pushed with push_with. Unlike a task awaiting
+    // the Op, this does not require a LocalSet.
+    fn detach(mut self) {
+        let resources = self.resources.take();
+        let mut guard = self.slab.borrow_mut();
+        let lifecycle = &mut guard[self.index].lifecycle;
+        if let Lifecycle::WaitingTask = lifecycle {
+            self.uring.task_waker().unwrap().borrow_mut().waiting -= 1;
+        }
+        *lifecycle = Lifecycle::Callback(Box::new(move |_| drop(resources)));
+    }
+
+    // Synchronously cancel the Op and block the current thread until both the
+    // Op and the cancel request have completed. This is the fallback used when
+    // an Op is dropped without a runtime to process its completion.
+    // Returns false if the Op could not be reaped, in which case the kernel may
+    // still be using its resources.
+    fn cancel_and_reap(self) -> bool {
+        let cancel = match self.push_cancel() {
+            Ok(cancel) => cancel,
+            Err(_) => {
+                // The kernel may still own resources belonging to the Op, so
+                // the slab entry is leaked rather than freed.
+                std::mem::forget(self);
+                return false;
+            }
+        };
+
+        let reaped = (|| {
+            while !(self.is_completed() && cancel.is_completed()) {
+                self.uring.submit_and_wait(1)?;
+                dispatch_completions(&*self.uring, &self.slab);
+            }
+            std::io::Result::Ok(())
+        })();
+
+        if reaped.is_err() {
+            std::mem::forget(self);
+            std::mem::forget(cancel);
+            return false;
+        }
+        true
+    }
+}
+
+// Push an AsyncCancel request targeting the slab entry at `target` onto the
+// submission queue.
+fn push_cancel<C: cqueue::Entry>(uring: &Rc<dyn Driver<C>>, slab: &Rc<RefCell<Slots<C>>>, target: usize) -> std::io::Result<OpInner<C>> {
+    let index = slab.borrow_mut().insert(Slot::new(None));
+    let entry = AsyncCancel::new(target.try_into().unwrap())
+        .build()
+        .user_data(index.try_into().unwrap());
+    if let Err(err) = uring.push(entry) {
+        slab.borrow_mut().remove(index);
+        return Err(err);
+    }
+    Ok(OpInner {
+        uring: uring.clone(),
+        slab: slab.clone(),
+        index,
+        resources: None,
+    })
 }
 
 impl<C: cqueue::Entry> Future for OpInner<C> {
@@ -65,19 +468,42 @@ impl<C: cqueue::Entry> Future for OpInner<C> {
 
     fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
         let mut guard = self.slab.borrow_mut();
-        let lifecycle = &mut guard[self.index];
+        let lifecycle = &mut guard[self.index].lifecycle;
+        let task_waker = self.uring.task_waker();
         match lifecycle {
-            Lifecycle::Submitted => {
-                *lifecycle = Lifecycle::Waiting(cx.waker().clone());
+            // An Op that completed before it was polled, as is common when it is
+            // awaited right after being submitted, resolves without touching the
+            // waker.
+            Lifecycle::Completed(cqe) => {
+                std::task::Poll::Ready(cqe.clone())
+            }
+            // Polling again from the same task, such as from a select loop, keeps
+            // the registered waker rather than cloning it.
+            Lifecycle::Waiting(waker) if waker.will_wake(cx.waker()) => {
                 std::task::Poll::Pending
             }
-            Lifecycle::Waiting(_) => {
-                *lifecycle = Lifecycle::Waiting(cx.waker().clone());
+            Lifecycle::WaitingTask if task_waker.unwrap().borrow().will_wake(cx.waker()) => {
                 std::task::Poll::Pending
             }
-            Lifecycle::Completed(cqe) => {
-                std::task::Poll::Ready(cqe.clone())
+            Lifecycle::Submitted | Lifecycle::Waiting(_) | Lifecycle::WaitingTask => {
+                if let Some(task_waker) = task_waker {
+                    let mut task_waker = task_waker.borrow_mut();
+                    // An Op that moved to another task, such as one that was
+                    // spawned, stops waiting on the shared waker.
+                    if let Lifecycle::WaitingTask = lifecycle {
+                        task_waker.waiting -= 1;
+                    }
+                    if task_waker.register(cx.waker()) {
+                        *lifecycle = Lifecycle::WaitingTask;
+                        return std::task::Poll::Pending;
+                    }
+                }
+                *lifecycle = Lifecycle::Waiting(cx.waker().clone());
+                std::task::Poll::Pending
             }
+            Lifecycle::Multishot(_) => unreachable!("multishot slot polled as an Op"),
+            Lifecycle::Callback(_) => unreachable!("callback slot polled as an Op"),
+            Lifecycle::Skipped { .. } => unreachable!("skipped slot polled as an Op"),
         }
     }
 }
@@ -85,149 +511,1780 @@ impl<C: cqueue::Entry> Future for OpInner<C> {
 impl<C: cqueue::Entry> Drop for OpInner<C> {
     fn drop(&mut self) {
         let mut guard = self.slab.borrow_mut();
-        let lifecycle = guard.remove(self.index);
-        match lifecycle {
-            Lifecycle::Completed(_) => {},
-            _ => panic!("Op drop occured before completion")
+        match guard[self.index].lifecycle {
+            Lifecycle::Completed(_) => drop(guard.remove(self.index)),
+            // A detached Op, whose slot is freed once it completes.
+            Lifecycle::Callback(_) => {},
+            _ => {
+                guard.remove(self.index);
+                panic!("Op drop occured before completion")
+            }
         };
     }
 }
 
 pub mod squeue;
 pub mod cqueue;
+pub mod buf_ring;
+pub mod buffers;
+pub mod builder;
+pub mod combinator;
+pub mod fd;
+pub mod files;
+pub mod fs;
+pub mod futex;
+pub mod group;
+pub mod link;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod multishot;
+pub mod net;
+pub mod ops;
+pub mod scoped;
+pub mod shutdown;
+pub mod stats;
+pub mod stream;
+pub mod tag;
+pub mod time;
+pub mod trace;
+pub mod writer;
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod testing;
 
-pub struct IoUringAsync<S: squeue::Entry = io_uring::squeue::Entry, C: cqueue::Entry = io_uring::cqueue::Entry> {
-    uring: Rc<IoUring<S, C>>,
-    slab: Rc<RefCell<slab::Slab<Lifecycle<C>>>>
+// Type-erased access to the io_uring that an Op was pushed onto. This allows an
+// Op to drive the ring itself without knowing its submission queue entry type.
+trait Driver<C: cqueue::Entry> {
+    fn push(&self, entry: io_uring::squeue::Entry) -> std::io::Result<()>;
+    fn submit_and_wait(&self, want: usize) -> std::io::Result<usize>;
+    fn next_completion(&self) -> Option<C>;
+    // Called after a batch of completions has been dispatched.
+    fn completions_dispatched(&self);
+    // Called when a completion queue entry does not belong to any Op.
+    fn orphan_completion(&self, cqe: &C);
+    // Called when a completion queue entry belongs to an Op that has already
+    // completed.
+    fn unexpected_completion(&self, cqe: &C);
+    // Called when a direct descriptor sent by another ring has been installed.
+    // Returns the wakers of the tasks waiting for it.
+    fn fd_received(&self, cqe: &C) -> Vec<std::task::Waker>;
+    fn submitter(&self) -> io_uring::Submitter<'_>;
+    // Returns the user_data and submission time of the entries submitted since
+    // this was last called, if latency stats are enabled.
+    fn take_submitted(&self) -> Vec<(u64, std::time::Instant)>;
+    // Called with every completion queue entry that belongs to an Op whose push
+    // was timestamped, before the Op is woken. The submission time is only
+    // passed with the first completion of an Op.
+    fn completed(&self, tag: Option<u64>, seq: Option<u64>, pushed: (u8, std::time::Instant), submitted: Option<std::time::Instant>, cqe: &C);
+    // Called with every completion queue entry that belongs to an Op with a
+    // tag. Returns the wakers of the tasks waiting for the tag.
+    fn tag_completed(&self, tag: u64, cqe: &C) -> Vec<std::task::Waker>;
+    // Returns the waker shared by the Ops of a single-task ring.
+    fn task_waker(&self) -> Option<&RefCell<TaskWaker>>;
 }
 
-impl<S: squeue::Entry, C: cqueue::Entry> AsRawFd for IoUringAsync<S, C> {
-    fn as_raw_fd(&self) -> RawFd {
-        self.uring.as_raw_fd()
-    }
+// The waker shared by the Ops of a single-task ring, which is registered once
+// rather than cloned into every Op. It is kept after its Ops complete, so that
+// the next Op polled from the same task does not clone it again, and is only
+// replaced by the waker of another task once no Op is waiting on it.
+#[derive(Default)]
+struct TaskWaker {
+    waker: Option<std::task::Waker>,
+    // The number of Ops in the WaitingTask state.
+    waiting: usize,
 }
 
-impl IoUringAsync<io_uring::squeue::Entry, io_uring::cqueue::Entry> {
-    pub fn new(entries: u32) -> std::io::Result<Self> {
-        Ok(Self {
-            uring: Rc::new(io_uring::IoUring::generic_new(entries)?),
-            slab: Rc::new(RefCell::new(slab::Slab::new()))
-        })
+impl TaskWaker {
+    fn will_wake(&self, waker: &std::task::Waker) -> bool {
+        self.waker.as_ref().is_some_and(|shared| shared.will_wake(waker))
+    }
+
+    // Register an Op polled with `waker` as waiting on the shared waker.
+    // Returns false if Ops of another task are waiting on it, in which case
+    // the Op must keep a waker of its own.
+    fn register(&mut self, waker: &std::task::Waker) -> bool {
+        if !self.will_wake(waker) {
+            if self.waiting > 0 {
+                return false;
+            }
+            self.waker = Some(waker.clone());
+        }
+        self.waiting += 1;
+        true
     }
 }
 
-impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
-    
-    pub async fn listen(uring: Rc<IoUringAsync<S, C>>) {
-        let async_fd = AsyncFd::new(uring).unwrap();
-        loop {
-            let mut guard = async_fd.readable().await.unwrap();
-            guard.get_inner().handle_cqe();
-            guard.clear_ready();
+// The io_uring backing an IoUringAsync, along with the statistics that are
+// updated as it is used. All pushes and submissions go through the Ring so
+// that the statistics stay consistent with the state of the queues.
+struct Ring<S: squeue::Entry, C: cqueue::Entry> {
+    inner: IoUring<S, C>,
+    stats: Cell<stats::RingStats>,
+    // Tasks waiting for the ring to have no Ops in flight, which are woken
+    // whenever completions are dispatched.
+    drain_wakers: RefCell<Vec<std::task::Waker>>,
+    // The number of entries that have been pushed onto the submission queue but
+    // not yet submitted to the kernel.
+    unsubmitted: Cell<usize>,
+    // Memory provided to the kernel with ProvideBuffers. This is declared after
+    // `inner` so that it is freed after the io_uring is closed.
+    provided_buffers: RefCell<Vec<buffers::ProvidedMemory>>,
+    on_completion: Option<Rc<trace::CompletionHook>>,
+    on_unexpected_completion: Option<Rc<trace::UnexpectedHook>>,
+    // The direct descriptor slots installed by other rings, which have not yet
+    // been received with IoUringAsync::recv_msg_ring_fd.
+    received_fds: RefCell<files::ReceivedFds>,
+    // The submission times of pushed entries, if latency stats are enabled.
+    submit_times: Option<RefCell<stats::SubmitTimes>>,
+    // The tasks waiting with IoUringAsync::await_tag.
+    tag_waiters: RefCell<tag::TagWaiters<C>>,
+    // Set by IoUringAsync::shutdown to make the listen loop return, which is
+    // woken through the listen wakers.
+    listen_stopped: Cell<bool>,
+    listen_wakers: RefCell<Vec<std::task::Waker>>,
+    // Set by IoUringAsync::kick to make the listen loop process completions
+    // without waiting for the ring to become readable.
+    listen_kicked: Cell<bool>,
+    // The waker shared by the Ops of a single-task ring.
+    task_waker: Option<RefCell<TaskWaker>>,
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Ring<S, C> {
+    fn new(inner: IoUring<S, C>) -> Self {
+        Self {
+            inner,
+            stats: Cell::new(stats::RingStats::default()),
+            drain_wakers: RefCell::new(Vec::new()),
+            unsubmitted: Cell::new(0),
+            provided_buffers: RefCell::new(Vec::new()),
+            on_completion: None,
+            on_unexpected_completion: None,
+            received_fds: RefCell::default(),
+            submit_times: None,
+            tag_waiters: RefCell::default(),
+            listen_stopped: Cell::new(false),
+            listen_wakers: RefCell::default(),
+            listen_kicked: Cell::new(false),
+            task_waker: None,
         }
     }
 
-    pub fn generic_new(entries: u32) -> std::io::Result<Self> {
-        Ok(Self {
-            uring: Rc::new(io_uring::IoUring::generic_new(entries)?),
-            slab: Rc::new(RefCell::new(slab::Slab::new()))
-        })
+    fn record(&self, f: impl FnOnce(&mut stats::RingStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
     }
 
-    pub fn push(&self, entry: impl Into<S>) -> Op<C> {
-        let mut guard = self.slab.borrow_mut();
-        let index = guard.insert(Lifecycle::Submitted);
-        let entry = entry.into().user_data(index.try_into().unwrap());
-        while unsafe { self.uring.submission_shared().push(&entry).is_err() } {
-            self.uring.submit().unwrap();
+    // Push entries onto the submission queue, submitting queued entries to the
+    // kernel as needed to make room. The entries are pushed contiguously.
+    fn push_multiple(&self, entries: &[S]) -> std::io::Result<()> {
+        while unsafe { self.inner.submission_shared().push_multiple(entries).is_err() } {
+            self.submit()?;
         }
-        Op {
-            inner: Some(OpInner {
-                slab: self.slab.clone(),
-                index: index,
-            })
+        if let Some(times) = &self.submit_times {
+            let mut times = times.borrow_mut();
+            for entry in entries {
+                times.pushed(squeue::user_data_of(entry));
+            }
+        }
+        #[cfg(feature = "tracing")]
+        for entry in entries {
+            tracing::trace!(opcode = entry.opcode(), user_data = squeue::user_data_of(entry), "io_uring entry pushed");
         }
+        self.unsubmitted.set(self.unsubmitted.get() + entries.len());
+        self.record(|stats| stats.pushed += entries.len() as u64);
+        Ok(())
     }
 
-    pub fn handle_cqe(&self) {
-        let mut guard = self.slab.borrow_mut();
-        while let Some(cqe) = unsafe{ self.uring.completion_shared() }.next() {
-            let index = cqe.user_data();
-            let lifecycle = &mut guard[index.try_into().unwrap()];
-            match lifecycle {
-                Lifecycle::Submitted => {
-                    *lifecycle = Lifecycle::Completed(cqe);
-                }
-                Lifecycle::Waiting(waker) => {
-                    waker.wake_by_ref();
-                    *lifecycle = Lifecycle::Completed(cqe);
-                }
-                Lifecycle::Completed(cqe) => {
-                    println!("multishot operations not implemented: {}, {}", cqe.user_data(), cqe.result());
-                }
+    // Submit queued entries and wait for `want` completions like submit_and_wait,
+    // but for no longer than `timeout` in total. The timeout elapsing is not an
+    // error. Requires IORING_FEAT_EXT_ARG (Linux 5.11).
+    fn submit_and_wait_timeout(&self, want: usize, timeout: std::time::Duration) -> std::io::Result<usize> {
+        let deadline = std::time::Instant::now() + timeout;
+        self.enter(|| {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            let timespec = io_uring::types::Timespec::new().sec(remaining.as_secs()).nsec(remaining.subsec_nanos());
+            let args = io_uring::types::SubmitArgs::new().timespec(&timespec);
+            match self.inner.submitter().submit_with_args(want, &args) {
+                Err(err) if err.raw_os_error() == Some(libc::ETIME) => Ok(0),
+                result => result,
+            }
+        })
+    }
+
+    // Enter the kernel with `enter`, retrying if it is interrupted by a signal,
+    // and account for the entries that it submitted.
+    fn enter(&self, enter: impl Fn() -> std::io::Result<usize>) -> std::io::Result<usize> {
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("io_uring_submit", queued = self.unsubmitted.get(), submitted = tracing::field::Empty).entered();
+        let submitted = loop {
+            match enter() {
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => self.record(|stats| {
+                    stats.submit_calls += 1;
+                    stats.interrupted_submits += 1;
+                }),
+                result => break result?,
             }
+        };
+        #[cfg(feature = "tracing")]
+        span.record("submitted", submitted);
+        self.unsubmitted.set(self.unsubmitted.get().saturating_sub(submitted));
+        if let Some(times) = &self.submit_times {
+            times.borrow_mut().submitted(submitted);
         }
+        self.record(|stats| {
+            stats.submit_calls += 1;
+            stats.submitted += submitted as u64;
+        });
+        Ok(submitted)
     }
 
-    /// Submit all queued submission queue events to the kernel.
-    pub fn submit(&self) -> std::io::Result<usize> {
-        self.uring.submit()
+    // Submit queued entries to the kernel, without entering the kernel if
+    // nothing is queued. The kernel is still entered if completion queue entries
+    // have overflowed, since entering the kernel is what flushes them.
+    // Pass a completion queue entry that is discarded to the unexpected
+    // completion hook.
+    fn report_unexpected(&self, cqe: &C, orphan: bool) {
+        let event = trace::UnexpectedCompletion { user_data: cqe.user_data(), result: cqe.result(), flags: cqe.flags(), orphan };
+        #[cfg(feature = "tracing")]
+        tracing::warn!(user_data = event.user_data, result = event.result, orphan, "io_uring completion discarded");
+        if let Some(hook) = &self.on_unexpected_completion {
+            hook(&event);
+        }
+    }
+
+    fn submit(&self) -> std::io::Result<usize> {
+        if self.unsubmitted.get() == 0 && !unsafe { self.inner.submission_shared() }.cq_overflow() {
+            return Ok(0);
+        }
+        self.submit_and_wait(0)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::rc::Rc;
-    use io_uring::opcode::Nop;
-    use super::IoUringAsync;
-    use send_wrapper::SendWrapper;
+impl<S: squeue::Entry, C: cqueue::Entry> Driver<C> for Ring<S, C> {
+    fn push(&self, entry: io_uring::squeue::Entry) -> std::io::Result<()> {
+        self.push_multiple(std::slice::from_ref(&entry.into()))
+    }
 
-    #[test]
-    fn example1() {
-        let uring = Rc::new(IoUringAsync::new(8).unwrap());
-        let runtime = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .unwrap();  
+    // A signal that interrupts io_uring_enter is retried rather than reported,
+    // since the kernel only fails with EINTR if nothing was submitted.
+    fn submit_and_wait(&self, want: usize) -> std::io::Result<usize> {
+        self.enter(|| self.inner.submit_and_wait(want))
+    }
+    fn next_completion(&self) -> Option<C> {
+        unsafe { self.inner.completion_shared() }.next()
+    }
 
-        runtime.block_on(async move {
-            tokio::task::LocalSet::new().run_until(async {
-                tokio::task::spawn_local(IoUringAsync::listen(uring.clone()));
+    fn completions_dispatched(&self) {
+        for waker in self.drain_wakers.take() {
+            waker.wake();
+        }
+    }
 
-                let fut1 = uring.push(Nop::new().build());
-                let fut2 = uring.push(Nop::new().build());
-                
-                uring.submit().unwrap();
-                
-                let cqe1 = fut1.await;
-                let cqe2 = fut2.await;
+    fn orphan_completion(&self, cqe: &C) {
+        self.record(|stats| stats.orphan_completions += 1);
+        self.report_unexpected(cqe, true);
+    }
 
-                assert!(cqe1.result() >= 0, "nop error: {}", cqe1.result()); 
-                assert!(cqe2.result() >= 0, "nop error: {}", cqe2.result()); 
-            }).await; 
-        });
+    fn unexpected_completion(&self, cqe: &C) {
+        self.record(|stats| stats.unexpected_completions += 1);
+        self.report_unexpected(cqe, false);
     }
 
-    #[test]
-    fn example2() {
-        let uring = IoUringAsync::new(8).unwrap();
-        let uring = Rc::new(uring);
+    fn fd_received(&self, cqe: &C) -> Vec<std::task::Waker> {
+        self.received_fds.borrow_mut().receive(cqe)
+    }
 
-        // Create a new current_thread runtime that submits all outstanding submission queue
-        // entries as soon as the executor goes idle.
-        let uring_clone = SendWrapper::new(uring.clone());
-        let runtime = tokio::runtime::Builder::new_current_thread().
-            on_thread_park(move || { uring_clone.submit().unwrap(); }).
-            enable_all().
-            build().unwrap();  
+    fn submitter(&self) -> io_uring::Submitter<'_> {
+        self.inner.submitter()
+    }
 
-        runtime.block_on(async move {
-            tokio::task::LocalSet::new().run_until(async {
-                tokio::task::spawn_local(IoUringAsync::listen(uring.clone()));
+    fn take_submitted(&self) -> Vec<(u64, std::time::Instant)> {
+        self.submit_times.as_ref().map_or_else(Vec::new, |times| times.borrow_mut().take())
+    }
 
-                let cqe = uring.push(Nop::new().build()).await;
-                assert!(cqe.result() >= 0, "nop error: {}", cqe.result()); 
-            }).await; 
+    fn tag_completed(&self, tag: u64, cqe: &C) -> Vec<std::task::Waker> {
+        self.tag_waiters.borrow_mut().complete(tag, cqe)
+    }
+
+    fn task_waker(&self) -> Option<&RefCell<TaskWaker>> {
+        self.task_waker.as_ref()
+    }
+
+    fn completed(&self, tag: Option<u64>, seq: Option<u64>, (opcode, pushed): (u8, std::time::Instant), submitted: Option<std::time::Instant>, cqe: &C) {
+        if let Some(submitted) = submitted {
+            self.record(|stats| {
+                stats.latency_samples += 1;
+                stats.sq_time += submitted.saturating_duration_since(pushed);
+                stats.kernel_time += submitted.elapsed();
+            });
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            opcode,
+            user_data = cqe.user_data(),
+            tag,
+            seq,
+            result = cqe.result(),
+            latency_us = pushed.elapsed().as_micros() as u64,
+            "io_uring op completed",
+        );
+        if let Some(hook) = &self.on_completion {
+            hook(&trace::CompletionEvent {
+                opcode,
+                user_data: cqe.user_data(),
+                tag,
+                seq,
+                result: cqe.result(),
+                latency: pushed.elapsed(),
+            });
+        }
+    }
+}
+
+// Work that dispatch_completions performs once the slab is no longer
+// borrowed, in the order that it was queued.
+enum Deferred<C: cqueue::Entry> {
+    Completed(Option<u64>, Option<u64>, (u8, std::time::Instant), Option<std::time::Instant>, C),
+    Unexpected(C),
+    Wake(std::task::Waker),
+    // An Op waiting on the shared waker of a single-task ring has completed.
+    WakeTask,
+    Callback(Box<dyn FnOnce(C)>, C),
+}
+
+// Route every available completion queue entry to the Op that it belongs to.
+// Returns the number of completion queue entries that were consumed.
+//
+// Hooks, wakers and callbacks are only run once every available entry has been
+// routed and the slab is no longer borrowed, so that they may push further Ops
+// or poll Ops re-entrantly.
+fn dispatch_completions<C: cqueue::Entry>(uring: &dyn Driver<C>, slab: &RefCell<Slots<C>>) -> usize {
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!("io_uring_complete", completions = tracing::field::Empty).entered();
+    let mut guard = slab.borrow_mut();
+    let mut count = 0;
+    let mut deferred = Vec::new();
+    for (user_data, submitted) in uring.take_submitted() {
+        if let Some(slot) = usize::try_from(user_data).ok().and_then(|index| guard.get_mut(index)) {
+            slot.submitted.get_or_insert(submitted);
+        }
+    }
+    while let Some(cqe) = uring.next_completion() {
+        count += 1;
+        // Direct descriptors sent by another ring with msg_ring_fd carry a
+        // tagged user_data rather than a slab index.
+        if cqe.user_data() & files::MSG_RING_FD != 0 {
+            deferred.extend(uring.fd_received(&cqe).into_iter().map(Deferred::Wake));
+            continue;
+        }
+        // A completion queue entry may not belong to any Op, for example if the
+        // entry was pushed onto the ring by a foreign submitter. It is counted
+        // and discarded rather than indexing into a vacant slot.
+        let index = usize::try_from(cqe.user_data()).ok().filter(|index| guard.contains(*index));
+        let Some(index) = index else {
+            uring.orphan_completion(&cqe);
+            continue;
+        };
+        if let Lifecycle::Skipped { .. } = guard[index].lifecycle {
+            let Lifecycle::Skipped { rest, canceled, .. } = guard.remove(index).lifecycle else { unreachable!() };
+            // Unless an earlier Op of the chain failed, in which case every Op
+            // that follows receives a completion of its own, the skipped entry
+            // failed, and the kernel canceled the rest of its chain without
+            // posting completion queue entries for it.
+            if !canceled {
+                for index in rest {
+                    complete_skipped_chain(&mut guard, index, &cqe, &mut deferred);
+                }
+            }
+            continue;
+        }
+        free_skipped(&mut guard, index);
+        if cqe.result() < 0 {
+            cancel_skipped(&mut guard, index);
+        }
+        let slot = &mut guard[index];
+        if let Some(tag) = slot.tag {
+            deferred.extend(uring.tag_completed(tag, &cqe).into_iter().map(Deferred::Wake));
+        }
+        if let Some(pushed) = slot.pushed {
+            deferred.push(Deferred::Completed(slot.tag, slot.seq(), pushed, slot.submitted.take(), cqe.clone()));
+        }
+        let lifecycle = &mut slot.lifecycle;
+        match lifecycle {
+            Lifecycle::Submitted => {
+                *lifecycle = Lifecycle::Completed(cqe);
+                guard.completed += 1;
+            }
+            Lifecycle::Waiting(_) => {
+                let Lifecycle::Waiting(waker) = std::mem::replace(lifecycle, Lifecycle::Completed(cqe)) else { unreachable!() };
+                guard.completed += 1;
+                deferred.push(Deferred::Wake(waker));
+            }
+            Lifecycle::WaitingTask => {
+                *lifecycle = Lifecycle::Completed(cqe);
+                guard.completed += 1;
+                deferred.push(Deferred::WakeTask);
+            }
+            Lifecycle::Completed(_) => {
+                deferred.push(Deferred::Unexpected(cqe));
+            }
+            Lifecycle::Multishot(shots) => {
+                let was_done = shots.done;
+                let remove = shots.receive(cqe);
+                let waker = if remove { None } else { shots.waker.take() };
+                if !was_done && shots.done {
+                    guard.completed += 1;
+                }
+                if remove {
+                    guard.remove(index);
+                } else if let Some(waker) = waker {
+                    deferred.push(Deferred::Wake(waker));
+                }
+            }
+            Lifecycle::Callback(_) => {
+                let Lifecycle::Callback(callback) = guard.remove(index).lifecycle else { unreachable!() };
+                deferred.push(Deferred::Callback(callback, cqe));
+            }
+            Lifecycle::Skipped { .. } => unreachable!(),
+        }
+    }
+    drop(guard);
+    #[cfg(feature = "tracing")]
+    span.record("completions", count);
+    let mut wake_task = false;
+    for work in deferred {
+        match work {
+            Deferred::Completed(tag, seq, pushed, submitted, cqe) => uring.completed(tag, seq, pushed, submitted, &cqe),
+            Deferred::Unexpected(cqe) => uring.unexpected_completion(&cqe),
+            Deferred::Wake(waker) => waker.wake(),
+            Deferred::WakeTask => {
+                uring.task_waker().unwrap().borrow_mut().waiting -= 1;
+                wake_task = true;
+            }
+            Deferred::Callback(callback, cqe) => callback(cqe),
+        }
+    }
+    // The shared waker is woken once however many of its Ops completed. It is
+    // cloned so that the task may poll Ops re-entrantly while it is woken.
+    if wake_task {
+        let waker = uring.task_waker().unwrap().borrow().waker.clone();
+        waker.unwrap().wake();
+    }
+    uring.completions_dispatched();
+    count
+}
+
+// Free the skipped entries of a link chain that precede the Op at `index`, which
+// has completed, so they must have succeeded.
+fn free_skipped<C: cqueue::Entry>(guard: &mut Slots<C>, index: usize) {
+    for skipped in std::mem::take(&mut guard[index].skipped) {
+        if matches!(guard.get(skipped).map(|slot| &slot.lifecycle), Some(Lifecycle::Skipped { canceled: false, .. })) {
+            guard.remove(skipped);
+        }
+    }
+}
+
+// Mark the skipped entries of a link chain that follow the Op at `index`, which
+// has failed, as canceled, so that they are freed by their own completions.
+fn cancel_skipped<C: cqueue::Entry>(guard: &mut Slots<C>, index: usize) {
+    for skipped in std::mem::take(&mut guard[index].skipped_after) {
+        if let Some(Lifecycle::Skipped { canceled, .. }) = guard.get_mut(skipped).map(|slot| &mut slot.lifecycle) {
+            *canceled = true;
+        }
+    }
+}
+
+// Complete the Op at `index`, which follows a skipped entry that failed in a link
+// chain, with the failed entry's completion queue entry.
+fn complete_skipped_chain<C: cqueue::Entry>(guard: &mut Slots<C>, index: usize, cqe: &C, deferred: &mut Vec<Deferred<C>>) {
+    free_skipped(guard, index);
+    let lifecycle = &mut guard[index].lifecycle;
+    match std::mem::replace(lifecycle, Lifecycle::Completed(cqe.clone())) {
+        Lifecycle::Submitted => guard.completed += 1,
+        Lifecycle::Waiting(waker) => {
+            guard.completed += 1;
+            deferred.push(Deferred::Wake(waker));
+        }
+        Lifecycle::WaitingTask => {
+            guard.completed += 1;
+            deferred.push(Deferred::WakeTask);
+        }
+        Lifecycle::Callback(callback) => {
+            guard.completed += 1;
+            guard.remove(index);
+            deferred.push(Deferred::Callback(callback, cqe.clone()));
+        }
+        other => *lifecycle = other,
+    }
+}
+
+// Unregisters the eventfd of a DEFER_TASKRUN listen loop when the loop returns
+// or is dropped, so that a later listen loop can register its own.
+struct EventfdRegistration<'a, S: squeue::Entry, C: cqueue::Entry>(&'a IoUring<S, C>);
+
+impl<S: squeue::Entry, C: cqueue::Entry> Drop for EventfdRegistration<'_, S, C> {
+    fn drop(&mut self) {
+        self.0.submitter().unregister_eventfd().ok();
+    }
+}
+
+// The user_data of entries pushed with push_raw_nowait, which is never a slab
+// index, and does not carry the MSG_RING_FD bit.
+const RAW_NOWAIT: u64 = 1 << 62;
+
+pub struct IoUringAsync<S: squeue::Entry = io_uring::squeue::Entry, C: cqueue::Entry = io_uring::cqueue::Entry> {
+    uring: Rc<Ring<S, C>>,
+    slab: Rc<RefCell<Slots<C>>>,
+    // The opcodes supported by the kernel, if it supports probing.
+    probe: Option<io_uring::Probe>,
+    readiness_fallback: bool,
+    // Shared with the Ops that free slots once they complete, such as the close
+    // callbacks of dropped FixedFiles.
+    file_slots: Rc<RefCell<files::FileSlots>>,
+    // The buffers registered with the kernel, indexed by buffer index.
+    fixed_buffers: RefCell<Vec<libc::iovec>>,
+    completion_coalesce: std::time::Duration,
+    // Completion work is deferred until the ring is entered with GETEVENTS.
+    defer_taskrun: bool,
+    // The kernel flags pending completion work with IORING_SQ_TASKRUN.
+    taskrun_flag: bool,
+    // The number of Ops in flight at which try_push rejects further entries.
+    max_in_flight: Cell<usize>,
+    // The limit follows the size of the completion queue, since it was not set
+    // with Builder::max_in_flight and the kernel drops completions that overflow.
+    max_in_flight_follows_cq: bool,
+    // The RWF_* flags applied to reads and writes that do not override them.
+    rw_flags: io_uring::types::RwFlags,
+    // The submit sequence number of the next Op to be pushed.
+    #[cfg(feature = "op-sequence")]
+    next_seq: Cell<u64>,
+}
+
+/// The io_uring file descriptor, which becomes readable when completion queue
+/// entries are available. This allows the ring to be driven by a reactor other
+/// than tokio, such as an existing epoll loop: whenever the file descriptor is
+/// readable, call [`IoUringAsync::process_completions`].
+impl<S: squeue::Entry, C: cqueue::Entry> AsRawFd for IoUringAsync<S, C> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.uring.inner.as_raw_fd()
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> std::fmt::Debug for IoUringAsync<S, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("IoUringAsync");
+        match self.slab.try_borrow() {
+            Ok(guard) => debug.field("in_flight", &guard.iter().filter(|(_, slot)| !slot.is_completed()).count()),
+            Err(_) => debug.field("in_flight", &format_args!("<borrowed>")),
+        };
+        debug
+            .field("unsubmitted", &self.unsubmitted())
+            .field("sq_entries", &self.params().sq_entries())
+            .field("cq_entries", &self.params().cq_entries())
+            .finish()
+    }
+}
+
+impl IoUringAsync<io_uring::squeue::Entry, io_uring::cqueue::Entry> {
+    pub fn new(entries: u32) -> std::io::Result<Self> {
+        Self::builder().build(entries)
+    }
+
+    /// Create a [`Builder`](builder::Builder) for an `IoUringAsync` instance.
+    pub fn builder() -> builder::Builder {
+        builder::Builder::new()
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    
+    pub async fn listen(uring: Rc<IoUringAsync<S, C>>) {
+        if uring.defer_taskrun {
+            return Self::listen_deferred(uring).await;
+        }
+        let coalesce = uring.completion_coalesce;
+        let async_fd = AsyncFd::new(uring).unwrap();
+        loop {
+            let Some(guard) = async_fd.get_ref().until_stopped(async_fd.readable()).await else {
+                return;
+            };
+            let mut guard = guard.unwrap();
+            if !coalesce.is_zero() {
+                tokio::time::sleep(coalesce).await;
+            }
+            // The completion queue is drained until it is empty before and after
+            // readiness is cleared, so that completions posted while it was being
+            // drained are not stranded until the next notification. Anything
+            // posted after the final check makes the ring readable again.
+            loop {
+                while guard.get_inner().process_completions() > 0 {}
+                guard.clear_ready();
+                if !guard.get_inner().needs_completion_processing() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // The listen loop of a DEFER_TASKRUN ring. Deferred completion work does
+    // not make the ring's file descriptor readable, but does signal a registered
+    // eventfd, so the loop waits on an eventfd instead. Only one eventfd can be
+    // registered, so if another listen loop is running, this one returns at once.
+    async fn listen_deferred(uring: Rc<IoUringAsync<S, C>>) {
+        if uring.uring.listen_stopped.get() {
+            return;
+        }
+        if let Err(_err) = Self::listen_eventfd(&uring).await {
+            #[cfg(feature = "tracing")]
+            tracing::error!(error = %_err, "io_uring listen loop failed to register an eventfd");
+        }
+    }
+
+    async fn listen_eventfd(uring: &IoUringAsync<S, C>) -> std::io::Result<()> {
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if eventfd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let eventfd = unsafe { std::os::fd::OwnedFd::from_raw_fd(eventfd) };
+        let async_fd = AsyncFd::new(eventfd)?;
+        uring.uring.inner.submitter().register_eventfd(async_fd.as_raw_fd())?;
+        let _registration = EventfdRegistration(&uring.uring.inner);
+        loop {
+            // Completion work deferred before the eventfd was registered, or
+            // while completions were being processed, is run before waiting.
+            uring.handle_cqe();
+            let Some(guard) = uring.until_stopped(async_fd.readable()).await else {
+                return Ok(());
+            };
+            let mut guard = guard?;
+            let mut count = 0u64;
+            unsafe { libc::read(guard.get_inner().as_raw_fd(), (&mut count as *mut u64).cast(), 8) };
+            if !uring.completion_coalesce.is_zero() {
+                tokio::time::sleep(uring.completion_coalesce).await;
+            }
+            guard.clear_ready();
+        }
+    }
+
+    // Wait for `ready`, unless the listen loop is stopped by shutdown first.
+    async fn until_stopped<T>(&self, ready: impl Future<Output = T>) -> Option<T> {
+        let mut ready = std::pin::pin!(ready);
+        std::future::poll_fn(|cx| {
+            if self.uring.listen_stopped.get() {
+                return std::task::Poll::Ready(None);
+            }
+            if self.uring.listen_kicked.replace(false) {
+                self.process_completions();
+            }
+            let mut wakers = self.uring.listen_wakers.borrow_mut();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            drop(wakers);
+            ready.as_mut().poll(cx).map(Some)
+        }).await
+    }
+
+    /// Make the listen loop process the available completion queue entries the
+    /// next time that it runs, rather than once the ring becomes readable, which
+    /// the runtime may only notice after it next polls for I/O. The loop does a
+    /// single pass for each kick, and then waits as before, so Ops that complete
+    /// after the pass are woken by the next notification as usual. Unlike
+    /// [`IoUringAsync::process_completions`], the entries are processed on the
+    /// listen loop's task rather than the caller's.
+    pub fn kick(&self) {
+        self.uring.listen_kicked.set(true);
+        for waker in self.uring.listen_wakers.borrow().iter() {
+            waker.wake_by_ref();
+        }
+    }
+
+    pub fn generic_new(entries: u32) -> std::io::Result<Self> {
+        Self::generic_builder().build(entries)
+    }
+
+    /// Create a [`Builder`](builder::Builder) for an `IoUringAsync` instance with
+    /// any combination of submission and completion queue entry types.
+    pub fn generic_builder() -> builder::Builder<S, C> {
+        builder::Builder::new()
+    }
+
+    /// Returns whether the kernel supports the given opcode, such as
+    /// `io_uring::opcode::Accept::CODE`. Kernels that do not support probing
+    /// (older than 5.6) are reported as supporting no opcodes.
+    pub fn is_supported(&self, opcode: u8) -> bool {
+        self.probe.as_ref().is_some_and(|probe| probe.is_supported(opcode))
+    }
+
+    /// Returns the `RWF_*` flags applied to reads and writes by default, as set
+    /// with [`Builder::rw_flags`](builder::Builder::rw_flags).
+    pub fn default_rw_flags(&self) -> io_uring::types::RwFlags {
+        self.rw_flags
+    }
+
+    // Returns whether a helper should fall back to a readiness-based
+    // implementation of the given opcode.
+    pub(crate) fn use_fallback(&self, opcode: u8) -> bool {
+        self.readiness_fallback && !self.is_supported(opcode)
+    }
+
+    pub fn push(&self, entry: impl Into<S>) -> Op<C> {
+        self.push_slot(entry.into(), None)
+    }
+
+    /// Push an entry unless the number of Ops in flight has reached the limit set
+    /// with [`Builder::max_in_flight`](builder::Builder::max_in_flight), which
+    /// defaults to the size of the completion queue on kernels that would drop
+    /// completions that overflow it, and to no limit otherwise. Returns a
+    /// `WouldBlock` error rather than pushing the entry if it has, so that
+    /// callers can apply backpressure instead of risking lost completions.
+    pub fn try_push(&self, entry: impl Into<S>) -> std::io::Result<Op<C>> {
+        if self.in_flight() >= self.max_in_flight.get() {
+            return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "too many Ops in flight"));
+        }
+        Ok(self.push(entry))
+    }
+
+    /// Push an entry that is associated with an opaque `tag`, such as a trace
+    /// span identifier. The tag is distinct from the entry's user_data, which is
+    /// reserved for this crate, and can be read back with [`Op::tag`].
+    pub fn push_tagged(&self, entry: impl Into<S>, tag: u64) -> Op<C> {
+        self.push_slot(entry.into(), Some(tag))
+    }
+
+    /// Push an entry whose completion is handled by `callback` rather than by
+    /// awaiting an Op. The callback is run with the completion queue entry when
+    /// it is processed, which is on the task running [`IoUringAsync::listen`],
+    /// or within [`IoUringAsync::process_completions`]. This avoids the
+    /// overhead of a future for fire-and-forget operations that still need to
+    /// observe their result, such as logging a failed close.
+    ///
+    /// The callback is dropped without being run if the ring is dropped first.
+    pub fn push_with(&self, entry: impl Into<S>, callback: impl FnOnce(C) + 'static) {
+        self.push_callback(entry.into(), None, Box::new(callback));
+    }
+
+    /// Push an entry whose completion is handled by `callback`, as with
+    /// [`IoUringAsync::push_with`], that is associated with an opaque `tag`.
+    /// Its completion can be awaited with [`IoUringAsync::await_tag`].
+    pub fn push_with_tagged(&self, entry: impl Into<S>, tag: u64, callback: impl FnOnce(C) + 'static) {
+        self.push_callback(entry.into(), Some(tag), Box::new(callback));
+    }
+
+    fn push_callback(&self, entry: S, tag: Option<u64>, callback: Box<dyn FnOnce(C)>) {
+        let mut guard = self.slab.borrow_mut();
+        let mut slot = self.slot(&entry, tag);
+        slot.lifecycle = Lifecycle::Callback(callback);
+        let index = guard.insert(slot);
+        let entry = entry.user_data(index.try_into().unwrap());
+        self.uring.push_multiple(std::slice::from_ref(&entry)).unwrap();
+    }
+
+    /// Push an entry as it is, without an Op and keeping its user_data, for
+    /// callers that dispatch completions themselves with
+    /// [`IoUringAsync::drain_completions_into`]. The user_data should not
+    /// collide with the index of an Op in flight, since
+    /// [`IoUringAsync::listen`] would route the completion to that Op.
+    pub fn push_unmanaged(&self, entry: impl Into<S>) -> std::io::Result<()> {
+        self.uring.push_multiple(std::slice::from_ref(&entry.into()))
+    }
+
+    /// Push an entry flagged with `CQE_SKIP_SUCCESS`, without an Op or a slot to
+    /// route its completion to, so that it costs no more than pushing onto the
+    /// raw io_uring. This is meant for microbenchmarks that measure the overhead
+    /// of Ops, and for fire-and-forget entries whose result is genuinely ignored.
+    /// A successful entry posts no completion queue entry. A failed one is not
+    /// routed anywhere, and is counted in
+    /// [`RingStats::orphan_completions`](stats::RingStats::orphan_completions).
+    /// Requires Linux 5.17, without which every such entry fails with `EINVAL`.
+    pub fn push_raw_nowait(&self, entry: impl Into<S>) -> std::io::Result<()> {
+        let entry = entry.into().flags(io_uring::squeue::Flags::SKIP_SUCCESS).user_data(RAW_NOWAIT);
+        self.uring.push_multiple(std::slice::from_ref(&entry))
+    }
+
+    fn push_slot(&self, entry: S, tag: Option<u64>) -> Op<C> {
+        let mut guard = self.slab.borrow_mut();
+        let index = guard.insert(self.slot(&entry, tag));
+        let entry = entry.user_data(index.try_into().unwrap());
+        self.uring.push_multiple(std::slice::from_ref(&entry)).unwrap();
+        self.op(index)
+    }
+
+    // Push the entries of a link chain onto the submission queue such that they
+    // are submitted to the kernel together, since a chain cannot span multiple
+    // submissions, attaching resources to the slot of each entry. Entries
+    // flagged in `skipped` get a slot that tracks the Ops after them in the
+    // chain rather than an Op, and Ops are only returned for the other entries.
+    pub(crate) fn push_chain(&self, entries: Vec<S>, resources: Vec<Option<Box<dyn std::any::Any>>>, skipped: Vec<bool>) -> Vec<Op<C>> {
+        assert!(entries.len() <= self.uring.inner.params().sq_entries() as usize, "group is larger than the submission queue");
+        assert!(!skipped.last().unwrap_or(&false), "the last entry of a chain must post its completion");
+        let mut guard = self.slab.borrow_mut();
+        let mut ops = Vec::with_capacity(entries.len());
+        // The skipped entries so far, those since the last Op, and the Ops so
+        // far.
+        let mut skips = Vec::new();
+        let mut preceding = Vec::new();
+        let mut chained = Vec::new();
+        let entries: Vec<S> = entries.into_iter().zip(resources).zip(skipped).map(|((entry, resources), skip)| {
+            let mut slot = self.slot(&entry, None);
+            if skip {
+                slot.lifecycle = Lifecycle::Skipped { rest: Vec::new(), canceled: false, _resources: resources };
+                let index = guard.insert(slot);
+                skips.push(index);
+                preceding.push(index);
+                for &op in &chained {
+                    guard[op].skipped_after.push(index);
+                }
+                return entry.user_data(index.try_into().unwrap());
+            }
+            slot.skipped = std::mem::take(&mut preceding);
+            let index = guard.insert(slot);
+            for &skip in &skips {
+                let Lifecycle::Skipped { rest, .. } = &mut guard[skip].lifecycle else { unreachable!() };
+                rest.push(index);
+            }
+            chained.push(index);
+            let mut op = self.op(index);
+            op.inner.as_mut().unwrap().resources = resources;
+            ops.push(op);
+            entry.user_data(index.try_into().unwrap())
+        }).collect();
+        self.uring.push_multiple(&entries).unwrap();
+        ops
+    }
+
+    // Create the slab entry for an entry that is about to be pushed.
+    fn slot(&self, entry: &S, tag: Option<u64>) -> Slot<C> {
+        let mut slot = Slot::new(tag);
+        slot.fixed = squeue::fixed_resources_of(entry);
+        self.stamp(&mut slot);
+        if self.uring.on_completion.is_some() || self.uring.submit_times.is_some() || cfg!(feature = "tracing") {
+            slot.pushed = Some((entry.opcode(), std::time::Instant::now()));
+        }
+        slot
+    }
+
+    // Stamp the slot of an Op that is being pushed with the next submit
+    // sequence number, if they are recorded.
+    fn stamp(&self, _slot: &mut Slot<C>) {
+        #[cfg(feature = "op-sequence")]
+        {
+            _slot.seq = self.next_seq.get();
+            self.next_seq.set(_slot.seq + 1);
+        }
+    }
+
+    fn op(&self, index: usize) -> Op<C> {
+        Op {
+            inner: Some(OpInner {
+                uring: self.uring.clone(),
+                slab: self.slab.clone(),
+                index,
+                resources: None,
+            })
+        }
+    }
+
+    pub fn handle_cqe(&self) {
+        self.process_completions();
+    }
+
+    /// Wake the Ops of every available completion queue entry, returning the
+    /// number of entries that were processed. This is the non-async counterpart
+    /// of [`IoUringAsync::listen`] for driving the ring from an external reactor.
+    /// It never blocks, and should be called whenever the ring's file descriptor
+    /// is readable. For a ring built with
+    /// [`Builder::defer_taskrun`](builder::Builder::defer_taskrun), deferred
+    /// completion work does not make the file descriptor readable, so an eventfd
+    /// should be registered and polled instead.
+    pub fn process_completions(&self) -> usize {
+        if self.defer_taskrun || self.taskrun_pending() {
+            self.run_task_work();
+        }
+        dispatch_completions(&*self.uring, &self.slab)
+    }
+
+    /// Submit queued entries and block the current thread until at least `min`
+    /// completion queue entries are available, then process every available
+    /// entry in a single pass, returning the number processed. This is a
+    /// synchronous batch driver: waking once per batch rather than once per
+    /// completion amortizes the cost of entering the kernel. `min` should not
+    /// exceed the number of Ops in flight, or this blocks forever; see
+    /// [`IoUringAsync::submit_and_wait_timeout`] to bound the wait.
+    pub fn submit_and_wait_at_least(&self, min: usize) -> std::io::Result<usize> {
+        self.uring.submit_and_wait(min)?;
+        Ok(self.process_completions())
+    }
+
+    /// Like [`IoUringAsync::submit_and_wait_at_least`], but block for no longer
+    /// than `timeout`, after which the entries that are available, which may be
+    /// fewer than `min`, are processed. Requires Linux 5.11.
+    pub fn submit_and_wait_timeout(&self, min: usize, timeout: std::time::Duration) -> std::io::Result<usize> {
+        self.uring.submit_and_wait_timeout(min, timeout)?;
+        Ok(self.process_completions())
+    }
+
+    /// Move every available completion queue entry into `buf` in one pass,
+    /// returning the number of entries that were moved. The entries are appended
+    /// to `buf`, so that its allocation can be reused across calls.
+    ///
+    /// This bypasses the routing of completions to Ops, for callers that manage
+    /// user_data themselves, such as with [`IoUringAsync::push_unmanaged`], and
+    /// process completions in bulk. Ops whose completions are drained this way
+    /// are never woken, so a ring should not mix the two unless the caller
+    /// routes those completions itself.
+    pub fn drain_completions_into(&self, buf: &mut Vec<C>) -> usize {
+        if self.defer_taskrun || self.taskrun_pending() {
+            self.run_task_work();
+        }
+        let len = buf.len();
+        buf.extend(unsafe { self.uring.inner.completion_shared() });
+        buf.len() - len
+    }
+
+    // Enter the ring with IORING_ENTER_GETEVENTS without waiting, which runs the
+    // completion work that the kernel deferred for a DEFER_TASKRUN ring. If this
+    // fails, the work is run by the next successful enter.
+    fn run_task_work(&self) {
+        const IORING_ENTER_GETEVENTS: u32 = 1;
+        unsafe { self.uring.inner.submitter().enter::<libc::sigset_t>(0, 0, IORING_ENTER_GETEVENTS, None) }.ok();
+    }
+
+    /// Submit all queued submission queue events to the kernel. If nothing is
+    /// queued, this returns `Ok(0)` without entering the kernel, so it is cheap
+    /// to call from an `on_thread_park` hook.
+    pub fn submit(&self) -> std::io::Result<usize> {
+        self.uring.submit()
+    }
+
+    /// Returns the parameters reported by the kernel when the ring was set up,
+    /// including the actual submission and completion queue sizes, which may
+    /// differ from the requested number of entries, and the supported features.
+    pub fn params(&self) -> &io_uring::Parameters {
+        self.uring.inner.params()
+    }
+
+    /// Resize the completion queue to `entries` entries, rounded up to a power of
+    /// two, so that a ring under overflow pressure can buffer more completions
+    /// without being torn down. On success, the default limit of
+    /// [`Builder::max_in_flight`](builder::Builder::max_in_flight) follows the
+    /// new size.
+    ///
+    /// Resizing registers new rings with `IORING_REGISTER_RESIZE_RINGS`
+    /// (Linux 6.13), after which the queues must be mapped again. The io_uring
+    /// crate maps the queues only once, so this returns an `Unsupported` error
+    /// unless the queue already has the requested size. Size the queue for the
+    /// peak load with [`Builder::cq_entries`](builder::Builder::cq_entries)
+    /// instead. Returns an `InvalidInput` error if `entries` is zero or cannot
+    /// be rounded up.
+    pub fn resize_cq(&self, entries: u32) -> std::io::Result<()> {
+        let Some(entries) = entries.checked_next_power_of_two().filter(|_| entries > 0) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid completion queue size {}", entries),
+            ));
+        };
+        if entries != self.params().cq_entries() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "resizing the completion queue requires mapping the rings again, which the io_uring crate does not support, size it with Builder::cq_entries instead",
+            ));
+        }
+        if self.max_in_flight_follows_cq {
+            self.max_in_flight.set(entries as usize);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of entries that can be pushed before the submission
+    /// queue is full. Pushing onto a full queue submits the queued entries to
+    /// make room, so this allows callers to apply backpressure instead.
+    pub fn sq_space_left(&self) -> usize {
+        let sq = unsafe { self.uring.inner.submission_shared() };
+        sq.capacity() - sq.len()
+    }
+
+    /// Returns the number of completion queue entries that are waiting to be
+    /// processed.
+    pub fn cq_ready(&self) -> usize {
+        unsafe { self.uring.inner.completion_shared() }.len()
+    }
+
+    /// Returns whether there is anything for [`IoUringAsync::process_completions`]
+    /// to do, which is the case if completion queue entries are available, or if
+    /// the kernel has flagged pending completion work with `IORING_SQ_TASKRUN`.
+    /// The flag is only set for a ring built with
+    /// [`Builder::taskrun_flag`](builder::Builder::taskrun_flag), and tells
+    /// without a syscall when the ring must be entered to run that work.
+    pub fn needs_completion_processing(&self) -> bool {
+        self.cq_ready() > 0 || self.taskrun_pending()
+    }
+
+    // Returns whether the kernel has flagged completion work that is run by
+    // entering the ring.
+    fn taskrun_pending(&self) -> bool {
+        self.taskrun_flag && unsafe { self.uring.inner.submission_shared() }.taskrun()
+    }
+
+    /// Returns the number of entries that have been pushed but not yet submitted
+    /// to the kernel.
+    pub fn unsubmitted(&self) -> usize {
+        self.uring.unsubmitted.get()
+    }
+
+    /// Spin up io-wq worker threads ahead of real traffic by pushing `n` Nops with
+    /// `IOSQE_ASYNC`, which forces each of them to be punted to a worker, and
+    /// waiting for all of them to complete. This avoids a latency spike on the
+    /// first blocking operations. How many workers are kept alive, and for how
+    /// long, depends on the kernel version, so the benefit varies.
+    ///
+    /// The Nops are submitted immediately.
+    pub async fn warmup(&self, n: usize) {
+        let ops: Vec<_> = (0..n)
+            .map(|_| self.push(io_uring::opcode::Nop::new().build().flags(io_uring::squeue::Flags::ASYNC)))
+            .collect();
+        self.submit().ok();
+        for op in ops {
+            op.await;
+        }
+    }
+
+    /// Returns the number of Ops that have been pushed but have not yet completed.
+    pub fn in_flight(&self) -> usize {
+        self.slab.borrow().in_flight()
+    }
+
+    /// Returns the index and state of every Op in the ring, including Ops that
+    /// have completed but have not yet been consumed, for diagnosing futures that
+    /// never complete. The index is the user_data of the Op's submission queue
+    /// entry. This is a best-effort snapshot: it is empty if the Ops are being
+    /// accessed, for example when called from a completion callback.
+    pub fn debug_pending(&self) -> Vec<(usize, OpState)> {
+        match self.slab.try_borrow() {
+            Ok(guard) => guard.iter().map(|(index, slot)| (index, slot.lifecycle.state())).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the same snapshot as [`IoUringAsync::debug_pending`], along with
+    /// the submit sequence number of every Op. Ops are numbered from zero in
+    /// the order that they were pushed, so the numbers can be compared with the
+    /// order that completions arrive in, or with [`trace::CompletionEvent::seq`].
+    /// Requires the `op-sequence` feature.
+    #[cfg(feature = "op-sequence")]
+    pub fn debug_pending_seq(&self) -> Vec<(usize, u64, OpState)> {
+        match self.slab.try_borrow() {
+            Ok(guard) => guard.iter().map(|(index, slot)| (index, slot.seq, slot.lifecycle.state())).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Push an AsyncCancel request targeting the Op with the given slab index.
+    pub(crate) fn push_cancel_index(&self, index: usize) -> Op<C> {
+        self.push(AsyncCancel::new(index.try_into().unwrap()).build())
+    }
+
+    /// Returns a snapshot of the statistics that have been collected for this ring.
+    pub fn stats(&self) -> stats::RingStats {
+        self.uring.stats.get()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::future::Future;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use io_uring::opcode::Nop;
+    use super::{cqueue, squeue, Driver, IoUringAsync, OpState};
+    use send_wrapper::SendWrapper;
+
+    // Run a future to completion on a current_thread runtime that submits all
+    // outstanding submission queue entries whenever the executor goes idle.
+    pub(crate) fn run<S: squeue::Entry, C: cqueue::Entry, F: Future>(uring: &Rc<IoUringAsync<S, C>>, fut: F) -> F::Output {
+        let uring_clone = SendWrapper::new(uring.clone());
+        let runtime = tokio::runtime::Builder::new_current_thread().
+            on_thread_park(move || { uring_clone.submit().unwrap(); }).
+            enable_all().
+            build().unwrap();
+
+        runtime.block_on(tokio::task::LocalSet::new().run_until(async {
+            tokio::task::spawn_local(IoUringAsync::listen(uring.clone()));
+            fut.await
+        }))
+    }
+
+    #[test]
+    fn kick() {
+        // Yield to the LocalSet's other tasks without polling for I/O.
+        async fn yield_local() {
+            let mut yielded = false;
+            std::future::poll_fn(|cx| {
+                if std::mem::replace(&mut yielded, true) {
+                    return std::task::Poll::Ready(());
+                }
+                cx.waker().wake_by_ref();
+                std::task::Poll::Pending
+            }).await
+        }
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(tokio::task::LocalSet::new().run_until(async {
+            tokio::task::spawn_local(IoUringAsync::listen(uring.clone()));
+            tokio::task::yield_now().await;
+
+            let op = uring.push(Nop::new().build());
+            uring.submit().unwrap();
+            while uring.cq_ready() == 0 {}
+            uring.kick();
+            yield_local().await;
+            assert!(op.is_completed());
+            op.await;
+        }));
+    }
+
+    #[test]
+    fn example1() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();  
+
+        runtime.block_on(async move {
+            tokio::task::LocalSet::new().run_until(async {
+                tokio::task::spawn_local(IoUringAsync::listen(uring.clone()));
+
+                let fut1 = uring.push(Nop::new().build());
+                let fut2 = uring.push(Nop::new().build());
+                
+                uring.submit().unwrap();
+                
+                let cqe1 = fut1.await;
+                let cqe2 = fut2.await;
+
+                assert!(cqe1.result() >= 0, "nop error: {}", cqe1.result()); 
+                assert!(cqe2.result() >= 0, "nop error: {}", cqe2.result()); 
+            }).await; 
+        });
+    }
+
+    #[test]
+    fn example2() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let uring = Rc::new(uring);
+
+        // Create a new current_thread runtime that submits all outstanding submission queue
+        // entries as soon as the executor goes idle.
+        let uring_clone = SendWrapper::new(uring.clone());
+        let runtime = tokio::runtime::Builder::new_current_thread().
+            on_thread_park(move || { uring_clone.submit().unwrap(); }).
+            enable_all().
+            build().unwrap();  
+
+        runtime.block_on(async move {
+            tokio::task::LocalSet::new().run_until(async {
+                tokio::task::spawn_local(IoUringAsync::listen(uring.clone()));
+
+                let cqe = uring.push(Nop::new().build()).await;
+                assert!(cqe.result() >= 0, "nop error: {}", cqe.result()); 
+            }).await; 
+        });
+    }
+
+    #[test]
+    fn push_tagged() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        run(&uring, async {
+            let tagged = uring.push_tagged(Nop::new().build(), 42);
+            let untagged = uring.push(Nop::new().build());
+            assert_eq!(tagged.tag(), Some(42));
+            assert_eq!(untagged.tag(), None);
+            tagged.await;
+            untagged.await;
+        });
+    }
+
+    #[test]
+    fn cancel() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (rx, _tx) = crate::fd::pipe().unwrap();
+        let mut buf = [0u8; 1];
+        run(&uring, async {
+            let entry = io_uring::opcode::Read::new(io_uring::types::Fd(rx.as_raw_fd()), buf.as_mut_ptr(), 1).build();
+            let op = uring.push(entry);
+            uring.submit().unwrap();
+            op.cancel().await.unwrap();
+            assert!(uring.slab.borrow().is_empty());
+
+            // An Op that already completed is not an error.
+            let op = uring.push(Nop::new().build());
+            uring.drain().await;
+            assert!(op.is_completed());
+            op.cancel().await.unwrap();
+            assert!(uring.slab.borrow().is_empty());
+
+            // Nor is one that completes before the cancel request finds it.
+            uring.push(Nop::new().build()).cancel().await.unwrap();
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn drop_without_local_set() {
+        let uring = IoUringAsync::new(8).unwrap();
+        drop(uring.push(Nop::new().build()));
+        assert!(uring.slab.borrow().is_empty());
+
+        // A runtime without a LocalSet must not cause the drop to panic either.
+        // The Op is reaped once its completion is processed.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            drop(uring.push(Nop::new().build()));
+        });
+        assert_eq!(uring.debug_pending()[0].1, OpState::Callback);
+        uring.submit_and_wait_at_least(1).unwrap();
+        assert!(uring.slab.borrow().is_empty());
+    }
+
+    #[test]
+    fn debug() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut op = uring.push(Nop::new().build());
+        assert_eq!(format!("{:?}", op), "Op { index: 0, lifecycle: Submitted }");
+        assert_eq!(format!("{:?}", uring), "IoUringAsync { in_flight: 1, unsubmitted: 1, sq_entries: 8, cq_entries: 16 }");
+
+        // Formatting must not panic while the slab is borrowed.
+        let guard = uring.slab.borrow_mut();
+        assert_eq!(format!("{:?}", op), "Op { index: 0, lifecycle: <borrowed> }");
+        assert!(format!("{:?}", uring).contains("in_flight: <borrowed>"));
+        drop(guard);
+
+        run(&uring, async {
+            std::future::poll_fn(|cx| {
+                assert!(std::pin::Pin::new(&mut op).poll(cx).is_pending());
+                std::task::Poll::Ready(())
+            }).await;
+            assert_eq!(format!("{:?}", op), "Op { index: 0, lifecycle: Waiting }");
+            (&mut op).await;
+            assert_eq!(format!("{:?}", op), "Op { index: 0, lifecycle: Completed }");
+        });
+        assert_eq!(format!("{:?}", uring), "IoUringAsync { in_flight: 0, unsubmitted: 0, sq_entries: 8, cq_entries: 16 }");
+    }
+
+    #[test]
+    fn push_raw_nowait() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        for _ in 0..32 {
+            uring.push_raw_nowait(Nop::new().build()).unwrap();
+        }
+        uring.submit().unwrap();
+        assert_eq!(uring.cq_ready(), 0);
+        assert!(uring.slab.borrow().is_empty());
+
+        // A failure is counted as an orphan.
+        let buf = b"x";
+        uring.push_raw_nowait(io_uring::opcode::Write::new(io_uring::types::Fd(-1), buf.as_ptr(), 1).build()).unwrap();
+        uring.submit_and_wait_at_least(1).unwrap();
+        assert_eq!(uring.stats().orphan_completions, 1);
+    }
+
+    #[test]
+    fn orphan_completion() {
+        let events = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let hook_events = events.clone();
+        let uring = IoUringAsync::builder()
+            .on_unexpected_completion(move |event| hook_events.borrow_mut().push(*event))
+            .build(8)
+            .unwrap();
+        let uring = Rc::new(uring);
+        uring.uring.push_multiple(&[Nop::new().build().user_data(1000)]).unwrap();
+        run(&uring, async {
+            uring.push(Nop::new().build()).await;
+        });
+        assert_eq!(uring.stats().orphan_completions, 1);
+        assert_eq!(uring.stats().unexpected_completions, 0);
+        assert_eq!(*events.borrow(), [crate::trace::UnexpectedCompletion { user_data: 1000, result: 0, flags: 0, orphan: true }]);
+        assert!(uring.slab.borrow().is_empty());
+    }
+
+    #[test]
+    fn try_push() {
+        let uring = Rc::new(IoUringAsync::builder().max_in_flight(2).build(8).unwrap());
+        run(&uring, async {
+            let a = uring.try_push(Nop::new().build()).unwrap();
+            let b = uring.try_push(Nop::new().build()).unwrap();
+            let err = uring.try_push(Nop::new().build()).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+            a.await;
+            uring.try_push(Nop::new().build()).unwrap().await;
+            b.await;
+        });
+
+        // Completions that overflow the completion queue are not lost on
+        // NODROP kernels, so the default admits more Ops than fit in it.
+        let uring = IoUringAsync::new(4).unwrap();
+        assert!(uring.params().is_feature_nodrop());
+        let ops: Vec<_> = (0..24).map(|_| uring.try_push(Nop::new().build()).unwrap()).collect();
+        uring.reap_blocking().unwrap();
+        assert!(ops.iter().all(|op| op.is_completed()));
+        drop(ops);
+    }
+
+    #[test]
+    fn in_flight() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let a = uring.push(Nop::new().build());
+        let b = uring.push(Nop::new().build());
+        uring.push_with(Nop::new().build(), drop);
+        assert_eq!(uring.in_flight(), 3);
+
+        // Completed Ops are no longer in flight, even before they are consumed.
+        uring.submit_and_wait_at_least(3).unwrap();
+        assert_eq!(uring.in_flight(), 0);
+        drop(a);
+        assert_eq!(uring.in_flight(), 0);
+        assert_eq!(uring.slab.borrow().len(), 1);
+        drop(b);
+
+        // An Op dropped before it completes is in flight until it does.
+        let timespec = crate::time::timespec(std::time::Duration::from_millis(1));
+        run(&uring, async {
+            drop(uring.push(io_uring::opcode::Timeout::new(&timespec).build()));
+            assert_eq!(uring.in_flight(), 1);
+            uring.drain().await;
+        });
+        assert_eq!(uring.in_flight(), 0);
+        assert!(uring.slab.borrow().is_empty());
+    }
+
+    #[test]
+    fn reentrant_wake() {
+        // A waker that pushes an Op when it is woken, as an executor that polls
+        // tasks inline might.
+        struct PushOnWake {
+            uring: SendWrapper<Rc<IoUringAsync>>,
+            ops: SendWrapper<std::cell::RefCell<Vec<super::Op<io_uring::cqueue::Entry>>>>,
+        }
+        impl std::task::Wake for PushOnWake {
+            fn wake(self: std::sync::Arc<Self>) {
+                self.ops.borrow_mut().push(self.uring.push(Nop::new().build()));
+            }
+        }
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let wake = std::sync::Arc::new(PushOnWake {
+            uring: SendWrapper::new(uring.clone()),
+            ops: SendWrapper::new(Default::default()),
+        });
+        let waker = std::task::Waker::from(wake.clone());
+        let mut op = std::pin::pin!(uring.push(Nop::new().build()));
+        assert!(op.as_mut().poll(&mut std::task::Context::from_waker(&waker)).is_pending());
+        uring.reap_blocking().unwrap();
+        assert_eq!(wake.ops.borrow().len(), 1);
+        uring.reap_blocking().unwrap();
+        wake.ops.borrow_mut().clear();
+    }
+
+    #[test]
+    fn debug_pending() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut op = std::pin::pin!(uring.push(Nop::new().build()));
+        let nop = uring.push(Nop::new().build());
+        assert_eq!(uring.debug_pending(), [(0, OpState::Submitted), (1, OpState::Submitted)]);
+
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        assert!(op.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(uring.debug_pending()[0], (0, OpState::Waiting));
+
+        uring.submit().unwrap();
+        while uring.cq_ready() < 2 {}
+        uring.process_completions();
+        assert_eq!(uring.debug_pending(), [(0, OpState::Completed), (1, OpState::Completed)]);
+        drop(nop);
+
+        let _guard = uring.slab.borrow_mut();
+        assert!(uring.debug_pending().is_empty());
+    }
+
+    #[test]
+    fn poll_waker() {
+        struct Count(std::sync::atomic::AtomicUsize);
+        impl std::task::Wake for Count {
+            fn wake(self: std::sync::Arc<Self>) {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+
+        // Every clone of a waker holds a reference to its Count, so the
+        // reference count tells whether the Op cloned the waker.
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut op = std::pin::pin!(uring.push(Nop::new().build()));
+        let first = std::sync::Arc::new(Count(Default::default()));
+        let waker = std::task::Waker::from(first.clone());
+        for _ in 0..3 {
+            assert!(op.as_mut().poll(&mut std::task::Context::from_waker(&waker)).is_pending());
+            assert_eq!(std::sync::Arc::strong_count(&first), 3);
+        }
+
+        // Polling from another task replaces the waker.
+        let second = std::sync::Arc::new(Count(Default::default()));
+        let waker = std::task::Waker::from(second.clone());
+        assert!(op.as_mut().poll(&mut std::task::Context::from_waker(&waker)).is_pending());
+        assert_eq!(std::sync::Arc::strong_count(&first), 2);
+        assert_eq!(std::sync::Arc::strong_count(&second), 3);
+        uring.submit_and_wait_at_least(1).unwrap();
+        assert_eq!(first.0.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(second.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(op.as_mut().poll(&mut std::task::Context::from_waker(std::task::Waker::noop())).is_ready());
+    }
+
+    #[test]
+    fn single_task() {
+        struct Count(std::sync::atomic::AtomicUsize);
+        impl std::task::Wake for Count {
+            fn wake(self: std::sync::Arc<Self>) {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+        let counter = || {
+            let count = std::sync::Arc::new(Count(Default::default()));
+            (count.clone(), std::task::Waker::from(count))
+        };
+
+        let uring = Rc::new(IoUringAsync::builder().single_task().build(8).unwrap());
+        let (count, waker) = counter();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut ops: Vec<_> = (0..4).map(|_| Box::pin(uring.push(Nop::new().build()))).collect();
+        for op in &mut ops {
+            assert!(op.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(uring.debug_pending()[0].1, OpState::Waiting);
+        assert_eq!(std::sync::Arc::strong_count(&count), 3);
+
+        // The task is woken once for the whole batch.
+        uring.submit_and_wait_at_least(4).unwrap();
+        assert_eq!(count.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert!(ops.iter_mut().all(|op| op.as_mut().poll(&mut cx).is_ready()));
+        drop(ops);
+
+        // An Op of another task keeps its own waker while the first task waits,
+        // and each task is woken for its own Op.
+        let (other_count, other_waker) = counter();
+        let mut first = std::pin::pin!(uring.push(Nop::new().build()));
+        let mut second = std::pin::pin!(uring.push(Nop::new().build()));
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut std::task::Context::from_waker(&other_waker)).is_pending());
+        uring.submit_and_wait_at_least(2).unwrap();
+        assert_eq!(count.0.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(other_count.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Once no Op is waiting, the shared waker moves to the next task.
+        let mut third = std::pin::pin!(uring.push(Nop::new().build()));
+        assert!(third.as_mut().poll(&mut std::task::Context::from_waker(&other_waker)).is_pending());
+        uring.submit_and_wait_at_least(1).unwrap();
+        assert_eq!(other_count.0.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(std::sync::Arc::strong_count(&count), 2);
+
+        // Ops awaited on a runtime complete as usual.
+        crate::tests::run(&uring, async {
+            let (a, b) = (uring.push(Nop::new().build()), uring.push(Nop::new().build()));
+            assert_eq!(b.await.result(), 0);
+            assert_eq!(a.await.result(), 0);
+        });
+    }
+
+    #[cfg(feature = "op-sequence")]
+    #[test]
+    fn debug_pending_seq() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let first = uring.push(Nop::new().build());
+        let _second = uring.push(Nop::new().build());
+        uring.submit_and_wait_at_least(2).unwrap();
+        drop(first);
+
+        // A reused slot is stamped with a new sequence number.
+        let _third = uring.push(Nop::new().build());
+        assert_eq!(uring.debug_pending(), [(0, OpState::Submitted), (1, OpState::Completed)]);
+        assert_eq!(uring.debug_pending_seq(), [(0, 2, OpState::Submitted), (1, 1, OpState::Completed)]);
+    }
+
+    #[test]
+    fn map() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        if !uring.supports_nop_result() {
+            return;
+        }
+        run(&uring, async {
+            let n = uring.nop_with_result(5).unwrap().map(|cqe| cqueue::result_to_io(cqe.result())).await;
+            assert_eq!(n.unwrap(), 5);
+
+            drop(uring.push(Nop::new().build()).map(|cqe| cqe.result()));
+            uring.drain().await;
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn unexpected_completion() {
+        let events = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let hook_events = events.clone();
+        let uring = IoUringAsync::builder()
+            .on_unexpected_completion(move |event| hook_events.borrow_mut().push(*event))
+            .build(8)
+            .unwrap();
+        let uring = Rc::new(uring);
+        run(&uring, async {
+            // A second entry with the same user_data completes the Op twice.
+            let op = uring.push(Nop::new().build());
+            let index = op.inner.as_ref().unwrap().index as u64;
+            uring.uring.push_multiple(&[Nop::new().build().user_data(index)]).unwrap();
+            op.await;
+            uring.push(Nop::new().build()).await;
+        });
+        assert_eq!(uring.stats().unexpected_completions, 1);
+        assert_eq!(*events.borrow(), [crate::trace::UnexpectedCompletion { user_data: 0, result: 0, flags: 0, orphan: false }]);
+    }
+
+    #[test]
+    fn process_completions_from_epoll() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        assert!(epoll >= 0);
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: 0 };
+        assert_eq!(unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, uring.as_raw_fd(), &mut event) }, 0);
+
+        let mut op = std::pin::pin!(uring.push(Nop::new().build()));
+        let waker = std::task::Waker::noop();
+        let mut cx = std::task::Context::from_waker(waker);
+        assert!(op.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(uring.process_completions(), 0);
+
+        uring.submit().unwrap();
+        assert_eq!(unsafe { libc::epoll_wait(epoll, &mut event, 1, 1000) }, 1);
+        assert_eq!(uring.process_completions(), 1);
+        match op.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(cqe) => assert_eq!(cqe.result(), 0),
+            std::task::Poll::Pending => panic!("op is not ready"),
+        }
+        unsafe { libc::close(epoll) };
+    }
+
+    #[test]
+    fn fixed_resources() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let mut buf = [0u8; 16];
+        let nop = uring.push(Nop::new().build());
+        let entry = io_uring::opcode::ReadFixed::new(io_uring::types::Fixed(0), buf.as_mut_ptr(), 16, 0).build();
+        let read = uring.push(entry);
+        uring.reap_blocking().unwrap();
+        assert_eq!(nop.peek_result().unwrap().result(), 0);
+        assert_eq!(nop.fixed_resources(), crate::squeue::FixedResources::default());
+        // Neither a file table nor buffers are registered, so the read fails.
+        assert!(read.peek_result().unwrap().result() < 0);
+        assert_eq!(read.fixed_resources(), crate::squeue::FixedResources { file: true, buffer: true });
+    }
+
+    #[test]
+    fn submit_and_wait_at_least() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let timespec = io_uring::types::Timespec::new().nsec(100_000_000);
+        let timeout = uring.push(io_uring::opcode::Timeout::new(&timespec).build());
+        let nops: Vec<_> = (0..3).map(|_| uring.push(Nop::new().build())).collect();
+        assert_eq!(uring.submit_and_wait_at_least(2).unwrap(), 3);
+        assert!(nops.iter().all(|nop| nop.is_completed()));
+        assert!(!timeout.is_completed());
+
+        // The wait is bounded by the timeout, after which nothing is ready.
+        let start = std::time::Instant::now();
+        assert_eq!(uring.submit_and_wait_timeout(1, std::time::Duration::from_millis(1)).unwrap(), 0);
+        assert!(start.elapsed() < std::time::Duration::from_millis(100));
+        assert_eq!(uring.submit_and_wait_timeout(1, std::time::Duration::from_secs(1)).unwrap(), 1);
+        assert!(timeout.is_completed());
+        drop(nops);
+        drop(timeout);
+    }
+
+    #[test]
+    fn submit_retries_eintr() {
+        static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+        extern "C" fn handler(_: libc::c_int) {
+            INTERRUPTED.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+        // Without SA_RESTART, so that the signal interrupts io_uring_enter.
+        let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+        action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        let mut old: libc::sigaction = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::sigaction(libc::SIGUSR1, &action, &mut old) }, 0);
+
+        let uring = IoUringAsync::new(8).unwrap();
+        let timespec = io_uring::types::Timespec::new().nsec(200_000_000);
+        let timeout = uring.push(io_uring::opcode::Timeout::new(&timespec).build());
+        // The kernel only reports EINTR if nothing is submitted by the
+        // interrupted call.
+        uring.submit().unwrap();
+        let thread = unsafe { libc::pthread_self() };
+        let signaler = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            unsafe { libc::pthread_kill(thread, libc::SIGUSR1) };
+        });
+        uring.reap_blocking().unwrap();
+        signaler.join().unwrap();
+        assert_eq!(unsafe { libc::sigaction(libc::SIGUSR1, &old, std::ptr::null_mut()) }, 0);
+        assert!(INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(uring.stats().interrupted_submits, 1);
+        drop(timeout);
+    }
+
+    #[test]
+    fn listen_drains_burst() {
+        // The burst is much larger than the completion queue, so its completions
+        // are posted across many notifications while earlier ones are processed.
+        let uring = Rc::new(IoUringAsync::new(4).unwrap());
+        run(&uring, async {
+            let ops: Vec<_> = (0..256).map(|_| uring.push(Nop::new().build())).collect();
+            let all = async {
+                for op in ops {
+                    op.await;
+                }
+            };
+            tokio::time::timeout(std::time::Duration::from_secs(5), all).await.unwrap();
+        });
+        assert_eq!(uring.in_flight(), 0);
+    }
+
+    #[test]
+    fn is_completed() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let op = uring.push(Nop::new().build());
+        assert!(!op.is_completed());
+        assert!(op.peek_result().is_none());
+        // Peeking does not register a waker or otherwise change the Op.
+        assert_eq!(uring.debug_pending(), [(0, OpState::Submitted)]);
+
+        uring.submit().unwrap();
+        while uring.process_completions() == 0 {}
+        assert!(op.is_completed());
+        assert_eq!(op.peek_result().unwrap().result(), 0);
+        assert_eq!(op.peek_result().unwrap().result(), 0);
+        drop(op);
+    }
+
+    #[test]
+    fn drain_completions_into() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let mut buf = Vec::with_capacity(4);
+        assert_eq!(uring.drain_completions_into(&mut buf), 0);
+
+        for user_data in [7, 8, 9] {
+            uring.push_unmanaged(Nop::new().build().user_data(user_data)).unwrap();
+        }
+        uring.uring.submit_and_wait(3).unwrap();
+        assert_eq!(uring.drain_completions_into(&mut buf), 3);
+        assert_eq!(buf.iter().map(|cqe| cqe.user_data()).collect::<Vec<_>>(), [7, 8, 9]);
+        assert_eq!(uring.in_flight(), 0);
+
+        buf.clear();
+        uring.push_unmanaged(Nop::new().build().user_data(10)).unwrap();
+        uring.uring.submit_and_wait(1).unwrap();
+        assert_eq!(uring.drain_completions_into(&mut buf), 1);
+        assert_eq!(buf[0].user_data(), 10);
+        assert_eq!(buf.capacity(), 4);
+    }
+
+    #[test]
+    fn push_with() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let results = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let callback_uring = uring.clone();
+        let callback_results = results.clone();
+        uring.push_with(Nop::new().build(), move |cqe| {
+            callback_results.borrow_mut().push(cqe.result());
+            // Callbacks may push further entries.
+            let results = callback_results.clone();
+            callback_uring.push_with(Nop::new().build(), move |cqe| results.borrow_mut().push(cqe.result()));
+        });
+        assert_eq!(uring.in_flight(), 1);
+
+        uring.submit().unwrap();
+        assert_eq!(uring.process_completions(), 1);
+        assert_eq!(*results.borrow(), [0]);
+        uring.submit().unwrap();
+        assert_eq!(uring.process_completions(), 1);
+        assert_eq!(*results.borrow(), [0, 0]);
+        assert!(uring.slab.borrow().is_empty());
+    }
+
+    #[test]
+    fn warmup() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        run(&uring, uring.warmup(12));
+        assert_eq!(uring.stats().pushed, 12);
+        assert!(uring.slab.borrow().is_empty());
+    }
+
+    #[test]
+    fn params() {
+        let uring = IoUringAsync::new(8).unwrap();
+        assert_eq!(uring.params().sq_entries(), 8);
+        assert_eq!(uring.params().cq_entries(), 16);
+        assert!(uring.params().is_feature_nodrop());
+    }
+
+    #[test]
+    fn resize_cq() {
+        let uring = IoUringAsync::new(8).unwrap();
+        uring.resize_cq(12).unwrap();
+        assert_eq!(uring.resize_cq(0).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(uring.resize_cq(64).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(uring.params().cq_entries(), 16);
+    }
+
+    #[test]
+    fn queue_occupancy() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let ops: Vec<_> = (0..3).map(|_| uring.push(Nop::new().build())).collect();
+        assert_eq!(uring.sq_space_left(), 5);
+        assert_eq!(uring.cq_ready(), 0);
+
+        uring.submit().unwrap();
+        assert_eq!(uring.sq_space_left(), 8);
+        assert_eq!(uring.cq_ready(), 3);
+        assert_eq!(uring.process_completions(), 3);
+        assert_eq!(uring.cq_ready(), 0);
+        drop(ops);
+    }
+
+    #[test]
+    fn submit_without_queued_entries() {
+        let uring = Rc::new(IoUringAsync::new(4).unwrap());
+        assert_eq!(uring.submit().unwrap(), 0);
+        assert_eq!(uring.stats().submit_calls, 0);
+
+        // Pushing more entries than fit in the submission queue submits the
+        // queued entries to make room.
+        let ops: Vec<_> = (0..6).map(|_| uring.push(Nop::new().build())).collect();
+        assert_eq!(uring.unsubmitted(), 2);
+        assert_eq!(uring.submit().unwrap(), 2);
+        assert_eq!(uring.unsubmitted(), 0);
+        assert_eq!(uring.submit().unwrap(), 0);
+        let submit_calls = uring.stats().submit_calls;
+
+        let group = uring.push_chain((0..3).map(|_| Nop::new().build()).collect(), vec![None, None, None], vec![false; 3]);
+        assert_eq!(uring.unsubmitted(), 3);
+        assert_eq!(uring.submit().unwrap(), 3);
+        assert_eq!(uring.stats().submit_calls, submit_calls + 1);
+
+        // Nine completions overflow the completion queue, which must still be
+        // flushed even though nothing is left to submit.
+        run(&uring, async {
+            for op in ops.into_iter().chain(group) {
+                assert_eq!(op.await.result(), 0);
+            }
         });
     }
 }
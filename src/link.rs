@@ -0,0 +1,284 @@
+use std::time::Duration;
+use io_uring::{opcode, types};
+use io_uring::squeue::Flags;
+use crate::{cqueue, squeue, IoUringAsync, Op};
+
+// An entry in a link chain, along with any resources that must outlive it.
+struct Linked<S> {
+    entry: S,
+    // Whether the entry is a LinkTimeout guarding the entry before it.
+    timeout: bool,
+    // Whether the entry is flagged with CQE_SKIP_SUCCESS.
+    skip: bool,
+    resources: Option<Box<dyn std::any::Any>>,
+}
+
+/// A builder for a chain of linked entries, created with [`IoUringAsync::link`].
+///
+/// Each entry in the chain only starts once the entry before it has completed
+/// successfully. If an entry fails, the rest of the chain is canceled with
+/// `-ECANCELED`. The chain is pushed contiguously by [`Link::finish`], since a
+/// chain cannot span multiple submissions.
+pub struct Link<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    entries: Vec<Linked<S>>,
+}
+
+impl<'a, S: squeue::Entry, C: cqueue::Entry> Link<'a, S, C> {
+    /// Append an entry to the chain.
+    pub fn push(mut self, entry: impl Into<S>) -> Self {
+        self.entries.push(Linked { entry: entry.into(), timeout: false, skip: false, resources: None });
+        self
+    }
+
+    // Attach resources that must outlive the most recently pushed entry, which
+    // can be taken back from its Op once it has completed.
+    pub(crate) fn owning(mut self, resources: Box<dyn std::any::Any>) -> Self {
+        let linked = self.entries.last_mut().expect("resources must follow the entry that uses them");
+        assert!(linked.resources.is_none(), "entry already owns resources");
+        linked.resources = Some(resources);
+        self
+    }
+
+    /// Flag the most recently pushed entry with `CQE_SKIP_SUCCESS`, so that it
+    /// only posts a completion queue entry if it fails. This relieves the
+    /// completion queue for long chains where only the final result matters,
+    /// such as a chain of writes followed by an fsync. No Op is returned for the
+    /// entry by [`Link::finish`]. If it fails, the kernel cancels the rest of the
+    /// chain without posting completions for it either, so every later Op
+    /// resolves to the completion queue entry of the failed entry instead.
+    /// Requires Linux 5.17, without which the entry fails with `EINVAL`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chain is empty. [`Link::finish`] panics if the last entry
+    /// of the chain is flagged, since nothing would report its result.
+    pub fn skip_success(mut self) -> Self {
+        let linked = self.entries.last_mut().expect("skip_success must follow the entry that it flags");
+        linked.skip = true;
+        self
+    }
+
+    /// Guard the most recently pushed entry with a `LinkTimeout`. If the entry
+    /// has not completed after `timeout`, the kernel cancels it: the entry
+    /// completes with `-ECANCELED` and the timeout with `-ETIME`. If the entry
+    /// completes first, the timeout completes with `-ECANCELED`.
+    ///
+    /// The timeout is inserted immediately after the entry it guards, and the
+    /// entry is flagged with `IO_LINK`, so the timeout can never fire on its own.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chain is empty or the most recent entry is itself a
+    /// timeout.
+    pub fn link_timeout(self, timeout: Duration) -> Self {
+        self.timeout(crate::time::timespec(timeout), types::TimeoutFlags::empty())
+    }
+
+    // Guard the most recently pushed entry with a LinkTimeout that uses the given
+    // timespec and timeout flags.
+    pub(crate) fn timeout(mut self, timespec: types::Timespec, flags: types::TimeoutFlags) -> Self {
+        assert!(
+            self.entries.last().is_some_and(|linked| !linked.timeout),
+            "a link timeout must follow the entry that it guards"
+        );
+        let timespec = Box::new(timespec);
+        let entry = opcode::LinkTimeout::new(&*timespec).flags(flags).build();
+        self.entries.push(Linked { entry: entry.into(), timeout: true, skip: false, resources: Some(timespec) });
+        self
+    }
+
+    /// Push the chain onto the submission queue, returning an Op for each entry
+    /// in the order that they were added, including any timeouts, except for
+    /// entries flagged with [`Link::skip_success`].
+    ///
+    /// Every entry except the last is flagged with `IO_LINK`. In particular, a
+    /// timeout that ends the chain is not, so that it only guards the entry
+    /// before it.
+    pub fn finish(self) -> Vec<Op<C>> {
+        let uring = self.uring;
+        let skipped = self.entries.iter().map(|linked| linked.skip).collect();
+        let (entries, resources) = self.into_entries();
+        uring.push_chain(entries, resources, skipped)
+    }
+
+    // Flag the entries of the chain, separating them from their resources.
+    fn into_entries(self) -> (Vec<S>, Vec<Option<Box<dyn std::any::Any>>>) {
+        let count = self.entries.len();
+        let mut resources = Vec::with_capacity(count);
+        let entries = self.entries.into_iter().enumerate().map(|(i, linked)| {
+            resources.push(linked.resources);
+            let mut flags = Flags::empty();
+            if i + 1 < count {
+                flags |= Flags::IO_LINK;
+            }
+            if linked.skip {
+                flags |= Flags::SKIP_SUCCESS;
+            }
+            linked.entry.flags(flags)
+        }).collect();
+        (entries, resources)
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Create a [`Link`] builder for a chain of linked entries.
+    pub fn link(&self) -> Link<'_, S, C> {
+        Link { uring: self, entries: Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use io_uring::{opcode, types};
+    use io_uring::squeue::Flags;
+    use crate::{squeue, IoUringAsync};
+
+    #[test]
+    fn link_timeout() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut buf = [0u8; 16];
+        crate::tests::run(&uring, async {
+            let mut ops = uring.link()
+                .push(opcode::Nop::new().build())
+                .link_timeout(Duration::from_secs(5))
+                .finish();
+            assert_eq!(ops.remove(0).await.result(), 0);
+            assert_eq!(ops.remove(0).await.result(), -libc::ECANCELED);
+
+            // Nothing is ever written to the socket, so the recv can only be
+            // completed by the timeout.
+            let recv = opcode::Recv::new(types::Fd(a.as_raw_fd()), buf.as_mut_ptr(), buf.len() as u32).build();
+            let mut ops = uring.link()
+                .push(recv)
+                .link_timeout(Duration::from_millis(20))
+                .finish();
+            assert_eq!(ops.remove(0).await.result(), -libc::ECANCELED);
+            assert_eq!(ops.remove(0).await.result(), -libc::ETIME);
+        });
+    }
+
+    #[test]
+    fn link_timeout_flags() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let (mut entries, _resources) = uring.link()
+            .push(opcode::Nop::new().build())
+            .link_timeout(Duration::from_secs(1))
+            .into_entries();
+        // The guarded entry is linked to the timeout that follows it, and the
+        // timeout is not linked to anything.
+        let raw: Vec<_> = entries.iter_mut().map(|entry| {
+            let raw = squeue::raw_mut(entry);
+            (raw.opcode, Flags::from_bits_truncate(raw.flags))
+        }).collect();
+        assert_eq!(raw, [(opcode::Nop::CODE, Flags::IO_LINK), (opcode::LinkTimeout::CODE, Flags::empty())]);
+    }
+
+    #[test]
+    fn link_chain() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let ops = uring.link()
+                .push(opcode::Nop::new().build())
+                .link_timeout(Duration::from_secs(5))
+                .push(opcode::Nop::new().build())
+                .finish();
+            let mut results = Vec::new();
+            for op in ops {
+                results.push(op.await.result());
+            }
+            assert_eq!(results, [0, -libc::ECANCELED, 0]);
+        });
+    }
+
+    #[test]
+    fn skip_success() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (entries, _resources) = uring.link()
+            .push(opcode::Nop::new().build())
+            .skip_success()
+            .push(opcode::Nop::new().build())
+            .into_entries();
+        let flags: Vec<_> = entries.into_iter().map(|mut entry| Flags::from_bits_truncate(squeue::raw_mut(&mut entry).flags)).collect();
+        assert_eq!(flags, [Flags::IO_LINK | Flags::SKIP_SUCCESS, Flags::empty()]);
+
+        crate::tests::run(&uring, async {
+            let ops = uring.link()
+                .push(opcode::Nop::new().build())
+                .skip_success()
+                .push(opcode::Nop::new().build())
+                .skip_success()
+                .push(opcode::Nop::new().build())
+                .finish();
+            assert_eq!(ops.len(), 1);
+            for op in ops {
+                assert_eq!(op.await.result(), 0);
+            }
+            assert!(uring.slab.borrow().is_empty());
+
+            // A failed entry hands its completion to every later Op, for which
+            // the kernel posts none.
+            let buf = b"x";
+            let ops = uring.link()
+                .push(opcode::Nop::new().build())
+                .skip_success()
+                .push(opcode::Write::new(types::Fd(-1), buf.as_ptr(), 1).build())
+                .skip_success()
+                .push(opcode::Nop::new().build())
+                .push(opcode::Nop::new().build())
+                .skip_success()
+                .push(opcode::Nop::new().build())
+                .finish();
+            assert_eq!(ops.len(), 2);
+            for op in ops {
+                assert_eq!(op.await.result(), -libc::EBADF);
+            }
+            uring.drain().await;
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn skip_success_after_failure() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            // An Op that fails before a skipped entry cancels it with a
+            // completion of its own, as well as every later Op.
+            let buf = b"x";
+            let ops = uring.link()
+                .push(opcode::Write::new(types::Fd(-1), buf.as_ptr(), 1).build())
+                .push(opcode::Nop::new().build())
+                .skip_success()
+                .push(opcode::Nop::new().build())
+                .finish();
+            let mut results = Vec::new();
+            for op in ops {
+                results.push(op.await.result());
+            }
+            assert_eq!(results, [-libc::EBADF, -libc::ECANCELED]);
+            uring.drain().await;
+            assert!(uring.slab.borrow().is_empty());
+        });
+        assert_eq!(uring.stats().unexpected_completions, 0);
+        assert_eq!(uring.stats().orphan_completions, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must post its completion")]
+    fn skip_success_last() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let _ = uring.link().push(opcode::Nop::new().build()).skip_success().finish();
+    }
+
+    #[test]
+    #[should_panic(expected = "must follow")]
+    fn link_timeout_without_entry() {
+        let uring = IoUringAsync::new(8).unwrap();
+        let _ = uring.link().link_timeout(Duration::from_secs(1));
+    }
+}
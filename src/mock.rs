@@ -0,0 +1,320 @@
+//! An in-memory ring for testing code built on top of this crate without a
+//! kernel io_uring. This is only available with the `mock` feature.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use io_uring::opcode::AsyncCancel;
+use crate::{squeue, Driver, Op, OpInner, Slot, Slots, TaskWaker};
+
+// A synthetic completion queue entry with the kernel's layout.
+#[repr(C)]
+struct RawCqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+const _: () = assert!(std::mem::size_of::<RawCqe>() == std::mem::size_of::<io_uring::cqueue::Entry>());
+
+fn cqe(user_data: u64, res: i32, flags: u32) -> io_uring::cqueue::Entry {
+    unsafe { std::mem::transmute(RawCqe { user_data, res, flags }) }
+}
+
+/// A submission queue entry that was pushed onto a [`MockRing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pushed {
+    /// The user_data of the entry, which identifies the Op when completing it
+    /// with [`MockRing::complete`].
+    pub user_data: u64,
+    /// The opcode of the entry.
+    pub opcode: u8,
+}
+
+/// The order in which [`MockRing::handle_cqe`] delivers the completion queue
+/// entries that have been injected since it was last called. Entries are always
+/// recorded in the order that they are pushed, and the order in which they are
+/// delivered is recorded by [`MockRing::delivered`], so that an order found by
+/// a fuzz or property test can be reproduced with [`CompletionOrder::Replay`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CompletionOrder {
+    /// Deliver entries in the order that they were injected.
+    #[default]
+    Fifo,
+    /// Deliver entries in a pseudo-random order that is fully determined by
+    /// the seed.
+    Seeded(u64),
+    /// Deliver entries in the order of their user_data in the list, such as
+    /// one returned by [`MockRing::delivered`]. Entries whose user_data is not
+    /// in the list are delivered afterwards, in the order they were injected.
+    Replay(Vec<u64>),
+}
+
+// A xorshift64* generator, which is enough to shuffle completions
+// reproducibly without a dependency.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+impl CompletionOrder {
+    // Reorder the pending completion queue entries in place.
+    fn apply(&mut self, completions: &mut VecDeque<io_uring::cqueue::Entry>) {
+        match self {
+            CompletionOrder::Fifo => {},
+            CompletionOrder::Seeded(state) => {
+                // A zero state would only ever produce zeros.
+                if *state == 0 {
+                    *state = 0x9e37_79b9_7f4a_7c15;
+                }
+                let slice = completions.make_contiguous();
+                for i in (1..slice.len()).rev() {
+                    let j = (next_random(state) % (i as u64 + 1)) as usize;
+                    slice.swap(i, j);
+                }
+            },
+            CompletionOrder::Replay(order) => {
+                let mut ordered = VecDeque::with_capacity(completions.len());
+                let mut consumed = 0;
+                for user_data in order.iter() {
+                    let Some(position) = completions.iter().position(|cqe| cqe.user_data() == *user_data) else {
+                        break;
+                    };
+                    ordered.extend(completions.remove(position));
+                    consumed += 1;
+                }
+                order.drain(..consumed);
+                ordered.append(completions);
+                *completions = ordered;
+            },
+        }
+    }
+}
+
+// The mock counterpart of the Ring, which records pushed entries and hands out
+// the completion queue entries that the test has injected.
+struct MockDriver {
+    pushed: RefCell<Vec<Pushed>>,
+    completions: RefCell<VecDeque<io_uring::cqueue::Entry>>,
+    orphan_completions: Cell<u64>,
+    order: RefCell<CompletionOrder>,
+    delivered: RefCell<Vec<u64>>,
+}
+
+impl Driver<io_uring::cqueue::Entry> for MockDriver {
+    fn push(&self, mut entry: io_uring::squeue::Entry) -> std::io::Result<()> {
+        let raw = squeue::raw_mut(&mut entry);
+        if raw.opcode == AsyncCancel::CODE {
+            // Cancellation is performed immediately, as if every Op could be
+            // canceled, so that dropped Ops can be reaped.
+            let mut completions = self.completions.borrow_mut();
+            completions.push_back(cqe(raw.addr, -libc::ECANCELED, 0));
+            completions.push_back(cqe(raw.user_data, 0, 0));
+        } else {
+            self.pushed.borrow_mut().push(Pushed { user_data: raw.user_data, opcode: raw.opcode });
+        }
+        Ok(())
+    }
+
+    fn submit_and_wait(&self, _want: usize) -> std::io::Result<usize> {
+        Ok(0)
+    }
+
+    fn next_completion(&self) -> Option<io_uring::cqueue::Entry> {
+        let cqe = self.completions.borrow_mut().pop_front()?;
+        self.delivered.borrow_mut().push(cqe.user_data());
+        Some(cqe)
+    }
+
+    fn completions_dispatched(&self) {}
+
+    fn orphan_completion(&self, _cqe: &io_uring::cqueue::Entry) {
+        self.orphan_completions.set(self.orphan_completions.get() + 1);
+    }
+
+    fn unexpected_completion(&self, _cqe: &io_uring::cqueue::Entry) {}
+
+    fn fd_received(&self, _cqe: &io_uring::cqueue::Entry) -> Vec<std::task::Waker> {
+        Vec::new()
+    }
+
+    fn submitter(&self) -> io_uring::Submitter<'_> {
+        unreachable!("a MockRing has no kernel ring")
+    }
+
+    fn take_submitted(&self) -> Vec<(u64, std::time::Instant)> {
+        Vec::new()
+    }
+
+    fn tag_completed(&self, _tag: u64, _cqe: &io_uring::cqueue::Entry) -> Vec<std::task::Waker> {
+        Vec::new()
+    }
+
+    fn task_waker(&self) -> Option<&RefCell<TaskWaker>> {
+        None
+    }
+
+    fn completed(&self, _tag: Option<u64>, _seq: Option<u64>, _pushed: (u8, std::time::Instant), _submitted: Option<std::time::Instant>, _cqe: &io_uring::cqueue::Entry) {}
+}
+
+/// An in-memory stand-in for an [`IoUringAsync`](crate::IoUringAsync). Entries
+/// pushed onto a `MockRing` are recorded rather than performed, and their Ops
+/// complete when the test injects a completion queue entry with
+/// [`MockRing::complete`] and calls [`MockRing::handle_cqe`].
+///
+/// The Ops returned by a `MockRing` are the same as those of a real ring, so
+/// their lifecycle, including being dropped before completion, behaves the
+/// same. Cancel requests, which are pushed when an Op is dropped, succeed
+/// immediately.
+pub struct MockRing {
+    driver: Rc<MockDriver>,
+    slab: Rc<RefCell<Slots<io_uring::cqueue::Entry>>>,
+}
+
+impl MockRing {
+    pub fn new() -> Self {
+        Self {
+            driver: Rc::new(MockDriver {
+                pushed: RefCell::new(Vec::new()),
+                completions: RefCell::new(VecDeque::new()),
+                orphan_completions: Cell::new(0),
+                order: RefCell::new(CompletionOrder::Fifo),
+                delivered: RefCell::new(Vec::new()),
+            }),
+            slab: Rc::new(RefCell::default()),
+        }
+    }
+
+    /// Push an entry, returning an Op that completes once a completion queue
+    /// entry is injected for its user_data.
+    pub fn push(&self, entry: impl Into<io_uring::squeue::Entry>) -> Op<io_uring::cqueue::Entry> {
+        let index = self.slab.borrow_mut().insert(Slot::new(None));
+        let entry = squeue::Entry::user_data(entry.into(), index.try_into().unwrap());
+        self.driver.push(entry).unwrap();
+        Op {
+            inner: Some(OpInner {
+                uring: self.driver.clone(),
+                slab: self.slab.clone(),
+                index,
+                resources: None,
+            })
+        }
+    }
+
+    /// Take the entries that have been pushed since the last call.
+    pub fn take_pushed(&self) -> Vec<Pushed> {
+        self.driver.pushed.take()
+    }
+
+    /// Inject a completion queue entry with the given user_data, result and
+    /// flags. It is delivered to its Op by the next call to [`MockRing::handle_cqe`].
+    pub fn complete(&self, user_data: u64, result: i32, flags: u32) {
+        self.driver.completions.borrow_mut().push_back(cqe(user_data, result, flags));
+    }
+
+    /// Set the order in which [`MockRing::handle_cqe`] delivers injected
+    /// completion queue entries. The default is [`CompletionOrder::Fifo`].
+    pub fn set_completion_order(&self, order: CompletionOrder) {
+        *self.driver.order.borrow_mut() = order;
+    }
+
+    /// Take the user_data of every completion queue entry that has been
+    /// delivered since the last call, in the order they were delivered.
+    pub fn delivered(&self) -> Vec<u64> {
+        self.driver.delivered.take()
+    }
+
+    /// Deliver every injected completion queue entry to its Op, in the order
+    /// set by [`MockRing::set_completion_order`], returning the number of
+    /// entries that were delivered. Entries that are injected while delivering,
+    /// such as those of cancellations, are delivered after the rest, in order.
+    pub fn handle_cqe(&self) -> usize {
+        self.driver.order.borrow_mut().apply(&mut self.driver.completions.borrow_mut());
+        crate::dispatch_completions(&*self.driver, &self.slab)
+    }
+
+    /// Returns the number of Ops that have not yet completed.
+    pub fn in_flight(&self) -> usize {
+        self.slab.borrow().in_flight()
+    }
+
+    /// Returns the number of injected completion queue entries whose user_data
+    /// did not belong to any Op.
+    pub fn orphan_completions(&self) -> u64 {
+        self.driver.orphan_completions.get()
+    }
+}
+
+impl Default for MockRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+    use io_uring::opcode;
+    use super::{CompletionOrder, MockRing, Pushed};
+
+    #[test]
+    fn complete() {
+        let ring = MockRing::new();
+        let mut op = pin!(ring.push(opcode::Nop::new().build()));
+        let pushed = ring.take_pushed();
+        assert_eq!(pushed, [Pushed { user_data: 0, opcode: opcode::Nop::CODE }]);
+
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(op.as_mut().poll(&mut cx).is_pending());
+        ring.complete(pushed[0].user_data, 42, 0);
+        ring.complete(99, 0, 0);
+        assert_eq!(ring.handle_cqe(), 2);
+        assert_eq!(ring.orphan_completions(), 1);
+        match op.as_mut().poll(&mut cx) {
+            Poll::Ready(cqe) => assert_eq!(cqe.result(), 42),
+            Poll::Pending => panic!("op is not ready"),
+        }
+        assert_eq!(ring.in_flight(), 0);
+    }
+
+    #[test]
+    fn drop_before_completion() {
+        let ring = MockRing::new();
+        drop(ring.push(opcode::Nop::new().build()));
+        assert_eq!(ring.in_flight(), 0);
+        assert!(ring.slab.borrow().is_empty());
+    }
+
+    #[test]
+    fn completion_order() {
+        // Run eight Nops on a new ring, completing them all at once.
+        let round = |order: CompletionOrder| {
+            let ring = MockRing::new();
+            let ops: Vec<_> = (0..8).map(|_| ring.push(opcode::Nop::new().build())).collect();
+            let pushed = ring.take_pushed();
+            ring.set_completion_order(order);
+            for pushed in pushed {
+                ring.complete(pushed.user_data, 0, 0);
+            }
+            ring.complete(99, 0, 0);
+            assert_eq!(ring.handle_cqe(), 9);
+            drop(ops);
+            assert_eq!(ring.in_flight(), 0);
+            ring.delivered()
+        };
+
+        // The same seed delivers the same order, which can be replayed.
+        let shuffled = round(CompletionOrder::Seeded(7));
+        assert_eq!(round(CompletionOrder::Seeded(7)), shuffled);
+        assert_ne!(round(CompletionOrder::Fifo), shuffled);
+        let mut replay = shuffled.clone();
+        replay.retain(|user_data| *user_data != 99);
+        let replayed = round(CompletionOrder::Replay(replay.clone()));
+        assert_eq!(replayed[..8], replay[..]);
+        assert_eq!(replayed[8], 99);
+    }
+}
@@ -0,0 +1,192 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use crate::{cqueue, squeue, Driver, IoUringAsync, Lifecycle, Op, Slot, Slots};
+
+// The completion queue entries received by a multishot Op that have not yet
+// been yielded by its MultishotOp.
+pub(crate) struct Shots<C: cqueue::Entry> {
+    queue: VecDeque<C>,
+    // The task waiting for the next entry, which is woken by dispatch_completions.
+    pub(crate) waker: Option<Waker>,
+    // The final completion queue entry, which lacks IORING_CQE_F_MORE, has
+    // been received.
+    pub(crate) done: bool,
+    // The MultishotOp has been dropped, so the slot is freed as soon as the
+    // final completion queue entry is received.
+    detached: bool,
+    // Resources that the kernel may access until the final completion queue
+    // entry, which are freed along with the slot.
+    resources: Option<Box<dyn std::any::Any>>,
+}
+
+impl<C: cqueue::Entry> Shots<C> {
+    // Record a completion queue entry. Returns whether the slot should be freed.
+    pub(crate) fn receive(&mut self, cqe: C) -> bool {
+        self.done = !cqueue::has_more(&cqe);
+        if self.detached {
+            return self.done;
+        }
+        self.queue.push_back(cqe);
+        false
+    }
+}
+
+/// A stream of the completion queue entries posted by a multishot operation,
+/// such as a multishot accept, receive or poll. See [`IoUringAsync::push_multishot`].
+///
+/// The raw completion queue entries are yielded as-is, and can be inspected
+/// with the helpers in [`cqueue`], such as [`cqueue::buffer_id`]. The stream
+/// ends after the entry without `IORING_CQE_F_MORE` has been yielded. Dropping
+/// the stream before then cancels the operation.
+pub struct MultishotOp<C: cqueue::Entry> {
+    uring: Rc<dyn Driver<C>>,
+    slab: Rc<RefCell<Slots<C>>>,
+    index: usize,
+}
+
+impl<C: cqueue::Entry> MultishotOp<C> {
+    /// Poll for the next completion queue entry. This mirrors `Stream::poll_next`
+    /// from the futures crate.
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<C>> {
+        let mut guard = self.slab.borrow_mut();
+        let Lifecycle::Multishot(shots) = &mut guard[self.index].lifecycle else {
+            unreachable!("MultishotOp slot is not multishot");
+        };
+        if let Some(cqe) = shots.queue.pop_front() {
+            return Poll::Ready(Some(cqe));
+        }
+        if shots.done {
+            return Poll::Ready(None);
+        }
+        shots.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Wait for the next completion queue entry.
+    pub async fn next(&mut self) -> Option<C> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    // Take the resources attached to the operation, which is only safe once the
+    // final completion queue entry has been received.
+    pub(crate) fn take_resources(&mut self) -> Option<Box<dyn std::any::Any>> {
+        let mut guard = self.slab.borrow_mut();
+        let Lifecycle::Multishot(shots) = &mut guard[self.index].lifecycle else {
+            unreachable!("MultishotOp slot is not multishot");
+        };
+        assert!(shots.done, "resources taken from a MultishotOp before its final completion");
+        shots.resources.take()
+    }
+}
+
+impl<C: cqueue::Entry> Unpin for MultishotOp<C> {}
+
+impl<C: cqueue::Entry> Drop for MultishotOp<C> {
+    fn drop(&mut self) {
+        let mut guard = self.slab.borrow_mut();
+        let Lifecycle::Multishot(shots) = &mut guard[self.index].lifecycle else {
+            unreachable!("MultishotOp slot is not multishot");
+        };
+        if shots.done {
+            guard.remove(self.index);
+            return;
+        }
+        shots.detached = true;
+        shots.queue.clear();
+        drop(guard);
+
+        // The slot is freed by dispatch_completions once the kernel posts the
+        // final completion queue entry. If the cancel cannot be pushed, the
+        // operation is left to terminate on its own.
+        if let Ok(cancel) = crate::push_cancel(&self.uring, &self.slab, self.index) {
+            drop(Op { inner: Some(cancel) });
+        }
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Push a multishot entry, such as `AcceptMulti` or `RecvMulti`, yielding
+    /// every completion queue entry that the kernel posts for it.
+    pub fn push_multishot(&self, entry: impl Into<S>) -> MultishotOp<C> {
+        self.push_multishot_owning(entry.into(), None)
+    }
+
+    // Push a multishot entry along with resources that must outlive every
+    // completion queue entry it posts, including after the MultishotOp has been
+    // dropped.
+    pub(crate) fn push_multishot_owning(&self, entry: S, resources: Option<Box<dyn std::any::Any>>) -> MultishotOp<C> {
+        let mut guard = self.slab.borrow_mut();
+        let mut slot = Slot::new(None);
+        self.stamp(&mut slot);
+        slot.lifecycle = Lifecycle::Multishot(Shots {
+            queue: VecDeque::new(),
+            waker: None,
+            done: false,
+            detached: false,
+            resources,
+        });
+        let index = guard.insert(slot);
+        let entry = entry.user_data(index.try_into().unwrap());
+        self.uring.push_multiple(std::slice::from_ref(&entry)).unwrap();
+        MultishotOp {
+            uring: self.uring.clone(),
+            slab: self.slab.clone(),
+            index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use io_uring::{opcode, types};
+    use crate::{cqueue, IoUringAsync};
+
+    #[test]
+    fn multishot_poll() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let poll = opcode::PollAdd::new(types::Fd(a.as_raw_fd()), libc::POLLIN as u32)
+                .multi(true)
+                .build();
+            let mut stream = uring.push_multishot(poll);
+            b.write_all(b"ping").unwrap();
+            let cqe = stream.next().await.unwrap();
+            assert!(cqe.result() & libc::POLLIN as i32 != 0);
+            assert!(cqueue::has_more(&cqe));
+            assert_eq!(cqueue::buffer_id(&cqe), None);
+
+            let index = stream.index;
+            uring.push_cancel_index(index).await;
+            let cqe = stream.next().await.unwrap();
+            assert_eq!(cqe.result(), -libc::ECANCELED);
+            assert!(!cqueue::has_more(&cqe));
+            assert!(stream.next().await.is_none());
+            drop(stream);
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn multishot_cancel_on_drop() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let poll = opcode::PollAdd::new(types::Fd(a.as_raw_fd()), libc::POLLIN as u32)
+                .multi(true)
+                .build();
+            let stream = uring.push_multishot(poll);
+            uring.push(opcode::Nop::new().build()).await;
+            drop(stream);
+            uring.drain().await;
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+}
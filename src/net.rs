@@ -0,0 +1,689 @@
+use std::future::Future;
+use std::io;
+use std::mem::size_of;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+use io_uring::{opcode, types};
+use crate::{cqueue, squeue, IoUringAsync, OwnedOp};
+
+// The bind and listen opcodes, which are not exposed by the io_uring crate.
+const OP_BIND: u8 = 56;
+const OP_LISTEN: u8 = 57;
+// The IORING_OP_URING_CMD command of sockets that reads the socket name.
+const SOCKET_URING_OP_GETSOCKNAME: u32 = 5;
+
+// Encode a SocketAddr as a sockaddr that can be passed to the kernel.
+pub(crate) fn to_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr = libc::in_addr { s_addr: u32::from_ne_bytes(addr.ip().octets()) };
+            size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr = libc::in6_addr { s6_addr: addr.ip().octets() };
+            sin6.sin6_scope_id = addr.scope_id();
+            size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+// Decode a sockaddr that was written by the kernel, such as the name buffer of
+// a recvmsg. The `len` is the address length reported by the kernel.
+pub(crate) fn from_sockaddr(storage: &libc::sockaddr_storage, len: libc::socklen_t) -> io::Result<SocketAddr> {
+    let len = len as usize;
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET if len >= size_of::<libc::sockaddr_in>() => {
+            let sin = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes());
+            Ok(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(sin.sin_port))))
+        }
+        libc::AF_INET6 if len >= size_of::<libc::sockaddr_in6>() => {
+            let sin6 = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Ok(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(sin6.sin6_port), sin6.sin6_flowinfo, sin6.sin6_scope_id)))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported socket address"))
+    }
+}
+
+// The state of a recvmsg or sendmsg Op. It is boxed so that the pointers
+// between its fields remain valid while the kernel owns them.
+struct MsgState {
+    addr: libc::sockaddr_storage,
+    iovec: libc::iovec,
+    msghdr: libc::msghdr,
+    buf: Vec<u8>,
+    // The control message buffer. It is made up of u64 words so that it is
+    // suitably aligned for a cmsghdr.
+    control: Vec<u64>,
+}
+
+impl MsgState {
+    fn new(mut buf: Vec<u8>, addr: libc::sockaddr_storage, addr_len: libc::socklen_t, len: usize, control: Vec<u64>) -> Box<Self> {
+        let mut state = Box::new(MsgState {
+            addr,
+            iovec: libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: len },
+            msghdr: unsafe { std::mem::zeroed() },
+            buf,
+            control,
+        });
+        state.msghdr.msg_name = &mut state.addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+        state.msghdr.msg_namelen = addr_len;
+        state.msghdr.msg_iov = &mut state.iovec;
+        state.msghdr.msg_iovlen = 1;
+        if !state.control.is_empty() {
+            state.msghdr.msg_control = state.control.as_mut_ptr().cast();
+            state.msghdr.msg_controllen = state.control.len() * size_of::<u64>();
+        }
+        state
+    }
+}
+
+// The state of a vectored sendmsg Op, boxed for the same reason as MsgState.
+struct VectoredState {
+    addr: libc::sockaddr_storage,
+    iovecs: Vec<libc::iovec>,
+    msghdr: libc::msghdr,
+    bufs: Vec<Vec<u8>>,
+}
+
+impl VectoredState {
+    // Build the msghdr for sending `bufs`, to `target` if it is given.
+    fn new(bufs: Vec<Vec<u8>>, target: Option<SocketAddr>) -> Box<Self> {
+        let iovecs = bufs.iter()
+            .map(|buf| libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() })
+            .collect();
+        let (addr, addr_len) = match &target {
+            Some(target) => to_sockaddr(target),
+            None => (unsafe { std::mem::zeroed() }, 0),
+        };
+        let mut state = Box::new(VectoredState { addr, iovecs, msghdr: unsafe { std::mem::zeroed() }, bufs });
+        if target.is_some() {
+            state.msghdr.msg_name = &mut state.addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+            state.msghdr.msg_namelen = addr_len;
+        }
+        state.msghdr.msg_iov = state.iovecs.as_mut_ptr();
+        state.msghdr.msg_iovlen = state.iovecs.len();
+        state
+    }
+}
+
+// Allocate a zeroed control message buffer large enough for `nfds` file descriptors.
+fn fd_control_buffer(nfds: usize) -> Vec<u64> {
+    let space = unsafe { libc::CMSG_SPACE((nfds * size_of::<RawFd>()) as u32) } as usize;
+    vec![0; space.div_ceil(size_of::<u64>())]
+}
+
+// Take ownership of every file descriptor carried by SCM_RIGHTS control
+// messages in a msghdr that was filled in by recvmsg.
+fn parse_fds(msghdr: &libc::msghdr) -> Vec<OwnedFd> {
+    let mut fds = Vec::new();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msghdr) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+            let data = unsafe { libc::CMSG_DATA(cmsg) } as *const RawFd;
+            let len = header.cmsg_len - unsafe { libc::CMSG_LEN(0) } as usize;
+            for i in 0..len / size_of::<RawFd>() {
+                let fd = unsafe { data.add(i).read_unaligned() };
+                fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msghdr, cmsg) };
+    }
+    fds
+}
+
+/// A UDP socket whose sends and receives are performed by an io_uring.
+///
+/// Like [`IoUringAsync::push`], the operations of a `UdpSocket` are only queued
+/// on the submission queue. They are submitted to the kernel with the next call
+/// to [`IoUringAsync::submit`].
+pub struct UdpSocket<S: squeue::Entry = io_uring::squeue::Entry, C: cqueue::Entry = io_uring::cqueue::Entry> {
+    uring: Rc<IoUringAsync<S, C>>,
+    socket: std::net::UdpSocket,
+    poll_first: bool,
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> UdpSocket<S, C> {
+    /// Create a UDP socket bound to the given address.
+    pub fn bind(uring: Rc<IoUringAsync<S, C>>, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self::from_std(uring, std::net::UdpSocket::bind(addr)?))
+    }
+
+    /// Create a UDP socket from an existing standard library socket.
+    pub fn from_std(uring: Rc<IoUringAsync<S, C>>, socket: std::net::UdpSocket) -> Self {
+        Self { uring, socket, poll_first: false }
+    }
+
+    /// Set whether sends and receives are pushed with `IORING_RECVSEND_POLL_FIRST`.
+    /// See [`squeue::poll_first`].
+    pub fn set_poll_first(&mut self, poll_first: bool) {
+        self.poll_first = poll_first;
+    }
+
+    // Apply the socket's options to a send or receive entry.
+    fn prepare(&self, entry: io_uring::squeue::Entry) -> io_uring::squeue::Entry {
+        if self.poll_first {
+            squeue::poll_first(entry)
+        } else {
+            entry
+        }
+    }
+
+    /// Returns the local address that this socket is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Receive a single datagram into the spare capacity of `buf`. On success, the
+    /// length of `buf` is set to the number of bytes received and the source address
+    /// of the datagram is returned. The buffer is always handed back to the caller.
+    pub async fn recv_from(&self, mut buf: Vec<u8>) -> (io::Result<(usize, SocketAddr)>, Vec<u8>) {
+        buf.clear();
+        let len = buf.capacity();
+        let addr_len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        let mut state = MsgState::new(buf, unsafe { std::mem::zeroed() }, addr_len, len, Vec::new());
+        let entry = self.prepare(opcode::RecvMsg::new(types::Fd(self.socket.as_raw_fd()), &mut state.msghdr).build());
+        let (cqe, mut state) = OwnedOp::new(self.uring.push(entry), state).await;
+        let result = cqueue::result_to_io(cqe.result()).and_then(|n| {
+            unsafe { state.buf.set_len(n as usize) };
+            Ok((n as usize, from_sockaddr(&state.addr, state.msghdr.msg_namelen)?))
+        });
+        (result, state.buf)
+    }
+
+    /// Send the contents of `buf` as a single datagram to `target`. Returns the
+    /// number of bytes sent, handing the buffer back to the caller.
+    pub async fn send_to(&self, buf: Vec<u8>, target: SocketAddr) -> (io::Result<usize>, Vec<u8>) {
+        let len = buf.len();
+        let (addr, addr_len) = to_sockaddr(&target);
+        let state = MsgState::new(buf, addr, addr_len, len, Vec::new());
+        let entry = self.prepare(opcode::SendMsg::new(types::Fd(self.socket.as_raw_fd()), &state.msghdr).build());
+        let (cqe, state) = OwnedOp::new(self.uring.push(entry), state).await;
+        let result = cqueue::result_to_io(cqe.result()).map(|n| n as usize);
+        (result, state.buf)
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> AsRawFd for UdpSocket<S, C> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Accept a new connection on the listening socket `fd`. The accepted socket
+    /// is close-on-exec.
+    ///
+    /// If the kernel does not support `IORING_OP_ACCEPT` and the readiness fallback
+    /// is enabled (see [`Builder::readiness_fallback`](crate::builder::Builder::readiness_fallback)),
+    /// this waits for the listener to become readable and then calls `accept4`. In
+    /// that case the listener should be non-blocking, so that losing a race with
+    /// another acceptor does not block the thread.
+    pub async fn accept(&self, fd: RawFd) -> io::Result<OwnedFd> {
+        if self.use_fallback(opcode::Accept::CODE) {
+            return self.accept_readiness(fd).await;
+        }
+        let entry = opcode::Accept::new(types::Fd(fd), std::ptr::null_mut(), std::ptr::null_mut())
+            .flags(libc::SOCK_CLOEXEC)
+            .build();
+        let fd = cqueue::result_to_io(self.push(entry).await.result())?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+    }
+
+    async fn accept_readiness(&self, fd: RawFd) -> io::Result<OwnedFd> {
+        loop {
+            self.wait_ready(fd, libc::POLLIN as u32).await?;
+            let accepted = unsafe { libc::accept4(fd, std::ptr::null_mut(), std::ptr::null_mut(), libc::SOCK_CLOEXEC) };
+            if accepted >= 0 {
+                return Ok(unsafe { OwnedFd::from_raw_fd(accepted) });
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+        }
+    }
+
+    /// Bind the socket `fd` to `addr`.
+    ///
+    /// If the kernel does not support `IORING_OP_BIND` (Linux 6.11), as reported
+    /// by the probe, this calls `bind(2)` synchronously when it is called, and
+    /// the returned future is immediately ready. Binding does not wait on the
+    /// network, so the blocking call is short.
+    pub fn socket_bind(&self, fd: RawFd, addr: SocketAddr) -> impl Future<Output = io::Result<()>> {
+        let sockaddr = Box::new(to_sockaddr(&addr));
+        let op = if self.is_supported(OP_BIND) {
+            let mut entry = opcode::Nop::new().build();
+            let raw = squeue::raw_mut(&mut entry);
+            raw.opcode = OP_BIND;
+            raw.fd = fd;
+            raw.addr = &sockaddr.0 as *const libc::sockaddr_storage as u64;
+            raw.off = sockaddr.1 as u64;
+            Ok(OwnedOp::new(self.push(entry), sockaddr))
+        } else {
+            Err(bind_blocking(fd, &sockaddr))
+        };
+        async move {
+            match op {
+                Ok(op) => cqueue::result_to_io(op.await.0.result()).map(drop),
+                Err(result) => result,
+            }
+        }
+    }
+
+    /// Mark the bound socket `fd` as listening for connections, with a queue of
+    /// up to `backlog` pending connections.
+    ///
+    /// If the kernel does not support `IORING_OP_LISTEN` (Linux 6.11), as
+    /// reported by the probe, this calls `listen(2)` synchronously when it is
+    /// called, and the returned future is immediately ready.
+    pub fn socket_listen(&self, fd: RawFd, backlog: u32) -> impl Future<Output = io::Result<()>> {
+        let op = if self.is_supported(OP_LISTEN) {
+            let mut entry = opcode::Nop::new().build();
+            let raw = squeue::raw_mut(&mut entry);
+            raw.opcode = OP_LISTEN;
+            raw.fd = fd;
+            raw.len = backlog;
+            Ok(self.push(entry))
+        } else {
+            Err(listen_blocking(fd, backlog))
+        };
+        async move {
+            match op {
+                Ok(op) => cqueue::result_to_io(op.await.result()).map(drop),
+                Err(result) => result,
+            }
+        }
+    }
+
+    /// Returns the local address that the socket `fd` is bound to, like
+    /// [`std::net::TcpStream::local_addr`].
+    ///
+    /// This uses the `SOCKET_URING_OP_GETSOCKNAME` command of
+    /// `IORING_OP_URING_CMD` (Linux 6.19). If the kernel does not support it,
+    /// this calls `getsockname(2)` synchronously instead, which only costs the
+    /// rejected Op on kernels that support `IORING_OP_URING_CMD` but not the
+    /// command. Addresses other than IPv4 and IPv6 return an error of kind
+    /// `InvalidData`.
+    pub fn getsockname(&self, fd: RawFd) -> impl Future<Output = io::Result<SocketAddr>> {
+        self.socket_name(fd, false)
+    }
+
+    /// Returns the address of the peer that the socket `fd` is connected to,
+    /// like [`std::net::TcpStream::peer_addr`]. See
+    /// [`IoUringAsync::getsockname`] for how it is read.
+    pub fn getpeername(&self, fd: RawFd) -> impl Future<Output = io::Result<SocketAddr>> {
+        self.socket_name(fd, true)
+    }
+
+    fn socket_name(&self, fd: RawFd, peer: bool) -> impl Future<Output = io::Result<SocketAddr>> {
+        let op = if self.is_supported(opcode::UringCmd16::CODE) {
+            // The kernel writes the address and its length to the boxed
+            // storage, which is owned by the Op until it completes.
+            let name = Box::new((unsafe { std::mem::zeroed::<libc::sockaddr_storage>() }, size_of::<libc::sockaddr_storage>() as libc::socklen_t));
+            let mut entry = opcode::UringCmd16::new(types::Fd(fd), SOCKET_URING_OP_GETSOCKNAME).build();
+            let raw = squeue::raw_mut(&mut entry);
+            raw.addr = &name.0 as *const libc::sockaddr_storage as u64;
+            raw.addr3 = &name.1 as *const libc::socklen_t as u64;
+            // The optlen field, which shares its offset with file_index.
+            raw.file_index = peer as u32;
+            Some(OwnedOp::new(self.push(entry), name))
+        } else {
+            None
+        };
+        async move {
+            if let Some(op) = op {
+                let (cqe, name) = op.await;
+                match cqe.result() {
+                    res if res >= 0 => return from_sockaddr(&name.0, name.1),
+                    res if res != -libc::EOPNOTSUPP => return Err(io::Error::from_raw_os_error(-res)),
+                    _ => {}
+                }
+            }
+            sockname_blocking(fd, peer)
+        }
+    }
+
+    // Wait for `fd` to report any of the poll(2) `events`.
+    pub(crate) async fn wait_ready(&self, fd: RawFd, events: u32) -> io::Result<()> {
+        let cqe = self.push(opcode::PollAdd::new(types::Fd(fd), events).build()).await;
+        cqueue::result_to_io(cqe.result()).map(|_| ())
+    }
+
+    /// Send every buffer in `bufs` over the connected socket `fd` with a single
+    /// `sendmsg`, without first copying them into one buffer. Returns the total
+    /// number of bytes sent, which may be less than the combined length of the
+    /// buffers, handing the buffers back to the caller.
+    pub fn send_vectored(&self, fd: RawFd, bufs: Vec<Vec<u8>>) -> impl Future<Output = (io::Result<usize>, Vec<Vec<u8>>)> {
+        let state = VectoredState::new(bufs, None);
+        let entry = opcode::SendMsg::new(types::Fd(fd), &state.msghdr).build();
+        let op = OwnedOp::new(self.push(entry), state);
+        async move {
+            let (cqe, state) = op.await;
+            (cqueue::result_to_io(cqe.result()).map(|n| n as usize), state.bufs)
+        }
+    }
+
+    /// Send every buffer in `bufs` over the socket `fd` with a single zero-copy
+    /// `sendmsg` (`IORING_OP_SENDMSG_ZC`), to `target` if it is given, such as
+    /// for an unconnected UDP socket. Requires Linux 6.1.
+    ///
+    /// The kernel posts the result of the send, followed by a notification once
+    /// it no longer references the buffers, which may be long after the data
+    /// was sent. This resolves to the number of bytes sent only once the
+    /// notification has arrived, handing the buffers back to the caller. If the
+    /// future is dropped first, the buffers are kept alive until then.
+    pub fn sendmsg_zc(&self, fd: RawFd, bufs: Vec<Vec<u8>>, target: Option<SocketAddr>) -> impl Future<Output = (io::Result<usize>, Vec<Vec<u8>>)> {
+        let state = VectoredState::new(bufs, target);
+        let entry = opcode::SendMsgZc::new(types::Fd(fd), &state.msghdr).build();
+        let mut op = self.push_multishot_owning(entry.into(), Some(state));
+        async move {
+            let mut result = None;
+            while let Some(cqe) = op.next().await {
+                if !cqueue::is_notification(&cqe) {
+                    result = Some(cqueue::result_to_io(cqe.result()).map(|n| n as usize));
+                }
+            }
+            let state = op.take_resources().unwrap().downcast::<VectoredState>().unwrap();
+            (result.unwrap_or_else(|| Err(io::Error::other("sendmsg_zc did not post a result"))), state.bufs)
+        }
+    }
+
+    /// Send the file descriptors `fds` over the connected unix socket `sock` using
+    /// an `SCM_RIGHTS` control message. A single zero byte is sent alongside the
+    /// control message, since stream sockets cannot carry ancillary data alone.
+    /// Returns an `InvalidInput` error if `fds` is empty.
+    pub async fn send_fds(&self, sock: RawFd, fds: &[RawFd]) -> io::Result<()> {
+        if fds.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no file descriptors to send"));
+        }
+        let mut control = fd_control_buffer(fds.len());
+        unsafe {
+            let mut msghdr: libc::msghdr = std::mem::zeroed();
+            msghdr.msg_control = control.as_mut_ptr().cast();
+            msghdr.msg_controllen = control.len() * size_of::<u64>();
+            let cmsg = &mut *libc::CMSG_FIRSTHDR(&msghdr);
+            cmsg.cmsg_level = libc::SOL_SOCKET;
+            cmsg.cmsg_type = libc::SCM_RIGHTS;
+            cmsg.cmsg_len = libc::CMSG_LEN(std::mem::size_of_val(fds) as u32) as usize;
+            std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+
+        let state = MsgState::new(vec![0], unsafe { std::mem::zeroed() }, 0, 1, control);
+        let entry = opcode::SendMsg::new(types::Fd(sock), &state.msghdr).build();
+        let (cqe, _) = OwnedOp::new(self.push(entry), state).await;
+        cqueue::result_to_io(cqe.result()).map(|_| ())
+    }
+
+    /// Receive up to `max` file descriptors that were sent over the connected unix
+    /// socket `sock` with [`IoUringAsync::send_fds`]. The received descriptors are
+    /// close-on-exec. An error is returned if the peer sent more than `max`
+    /// descriptors, in which case every received descriptor is closed.
+    pub async fn recv_fds(&self, sock: RawFd, max: usize) -> io::Result<Vec<OwnedFd>> {
+        let mut state = MsgState::new(vec![0], unsafe { std::mem::zeroed() }, 0, 1, fd_control_buffer(max));
+        let entry = opcode::RecvMsg::new(types::Fd(sock), &mut state.msghdr)
+            .flags(libc::MSG_CMSG_CLOEXEC as u32)
+            .build();
+        let (cqe, state) = OwnedOp::new(self.push(entry), state).await;
+        let n = cqueue::result_to_io(cqe.result())?;
+        let fds = parse_fds(&state.msghdr);
+        // The control buffer is rounded up to a whole number of words, which
+        // may leave room for more than `max` descriptors.
+        if state.msghdr.msg_flags & libc::MSG_CTRUNC != 0 || fds.len() > max {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the peer sent more file descriptors than requested"));
+        }
+        if n == 0 && fds.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(fds)
+    }
+}
+
+fn bind_blocking(fd: RawFd, (addr, len): &(libc::sockaddr_storage, libc::socklen_t)) -> io::Result<()> {
+    if unsafe { libc::bind(fd, (addr as *const libc::sockaddr_storage).cast(), *len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn listen_blocking(fd: RawFd, backlog: u32) -> io::Result<()> {
+    if unsafe { libc::listen(fd, backlog.try_into().unwrap_or(libc::c_int::MAX)) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn sockname_blocking(fd: RawFd, peer: bool) -> io::Result<SocketAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let addr = (&mut storage as *mut libc::sockaddr_storage).cast();
+    let res = unsafe { if peer { libc::getpeername(fd, addr, &mut len) } else { libc::getsockname(fd, addr, &mut len) } };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    from_sockaddr(&storage, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd};
+    use std::rc::Rc;
+    use crate::IoUringAsync;
+    use super::{bind_blocking, from_sockaddr, listen_blocking, sockname_blocking, to_sockaddr, UdpSocket};
+
+    #[test]
+    fn sockaddr_round_trip() {
+        for addr in ["127.0.0.1:8080", "[::1]:443", "[fe80::1%2]:53"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let (storage, len) = to_sockaddr(&addr);
+            assert_eq!(from_sockaddr(&storage, len).unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn udp_send_recv() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let a = UdpSocket::bind(uring.clone(), "127.0.0.1:0").unwrap();
+            let b = UdpSocket::bind(uring.clone(), "127.0.0.1:0").unwrap();
+
+            let (sent, _) = a.send_to(b"hello".to_vec(), b.local_addr().unwrap()).await;
+            assert_eq!(sent.unwrap(), 5);
+
+            let (received, buf) = b.recv_from(Vec::with_capacity(64)).await;
+            let (n, source) = received.unwrap();
+            assert_eq!(n, 5);
+            assert_eq!(&buf[..], b"hello");
+            assert_eq!(source, a.local_addr().unwrap());
+        });
+    }
+
+    #[test]
+    fn udp_poll_first() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let a = UdpSocket::bind(uring.clone(), "127.0.0.1:0").unwrap();
+            let mut b = UdpSocket::bind(uring.clone(), "127.0.0.1:0").unwrap();
+            b.set_poll_first(true);
+            let target = b.local_addr().unwrap();
+
+            // The receive is pushed before anything has been sent, so the kernel
+            // polls the socket rather than attempting the receive.
+            let recv = tokio::task::spawn_local(async move {
+                b.recv_from(Vec::with_capacity(64)).await
+            });
+            tokio::task::yield_now().await;
+            let (sent, _) = a.send_to(b"hello".to_vec(), target).await;
+            assert_eq!(sent.unwrap(), 5);
+
+            let (received, buf) = recv.await.unwrap();
+            assert_eq!(received.unwrap().0, 5);
+            assert_eq!(&buf[..], b"hello");
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "send or receive entry")]
+    fn poll_first_requires_send_or_recv() {
+        crate::squeue::poll_first(io_uring::opcode::Nop::new().build());
+    }
+
+    #[test]
+    fn pass_fds() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (tx, rx) = UnixStream::pair().unwrap();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        let received = crate::tests::run(&uring, async {
+            uring.send_fds(tx.as_raw_fd(), &[a.as_raw_fd()]).await.unwrap();
+            uring.recv_fds(rx.as_raw_fd(), 4).await.unwrap()
+        });
+        assert_eq!(received.len(), 1);
+
+        // The received descriptor refers to the same socket as the one sent.
+        drop(a);
+        let mut a = UnixStream::from(received.into_iter().next().unwrap());
+        a.write_all(b"hi").unwrap();
+        let mut buf = [0; 2];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi");
+
+        // The control buffer for a single descriptor has room for two, which
+        // the kernel installs without truncating them.
+        crate::tests::run(&uring, async {
+            let err = uring.send_fds(tx.as_raw_fd(), &[]).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            uring.send_fds(tx.as_raw_fd(), &[b.as_raw_fd(), b.as_raw_fd()]).await.unwrap();
+            let err = uring.recv_fds(rx.as_raw_fd(), 1).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        });
+    }
+
+    #[test]
+    fn accept() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        crate::tests::run(&uring, async {
+            let client = TcpStream::connect(addr).unwrap();
+            let server = TcpStream::from(uring.accept(listener.as_raw_fd()).await.unwrap());
+            assert_eq!(server.peer_addr().unwrap(), client.local_addr().unwrap());
+
+            // Exercise the readiness-based fallback even though this kernel
+            // supports IORING_OP_ACCEPT.
+            let client = TcpStream::connect(addr).unwrap();
+            let server = TcpStream::from(uring.accept_readiness(listener.as_raw_fd()).await.unwrap());
+            assert_eq!(server.peer_addr().unwrap(), client.local_addr().unwrap());
+        });
+    }
+
+    #[test]
+    fn send_vectored() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, mut b) = UnixStream::pair().unwrap();
+        crate::tests::run(&uring, async {
+            let bufs = vec![b"header\n".to_vec(), Vec::new(), b"body".to_vec()];
+            let (n, bufs) = uring.send_vectored(a.as_raw_fd(), bufs).await;
+            assert_eq!(n.unwrap(), 11);
+            assert_eq!(bufs[2], b"body");
+        });
+        let mut buf = [0; 11];
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"header\nbody");
+    }
+
+    #[test]
+    fn sendmsg_zc() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = receiver.local_addr().unwrap();
+        crate::tests::run(&uring, async {
+            let bufs = vec![b"zero ".to_vec(), b"copy".to_vec()];
+            let (n, bufs) = uring.sendmsg_zc(sender.as_raw_fd(), bufs, Some(target)).await;
+            assert_eq!(n.unwrap(), 9);
+            assert_eq!(bufs[1], b"copy");
+
+            // An unconnected socket without a target fails, and there is
+            // nothing to notify.
+            let (n, _) = uring.sendmsg_zc(sender.as_raw_fd(), vec![b"x".to_vec()], None).await;
+            assert_eq!(n.unwrap_err().raw_os_error(), Some(libc::EDESTADDRREQ));
+
+            // A dropped send still completes in the background.
+            drop(uring.sendmsg_zc(sender.as_raw_fd(), vec![b"again".to_vec()], Some(target)));
+            uring.drain().await;
+            assert!(uring.slab.borrow().is_empty());
+        });
+        let mut buf = [0; 16];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 9);
+        assert_eq!(&buf[..9], b"zero copy");
+    }
+
+    #[test]
+    fn bind_listen() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let socket = tcp_socket();
+        let fd = socket.as_raw_fd();
+        crate::tests::run(&uring, async {
+            uring.socket_bind(fd, "127.0.0.1:0".parse().unwrap()).await.unwrap();
+            uring.socket_listen(fd, 16).await.unwrap();
+        });
+        let listener = TcpListener::from(socket);
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        assert_eq!(server.peer_addr().unwrap(), client.local_addr().unwrap());
+
+        // Exercise the synchronous fallback even though this kernel supports
+        // IORING_OP_BIND and IORING_OP_LISTEN.
+        let socket = tcp_socket();
+        let fd = socket.as_raw_fd();
+        bind_blocking(fd, &to_sockaddr(&"127.0.0.1:0".parse().unwrap())).unwrap();
+        listen_blocking(fd, u32::MAX).unwrap();
+        let listener = TcpListener::from(socket);
+        let addr = listener.local_addr().unwrap();
+        let err = bind_blocking(fd, &to_sockaddr(&addr)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+        TcpStream::connect(addr).unwrap();
+    }
+
+    #[test]
+    fn socket_names() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let (rx, _tx) = crate::fd::pipe().unwrap();
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.getsockname(listener.as_raw_fd()).await.unwrap(), listener.local_addr().unwrap());
+            assert_eq!(uring.getsockname(client.as_raw_fd()).await.unwrap(), client.local_addr().unwrap());
+            assert_eq!(uring.getpeername(client.as_raw_fd()).await.unwrap(), server.local_addr().unwrap());
+            assert_eq!(uring.getpeername(server.as_raw_fd()).await.unwrap(), client.local_addr().unwrap());
+            let err = uring.getpeername(listener.as_raw_fd()).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOTCONN));
+            let err = uring.getsockname(rx.as_raw_fd()).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOTSOCK));
+        });
+        assert_eq!(sockname_blocking(server.as_raw_fd(), true).unwrap(), client.local_addr().unwrap());
+    }
+
+    fn tcp_socket() -> OwnedFd {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        assert!(fd >= 0);
+        unsafe { OwnedFd::from_raw_fd(fd) }
+    }
+}
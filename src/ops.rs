@@ -0,0 +1,403 @@
+use std::future::Future;
+use std::io;
+use std::os::unix::prelude::RawFd;
+use io_uring::{opcode, types};
+use io_uring::squeue::Flags;
+use crate::squeue::IoPriority;
+use crate::files::FixedFile;
+use crate::{cqueue, squeue, IoUringAsync, OwnedOp};
+
+/// The file targeted by a [`Read`], a [`Write`] or a [`UringCmd`]. It may be
+/// created from a `RawFd`, or from a [`FixedFile`] or a `types::Fixed` to
+/// target a direct descriptor in the registered file table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RwTarget {
+    /// An open file descriptor.
+    Fd(RawFd),
+    /// A direct descriptor in the registered file table.
+    Fixed(u32),
+}
+
+impl From<RawFd> for RwTarget {
+    fn from(fd: RawFd) -> Self {
+        RwTarget::Fd(fd)
+    }
+}
+
+impl From<types::Fixed> for RwTarget {
+    fn from(fixed: types::Fixed) -> Self {
+        RwTarget::Fixed(fixed.0)
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> From<&FixedFile<'_, S, C>> for RwTarget {
+    fn from(file: &FixedFile<'_, S, C>) -> Self {
+        RwTarget::Fixed(file.slot())
+    }
+}
+
+// The options shared by the read and write builders.
+struct RwOpts {
+    offset: u64,
+    rw_flags: types::RwFlags,
+    ioprio: Option<IoPriority>,
+    flags: Flags,
+}
+
+impl RwOpts {
+    fn new(rw_flags: types::RwFlags) -> Self {
+        Self { offset: 0, rw_flags, ioprio: None, flags: Flags::empty() }
+    }
+
+    // Apply the options that the opcode builders do not set themselves.
+    fn prepare(&self, mut entry: io_uring::squeue::Entry) -> io_uring::squeue::Entry {
+        if let Some(priority) = self.ioprio {
+            entry = squeue::ioprio(entry, priority);
+        }
+        entry.flags(self.flags)
+    }
+}
+
+// Define the option setters shared by the read and write builders.
+macro_rules! rw_setters {
+    () => {
+        /// Read or write at `offset`. Defaults to zero, which must be used for
+        /// descriptors that are not seekable. `u64::MAX` uses and advances the
+        /// file position, like `read(2)` and `write(2)`.
+        pub fn offset(mut self, offset: u64) -> Self {
+            self.opts.offset = offset;
+            self
+        }
+
+        /// Set the `RWF_*` flags, as accepted by `preadv2(2)` and `pwritev2(2)`.
+        /// This replaces the ring's default flags, set with
+        /// [`Builder::rw_flags`](crate::builder::Builder::rw_flags).
+        pub fn rw_flags(mut self, rw_flags: types::RwFlags) -> Self {
+            self.opts.rw_flags = rw_flags;
+            self
+        }
+
+        /// Set the I/O priority of the operation.
+        pub fn ioprio(mut self, priority: IoPriority) -> Self {
+            self.opts.ioprio = Some(priority);
+            self
+        }
+
+        /// Flag the entry with `IO_LINK`, so that the next entry pushed onto the
+        /// ring only starts once this one has completed successfully. The chain
+        /// may be split if the submission queue fills up in between, so
+        /// [`IoUringAsync::link`] should be preferred for chains that must not be.
+        pub fn link(mut self) -> Self {
+            self.opts.flags |= Flags::IO_LINK;
+            self
+        }
+
+        /// Flag the entry with `IO_DRAIN`, so that it only starts once every
+        /// previously submitted entry has completed.
+        pub fn drain(mut self) -> Self {
+            self.opts.flags |= Flags::IO_DRAIN;
+            self
+        }
+
+        /// Flag the entry with `ASYNC`, so that the kernel punts it to an io-wq
+        /// worker rather than first attempting it inline.
+        pub fn force_async(mut self) -> Self {
+            self.opts.flags |= Flags::ASYNC;
+            self
+        }
+    };
+}
+
+/// A builder for a read into an owned buffer, created with
+/// [`IoUringAsync::read`].
+#[must_use = "the read is not pushed until `push` is called"]
+pub struct Read<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    fd: RwTarget,
+    buf: Vec<u8>,
+    opts: RwOpts,
+}
+
+impl<'a, S: squeue::Entry, C: cqueue::Entry> Read<'a, S, C> {
+    rw_setters!();
+
+    /// Push the read onto the submission queue. Resolves to the number of bytes
+    /// read, handing the buffer back to the caller. The buffer is kept alive
+    /// until the read completes, even if the future is dropped.
+    pub fn push(self) -> impl Future<Output = (io::Result<usize>, Vec<u8>)> {
+        let Read { uring, fd, mut buf, opts } = self;
+        let read = match fd {
+            RwTarget::Fd(fd) => opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32),
+            RwTarget::Fixed(slot) => opcode::Read::new(types::Fixed(slot), buf.as_mut_ptr(), buf.len() as u32),
+        };
+        let entry = read
+            .offset64(opts.offset as libc::off64_t)
+            .rw_flags(opts.rw_flags)
+            .build();
+        let op = OwnedOp::new(uring.push(opts.prepare(entry)), buf);
+        async move {
+            let (cqe, buf) = op.await;
+            (cqueue::result_to_io(cqe.result()).map(|n| n as usize), buf)
+        }
+    }
+}
+
+/// A builder for a write from an owned buffer, created with
+/// [`IoUringAsync::write`].
+#[must_use = "the write is not pushed until `push` is called"]
+pub struct Write<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    fd: RwTarget,
+    buf: Vec<u8>,
+    opts: RwOpts,
+}
+
+impl<'a, S: squeue::Entry, C: cqueue::Entry> Write<'a, S, C> {
+    rw_setters!();
+
+    /// Push the write onto the submission queue. Resolves to the number of bytes
+    /// written, handing the buffer back to the caller. The buffer is kept alive
+    /// until the write completes, even if the future is dropped.
+    pub fn push(self) -> impl Future<Output = (io::Result<usize>, Vec<u8>)> {
+        let Write { uring, fd, buf, opts } = self;
+        let write = match fd {
+            RwTarget::Fd(fd) => opcode::Write::new(types::Fd(fd), buf.as_ptr(), buf.len() as u32),
+            RwTarget::Fixed(slot) => opcode::Write::new(types::Fixed(slot), buf.as_ptr(), buf.len() as u32),
+        };
+        let entry = write
+            .offset64(opts.offset as libc::off64_t)
+            .rw_flags(opts.rw_flags)
+            .build();
+        let op = OwnedOp::new(uring.push(opts.prepare(entry)), buf);
+        async move {
+            let (cqe, buf) = op.await;
+            (cqueue::result_to_io(cqe.result()).map(|n| n as usize), buf)
+        }
+    }
+}
+
+/// The largest command of a [`UringCmd`] that fits in a 64-byte submission
+/// queue entry.
+pub const URING_CMD_INLINE_LEN: usize = 16;
+
+/// The largest command of a [`UringCmd`], which requires a ring with 128-byte
+/// submission queue entries (`IORING_SETUP_SQE128`).
+pub const URING_CMD_MAX_LEN: usize = 80;
+
+/// A builder for a file-specific command (`IORING_OP_URING_CMD`), such as an
+/// NVMe passthrough, ublk or socket command, created with
+/// [`IoUringAsync::uring_cmd`].
+///
+/// The command is copied into the submission queue entry, so it does not need
+/// to outlive the push. Memory that the command refers to by address, such as
+/// the data buffer of an NVMe passthrough command, must stay valid until the
+/// command completes.
+#[must_use = "the command is not pushed until `push` is called"]
+pub struct UringCmd<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    fd: RwTarget,
+    cmd_op: u32,
+    cmd: [u8; URING_CMD_MAX_LEN],
+    len: usize,
+    flags: Flags,
+}
+
+/// The completion of a [`UringCmd`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UringCmdOutput {
+    /// The result of the command, whose meaning depends on the command.
+    pub result: u32,
+    /// The extra 16 bytes of a 32-byte completion queue entry, which commands
+    /// such as NVMe passthrough use to return data, or `None` if the ring has
+    /// 16-byte completion queue entries.
+    pub big_cqe: Option<[u64; 2]>,
+}
+
+impl<'a, S: squeue::Entry, C: cqueue::Entry> UringCmd<'a, S, C> {
+    /// Set the command, which defaults to zeroes. Commands of up to
+    /// [`URING_CMD_INLINE_LEN`] bytes fit in any ring, while longer ones, up to
+    /// [`URING_CMD_MAX_LEN`] bytes, require a ring with 128-byte submission
+    /// queue entries. Otherwise, `push` fails with an `InvalidInput` error.
+    pub fn cmd(mut self, cmd: &[u8]) -> Self {
+        self.len = cmd.len();
+        if let Some(dst) = self.cmd.get_mut(..cmd.len()) {
+            dst.copy_from_slice(cmd);
+        }
+        self
+    }
+
+    /// Flag the entry with `IO_LINK`, so that the next entry pushed onto the
+    /// ring only starts once this one has completed successfully.
+    pub fn link(mut self) -> Self {
+        self.flags |= Flags::IO_LINK;
+        self
+    }
+
+    /// Flag the entry with `ASYNC`, so that the kernel punts it to an io-wq
+    /// worker rather than first attempting it inline.
+    pub fn force_async(mut self) -> Self {
+        self.flags |= Flags::ASYNC;
+        self
+    }
+
+    /// Push the command onto the submission queue. Resolves to the result of
+    /// the command, including the extra data of a 32-byte completion queue
+    /// entry.
+    ///
+    /// Fails immediately with an `InvalidInput` error if the command does not
+    /// fit in the ring's submission queue entries, or with an `Unsupported`
+    /// error if the kernel does not support `IORING_OP_URING_CMD` (Linux 5.19),
+    /// as reported by the probe.
+    pub fn push(self) -> impl Future<Output = io::Result<UringCmdOutput>> {
+        let op = self.build().map(|entry| self.uring.push(entry.flags(self.flags)));
+        async move {
+            let cqe = op?.await;
+            let result = cqueue::result_to_io(cqe.result())?;
+            Ok(UringCmdOutput { result, big_cqe: cqe.big_cqe().copied() })
+        }
+    }
+
+    fn build(&self) -> io::Result<S> {
+        if !self.uring.is_supported(opcode::UringCmd16::CODE) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "the kernel does not support IORING_OP_URING_CMD"));
+        }
+        if self.len > URING_CMD_MAX_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("a uring_cmd command is at most {} bytes, got {}", URING_CMD_MAX_LEN, self.len)));
+        }
+        // The 80-byte form is used on any ring with 128-byte entries, since the
+        // kernel reads the whole command area of such entries.
+        let entry = match self.fd {
+            RwTarget::Fd(fd) => opcode::UringCmd80::new(types::Fd(fd), self.cmd_op),
+            RwTarget::Fixed(slot) => opcode::UringCmd80::new(types::Fixed(slot), self.cmd_op),
+        };
+        if let Some(entry) = S::from_entry128(entry.cmd(self.cmd).build()) {
+            return Ok(entry);
+        }
+        if self.len > URING_CMD_INLINE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("a uring_cmd command of {} bytes requires a ring with 128-byte submission queue entries", self.len),
+            ));
+        }
+        let cmd = self.cmd[..URING_CMD_INLINE_LEN].try_into().unwrap();
+        let entry = match self.fd {
+            RwTarget::Fd(fd) => opcode::UringCmd16::new(types::Fd(fd), self.cmd_op),
+            RwTarget::Fixed(slot) => opcode::UringCmd16::new(types::Fixed(slot), self.cmd_op),
+        };
+        Ok(entry.cmd(cmd).build().into())
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Create a [`Read`] builder that reads from `fd` into the whole of `buf`.
+    /// The builder borrows `fd` if it is a [`FixedFile`], so that its descriptor
+    /// is not closed before the read is pushed.
+    pub fn read<'a>(&'a self, fd: impl Into<RwTarget> + 'a, buf: Vec<u8>) -> Read<'a, S, C> {
+        Read { uring: self, fd: fd.into(), buf, opts: RwOpts::new(self.rw_flags) }
+    }
+
+    /// Create a [`Write`] builder that writes the whole of `buf` to `fd`.
+    /// The builder borrows `fd` if it is a [`FixedFile`], so that its descriptor
+    /// is not closed before the write is pushed.
+    pub fn write<'a>(&'a self, fd: impl Into<RwTarget> + 'a, buf: Vec<u8>) -> Write<'a, S, C> {
+        Write { uring: self, fd: fd.into(), buf, opts: RwOpts::new(self.rw_flags) }
+    }
+
+    /// Create a [`UringCmd`] builder for the command `cmd_op` of the file `fd`,
+    /// which must implement `uring_cmd`, such as an NVMe character device, a
+    /// ublk control device or a socket.
+    pub fn uring_cmd(&self, fd: impl Into<RwTarget>, cmd_op: u32) -> UringCmd<'_, S, C> {
+        UringCmd { uring: self, fd: fd.into(), cmd_op, cmd: [0; URING_CMD_MAX_LEN], len: 0, flags: Flags::empty() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, Write};
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use io_uring::cqueue::Entry32;
+    use io_uring::squeue::Entry128;
+    use crate::squeue::IoPriority;
+    use crate::IoUringAsync;
+    use super::{UringCmdOutput, URING_CMD_INLINE_LEN, URING_CMD_MAX_LEN};
+
+    #[test]
+    fn read_write() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let mut file = tempfile();
+        file.write_all(b"hello world").unwrap();
+        let fd = file.as_raw_fd();
+        crate::tests::run(&uring, async {
+            let (n, buf) = uring.read(fd, vec![0; 5]).offset(6).ioprio(IoPriority::BestEffort(4)).push().await;
+            assert_eq!(n.unwrap(), 5);
+            assert_eq!(buf, b"world");
+
+            // The write is linked to the read, which only starts once it is done.
+            let write = uring.write(fd, b"HELLO".to_vec()).link().push();
+            let read = uring.read(fd, vec![0; 11]).push();
+            assert_eq!(write.await.0.unwrap(), 5);
+            let (n, buf) = read.await;
+            assert_eq!(n.unwrap(), 11);
+            assert_eq!(buf, b"HELLO world");
+
+            let (n, _) = uring.write(fd, b"!".to_vec()).offset(u64::MAX).force_async().drain().push().await;
+            assert_eq!(n.unwrap(), 1);
+        });
+        assert_eq!(file.stream_position().unwrap(), 12);
+    }
+
+    #[test]
+    fn default_rw_flags() {
+        let uring = Rc::new(IoUringAsync::builder().rw_flags(libc::RWF_APPEND).build(8).unwrap());
+        assert_eq!(uring.default_rw_flags(), libc::RWF_APPEND);
+        let mut file = tempfile();
+        file.write_all(b"hello").unwrap();
+        let fd = file.as_raw_fd();
+        crate::tests::run(&uring, async {
+            // The default appends, even though the write is at offset 0.
+            assert_eq!(uring.write(fd, b"!".to_vec()).push().await.0.unwrap(), 1);
+            assert_eq!(uring.write(fd, b"J".to_vec()).rw_flags(0).push().await.0.unwrap(), 1);
+            let (n, buf) = uring.read(fd, vec![0; 6]).rw_flags(0).push().await;
+            assert_eq!(n.unwrap(), 6);
+            assert_eq!(buf, b"Jello!");
+        });
+    }
+
+    #[test]
+    fn uring_cmd() {
+        // SOCKET_URING_OP_SIOCINQ, which returns the number of bytes that are
+        // queued for reading on a socket and ignores the command.
+        const SIOCINQ: u32 = 0;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut tx = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (rx, _) = listener.accept().unwrap();
+        tx.write_all(b"hello").unwrap();
+
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let output = uring.uring_cmd(rx.as_raw_fd(), SIOCINQ).cmd(&[1; URING_CMD_INLINE_LEN]).push().await.unwrap();
+            assert_eq!(output, UringCmdOutput { result: 5, big_cqe: None });
+            let err = uring.uring_cmd(rx.as_raw_fd(), SIOCINQ).cmd(&[1; URING_CMD_INLINE_LEN + 1]).push().await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            let err = uring.uring_cmd(rx.as_raw_fd(), u32::MAX).push().await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP));
+        });
+
+        let uring = Rc::new(IoUringAsync::<Entry128, Entry32>::generic_new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let output = uring.uring_cmd(rx.as_raw_fd(), SIOCINQ).cmd(&[1; URING_CMD_MAX_LEN]).push().await.unwrap();
+            assert_eq!(output, UringCmdOutput { result: 5, big_cqe: Some([0, 0]) });
+            let err = uring.uring_cmd(rx.as_raw_fd(), SIOCINQ).cmd(&[1; URING_CMD_MAX_LEN + 1]).push().await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        });
+    }
+
+    fn tempfile() -> std::fs::File {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("io-uring-async-ops-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+}
@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use crate::{cqueue, squeue, IoUringAsync, OpInner};
+
+/// An Op whose entry borrows memory rather than owning it, such as a buffer on
+/// the stack. Created by [`IoUringAsync::scoped`].
+///
+/// Dropping a `ScopedOp` before it completes cancels the Op and blocks the
+/// current thread until the kernel is done with it, processing completions
+/// itself, so that the borrow cannot end while the kernel may still access the
+/// memory. This blocks the executor for the duration of the cancellation, which
+/// is usually short, but can be long for an Op that the kernel cannot interrupt,
+/// such as a read of a regular file that is already in progress.
+#[must_use = "the Op is canceled when the ScopedOp is dropped"]
+pub struct ScopedOp<'b, C: cqueue::Entry> {
+    inner: Option<OpInner<C>>,
+    _borrow: PhantomData<&'b mut ()>,
+}
+
+impl<C: cqueue::Entry> Future for ScopedOp<'_, C> {
+    type Output = C;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<C> {
+        Pin::new(self.inner.as_mut().unwrap()).poll(cx)
+    }
+}
+
+impl<C: cqueue::Entry> std::fmt::Debug for ScopedOp<'_, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScopedOp").field("index", &self.inner.as_ref().unwrap().index).finish()
+    }
+}
+
+impl<C: cqueue::Entry> Drop for ScopedOp<'_, C> {
+    fn drop(&mut self) {
+        let inner = self.inner.take().unwrap();
+        if inner.is_completed() {
+            return;
+        }
+        // Letting the borrow end while the kernel may still write to the
+        // memory would be a use after free, which aborting prevents. A panic
+        // would unwind past the borrow, so it cannot be used instead.
+        if !inner.cancel_and_reap() {
+            std::process::abort();
+        }
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Push the entry built by `build` from a mutable borrow of `borrowed`, such
+    /// as a buffer on the stack, returning a [`ScopedOp`] that keeps `borrowed`
+    /// borrowed until it is dropped. This is an alternative to the owned buffers
+    /// of helpers such as [`IoUringAsync::read`] for memory that cannot be moved
+    /// into the Op, at the cost of blocking the thread if the Op is dropped
+    /// before it completes.
+    ///
+    /// If the Op cannot be reaped when the `ScopedOp` is dropped, because the
+    /// ring fails to submit the cancel request or to wait for completions, the
+    /// process is aborted, since the kernel may still access `borrowed` once
+    /// the borrow has ended.
+    ///
+    /// # Safety
+    ///
+    /// The `ScopedOp` must be dropped or awaited to completion before the borrow
+    /// ends, so it must not be leaked, such as with `std::mem::forget` or in a
+    /// reference cycle. The entry must not refer to memory other than
+    /// `borrowed` that lives for less than the `ScopedOp`.
+    pub unsafe fn scoped<'b, T: ?Sized>(&self, borrowed: &'b mut T, build: impl FnOnce(&mut T) -> S) -> ScopedOp<'b, C> {
+        // The OpInner is moved out of the Op, whose Drop would find it missing.
+        let mut op = std::mem::ManuallyDrop::new(self.push(build(borrowed)));
+        ScopedOp { inner: op.inner.take(), _borrow: PhantomData }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use io_uring::{opcode, types};
+    use crate::IoUringAsync;
+
+    #[test]
+    fn scoped() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (rx, tx) = crate::fd::pipe().unwrap();
+        let read = |buf: &mut [u8; 4]| opcode::Read::new(types::Fd(rx.as_raw_fd()), buf.as_mut_ptr(), 4).build();
+        crate::tests::run(&uring, async {
+            let mut buf = [0u8; 4];
+            let op = unsafe { uring.scoped(&mut buf, read) };
+            uring.write(tx.as_raw_fd(), b"ping".to_vec()).push().await.0.unwrap();
+            assert_eq!(op.await.result(), 4);
+            assert_eq!(&buf, b"ping");
+
+            // Dropping the Op before it completes cancels it before returning.
+            let mut buf = [0u8; 4];
+            let op = unsafe { uring.scoped(&mut buf, read) };
+            uring.submit().unwrap();
+            drop(op);
+            assert!(uring.slab.borrow().is_empty());
+            uring.write(tx.as_raw_fd(), b"pong".to_vec()).push().await.0.unwrap();
+            assert_eq!(buf, [0; 4]);
+        });
+    }
+}
@@ -0,0 +1,296 @@
+use std::future::Future;
+use std::os::unix::prelude::RawFd;
+use std::pin::pin;
+use std::task::Poll;
+use std::time::Duration;
+use io_uring::opcode::AsyncCancel;
+use crate::{cqueue, squeue, Driver, IoUringAsync};
+
+// IORING_ASYNC_CANCEL_ANY, which is not exposed by the io_uring crate. It
+// matches every in-flight request rather than a specific user_data.
+const ASYNC_CANCEL_ANY: u32 = 1 << 2;
+// IORING_ASYNC_CANCEL_ALL and IORING_ASYNC_CANCEL_FD, which are not exposed by
+// the io_uring crate. Together they match every request on a file descriptor.
+const ASYNC_CANCEL_ALL: u32 = 1 << 0;
+const ASYNC_CANCEL_FD: u32 = 1 << 1;
+
+/// The outcome of [`IoUringAsync::shutdown`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ShutdownReport {
+    /// The number of Ops that the kernel canceled.
+    pub canceled: usize,
+    /// The number of Ops that completed while draining, including those that
+    /// were canceled.
+    pub drained: usize,
+    /// The number of Ops that were still in flight when the timeout elapsed.
+    /// The kernel may still use their resources, which must not be freed.
+    pub leaked: usize,
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Wait until the ring has no Ops in flight. Completions must be processed
+    /// while waiting, for example by [`IoUringAsync::listen`], and queued entries
+    /// must be submitted for the Ops to complete.
+    pub async fn drain(&self) {
+        std::future::poll_fn(|cx| {
+            if self.in_flight() == 0 {
+                return Poll::Ready(());
+            }
+            self.uring.drain_wakers.borrow_mut().push(cx.waker().clone());
+            Poll::Pending
+        }).await
+    }
+
+    /// Cancel every Op that is in flight when the cancel request is processed by
+    /// the kernel, using `IORING_ASYNC_CANCEL_ANY`. Resolves to the number of Ops
+    /// that were canceled. Each canceled Op completes with `-ECANCELED`, and
+    /// [`IoUringAsync::drain`] can be used to wait for all of them. Requires
+    /// Linux 5.19.
+    pub fn cancel_all(&self) -> impl Future<Output = usize> {
+        let mut entry = AsyncCancel::new(0).build();
+        squeue::raw_mut(&mut entry).op_flags = ASYNC_CANCEL_ANY;
+        let op = self.push(entry);
+        async move {
+            // The kernel reports -ENOENT if nothing was canceled.
+            usize::try_from(op.await.result()).unwrap_or(0)
+        }
+    }
+
+    /// Cancel every Op on the file descriptor `fd` that is in flight when the
+    /// cancel request is processed by the kernel, with a single entry using
+    /// `IORING_ASYNC_CANCEL_FD | IORING_ASYNC_CANCEL_ALL`. This tears down the
+    /// pending reads, writes and polls of a socket before it is closed.
+    /// Resolves to the number of Ops that were canceled, each of which
+    /// completes with `-ECANCELED`. Requires Linux 5.19.
+    pub fn cancel_fd_all(&self, fd: RawFd) -> impl Future<Output = usize> {
+        let mut entry = AsyncCancel::new(0).build();
+        let raw = squeue::raw_mut(&mut entry);
+        raw.fd = fd;
+        raw.op_flags = ASYNC_CANCEL_FD | ASYNC_CANCEL_ALL;
+        let op = self.push(entry);
+        async move {
+            // The kernel reports -ENOENT if nothing was canceled.
+            usize::try_from(op.await.result()).unwrap_or(0)
+        }
+    }
+
+    /// Wait until the ring has no Ops in flight, or until `timeout` elapses. If
+    /// the timeout elapses, every Op that is still in flight is canceled, and this
+    /// waits for the canceled Ops to complete. Returns the number of Ops that were
+    /// forcibly canceled.
+    ///
+    /// Ops that the kernel cannot cancel, such as a regular file read that is
+    /// already in progress, are still waited for, so that the kernel is done
+    /// with every resource once this returns. Requires the tokio runtime to have
+    /// the time driver enabled.
+    pub async fn drain_timeout(&self, timeout: Duration) -> usize {
+        let mut drain = pin!(self.drain());
+        let mut sleep = pin!(tokio::time::sleep(timeout));
+        let timed_out = std::future::poll_fn(|cx| {
+            if drain.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(false);
+            }
+            sleep.as_mut().poll(cx).map(|_| true)
+        }).await;
+        if !timed_out {
+            return 0;
+        }
+
+        let stragglers: Vec<usize> = self.slab.borrow().iter()
+            .filter(|(_, slot)| !slot.is_completed())
+            .map(|(index, _)| index)
+            .collect();
+        let cancels: Vec<_> = stragglers.into_iter().map(|index| self.push_cancel_index(index)).collect();
+        self.submit().ok();
+
+        let mut canceled = 0;
+        for cancel in cancels {
+            if cancel.await.result() == 0 {
+                canceled += 1;
+            }
+        }
+        self.drain().await;
+        canceled
+    }
+
+    /// Tear down the ring in the order that avoids leaking descriptors or
+    /// freeing memory the kernel still uses: every Op in flight is canceled,
+    /// and the canceled Ops are drained until none is left or `timeout`
+    /// elapses. Then [`IoUringAsync::listen`] is stopped, and the registered
+    /// files and buffers are unregistered.
+    ///
+    /// Completions must be processed while draining, which is done by the
+    /// listen loop, so it must be running until this returns. Once it has
+    /// returned, any later call to `listen` returns immediately. Requires the
+    /// tokio runtime to have the time driver enabled.
+    pub async fn shutdown(&self, timeout: Duration) -> std::io::Result<ShutdownReport> {
+        let in_flight = self.in_flight();
+        let canceled = self.cancel_all().await;
+        let mut drain = pin!(self.drain());
+        let mut sleep = pin!(tokio::time::sleep(timeout));
+        std::future::poll_fn(|cx| {
+            if drain.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(());
+            }
+            sleep.as_mut().poll(cx)
+        }).await;
+        let leaked = self.in_flight();
+
+        self.uring.listen_stopped.set(true);
+        for waker in self.uring.listen_wakers.take() {
+            waker.wake();
+        }
+        match self.unregister_files() {
+            Err(err) if err.raw_os_error() != Some(libc::ENXIO) => return Err(err),
+            _ => {},
+        }
+        if !self.fixed_buffers.borrow().is_empty() {
+            self.unregister_buffers()?;
+        }
+        Ok(ShutdownReport { canceled, drained: in_flight.saturating_sub(leaked), leaked })
+    }
+
+    /// Submit queued entries and block the current thread until the ring has no
+    /// Ops in flight, processing completions as they arrive. Unlike
+    /// [`IoUringAsync::drain`], this does not require an async context or a tokio
+    /// runtime, so it can be used from a `Drop` impl to ensure that the kernel is
+    /// done with every buffer before it is freed.
+    ///
+    /// Ops that will not complete on their own, such as a receive on an idle
+    /// socket or a multishot Op, must be canceled first, for example with
+    /// [`IoUringAsync::cancel_all`], or this blocks forever.
+    pub fn reap_blocking(&self) -> std::io::Result<()> {
+        while self.in_flight() > 0 {
+            self.uring.submit_and_wait(1)?;
+            crate::dispatch_completions(&*self.uring, &self.slab);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+    use io_uring::{opcode, types};
+    use crate::IoUringAsync;
+    use super::ShutdownReport;
+
+    #[test]
+    fn drain() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let ops: Vec<_> = (0..4).map(|_| uring.push(opcode::Nop::new().build())).collect();
+            uring.drain().await;
+            assert_eq!(uring.in_flight(), 0);
+            drop(ops);
+        });
+    }
+
+    #[test]
+    fn drain_timeout() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut buf = [0u8; 16];
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.drain_timeout(Duration::from_millis(10)).await, 0);
+
+            let recv = uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), buf.as_mut_ptr(), 16).build());
+            let nop = uring.push(opcode::Nop::new().build());
+            assert_eq!(uring.drain_timeout(Duration::from_millis(20)).await, 1);
+            assert_eq!(uring.in_flight(), 0);
+            assert_eq!(recv.await.result(), -libc::ECANCELED);
+            assert_eq!(nop.await.result(), 0);
+        });
+    }
+
+    #[test]
+    fn cancel_all() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut bufs = [[0u8; 16]; 2];
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.cancel_all().await, 0);
+
+            let recvs: Vec<_> = bufs.iter_mut().map(|buf| {
+                uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), buf.as_mut_ptr(), 16).build())
+            }).collect();
+            uring.submit().unwrap();
+            assert_eq!(uring.cancel_all().await, 2);
+            for recv in recvs {
+                assert_eq!(recv.await.result(), -libc::ECANCELED);
+            }
+            uring.drain().await;
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+
+    #[test]
+    fn cancel_fd_all() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        let (c, _d) = UnixStream::pair().unwrap();
+        let mut bufs = [[0u8; 16]; 3];
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.cancel_fd_all(a.as_raw_fd()).await, 0);
+
+            let [x, y, z] = &mut bufs;
+            let recvs: Vec<_> = [x, y].into_iter().map(|buf| {
+                uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), buf.as_mut_ptr(), 16).build())
+            }).collect();
+            let other = uring.push(opcode::Recv::new(types::Fd(c.as_raw_fd()), z.as_mut_ptr(), 16).build());
+            uring.submit().unwrap();
+            assert_eq!(uring.cancel_fd_all(a.as_raw_fd()).await, 2);
+            for recv in recvs {
+                assert_eq!(recv.await.result(), -libc::ECANCELED);
+            }
+            assert_eq!(uring.in_flight(), 1);
+            assert_eq!(uring.cancel_fd_all(c.as_raw_fd()).await, 1);
+            assert_eq!(other.await.result(), -libc::ECANCELED);
+        });
+    }
+
+    #[test]
+    fn shutdown() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        uring.register_files_sparse(4).unwrap();
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut bufs = [[0u8; 16]; 2];
+        crate::tests::run(&uring, async {
+            let recvs: Vec<_> = bufs.iter_mut().map(|buf| {
+                uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), buf.as_mut_ptr(), 16).build())
+            }).collect();
+            uring.submit().unwrap();
+            let report = uring.shutdown(Duration::from_secs(1)).await.unwrap();
+            assert_eq!(report, ShutdownReport { canceled: 2, drained: 2, leaked: 0 });
+            for recv in recvs {
+                assert_eq!(recv.await.result(), -libc::ECANCELED);
+            }
+            // The listen loop has been stopped, so a new one returns at once.
+            tokio::task::spawn_local(IoUringAsync::listen(uring.clone())).await.unwrap();
+        });
+        assert!(uring.slab.borrow().is_empty());
+        assert_eq!(uring.unregister_files().unwrap_err().raw_os_error(), Some(libc::ENXIO));
+    }
+
+    #[test]
+    fn reap_blocking() {
+        let uring = IoUringAsync::new(8).unwrap();
+        uring.reap_blocking().unwrap();
+
+        let timespec = types::Timespec::new().nsec(10_000_000);
+        let start = Instant::now();
+        let timeout = uring.push(opcode::Timeout::new(&timespec).build());
+        let nops: Vec<_> = (0..4).map(|_| uring.push(opcode::Nop::new().build())).collect();
+        uring.reap_blocking().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(uring.in_flight(), 0);
+
+        // Every Op has completed, so dropping them does not need a runtime.
+        drop(timeout);
+        drop(nops);
+        assert!(uring.slab.borrow().is_empty());
+    }
+}
@@ -1,5 +1,14 @@
 pub trait Entry: io_uring::squeue::EntryMarker + 'static + From<io_uring::squeue::Entry> {
     fn user_data(self, user_data: u64) -> Self;
+    fn flags(self, flags: io_uring::squeue::Flags) -> Self;
+    fn opcode(&self) -> u8;
+
+    /// Convert a 128-byte entry, such as one built by `UringCmd80`, into this
+    /// entry type, or return `None` if entries of this type are 64 bytes.
+    fn from_entry128(entry: io_uring::squeue::Entry128) -> Option<Self> {
+        let _ = entry;
+        None
+    }
 }
 
 impl Entry for io_uring::squeue::Entry {
@@ -7,6 +16,17 @@ impl Entry for io_uring::squeue::Entry {
     fn user_data(self, user_data: u64) -> Self {
         self.user_data(user_data)
     }
+
+    #[inline(always)]
+    fn flags(self, flags: io_uring::squeue::Flags) -> Self {
+        self.flags(flags)
+    }
+
+    #[inline(always)]
+    fn opcode(&self) -> u8 {
+        // The opcode is the first byte of the kernel's submission queue entry.
+        unsafe { *(self as *const Self).cast::<u8>() }
+    }
 }
 
 impl Entry for io_uring::squeue::Entry128 {
@@ -14,4 +34,179 @@ impl Entry for io_uring::squeue::Entry128 {
     fn user_data(self, user_data: u64) -> Self {
         self.user_data(user_data)
     }
+
+    #[inline(always)]
+    fn flags(self, flags: io_uring::squeue::Flags) -> Self {
+        self.flags(flags)
+    }
+
+    #[inline(always)]
+    fn opcode(&self) -> u8 {
+        // The opcode is the first byte of the kernel's submission queue entry.
+        unsafe { *(self as *const Self).cast::<u8>() }
+    }
+
+    #[inline(always)]
+    fn from_entry128(entry: io_uring::squeue::Entry128) -> Option<Self> {
+        Some(entry)
+    }
+}
+
+// The layout of a 64-byte submission queue entry as defined by the kernel ABI.
+// This allows fields that are not exposed by the io_uring opcode builders to be
+// set on an entry after it has been built.
+#[repr(C)]
+#[allow(dead_code)]
+pub(crate) struct RawEntry {
+    pub(crate) opcode: u8,
+    pub(crate) flags: u8,
+    pub(crate) ioprio: u16,
+    pub(crate) fd: i32,
+    pub(crate) off: u64,
+    pub(crate) addr: u64,
+    pub(crate) len: u32,
+    pub(crate) op_flags: u32,
+    pub(crate) user_data: u64,
+    pub(crate) buf_index: u16,
+    pub(crate) personality: u16,
+    pub(crate) file_index: u32,
+    pub(crate) addr3: u64,
+    pub(crate) pad: u64,
+}
+
+const _: () = assert!(std::mem::size_of::<RawEntry>() == std::mem::size_of::<io_uring::squeue::Entry>());
+
+pub(crate) fn raw_mut(entry: &mut io_uring::squeue::Entry) -> &mut RawEntry {
+    unsafe { &mut *(entry as *mut io_uring::squeue::Entry as *mut RawEntry) }
+}
+
+// Read the user_data of an entry. Both entry sizes begin with the 64-byte
+// layout of RawEntry.
+pub(crate) fn user_data_of<S: Entry>(entry: &S) -> u64 {
+    unsafe { (*(entry as *const S).cast::<RawEntry>()).user_data }
+}
+
+// IORING_RECVSEND_POLL_FIRST, which is not exposed by the io_uring crate.
+const RECVSEND_POLL_FIRST: u16 = 1 << 0;
+// IORING_RECVSEND_FIXED_BUF and IORING_URING_CMD_FIXED, which mark zero-copy
+// sends and passthrough commands that use a registered buffer.
+const RECVSEND_FIXED_BUF: u16 = 1 << 2;
+const URING_CMD_FIXED: u32 = 1 << 0;
+
+/// The registered resources that an Op was pushed with, as reported by
+/// [`Op::fixed_resources`](crate::Op::fixed_resources). Ops that use registered
+/// resources skip looking up the file, or pinning and mapping the buffer, on
+/// every operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FixedResources {
+    /// The entry referred to a direct descriptor (`IOSQE_FIXED_FILE`) rather
+    /// than a file descriptor.
+    pub file: bool,
+    /// The entry used a registered buffer, such as `ReadFixed`, `WriteFixed`,
+    /// or a zero-copy send with `IORING_RECVSEND_FIXED_BUF`.
+    pub buffer: bool,
+}
+
+// Determine the registered resources used by an entry from its opcode and
+// flags. Both entry sizes begin with the 64-byte layout of RawEntry.
+pub(crate) fn fixed_resources_of<S: Entry>(entry: &S) -> FixedResources {
+    use io_uring::opcode;
+    let raw = unsafe { &*(entry as *const S).cast::<RawEntry>() };
+    let buffer = match raw.opcode {
+        opcode::ReadFixed::CODE | opcode::WriteFixed::CODE => true,
+        opcode::SendZc::CODE | opcode::SendMsgZc::CODE => raw.ioprio & RECVSEND_FIXED_BUF != 0,
+        opcode::UringCmd16::CODE => raw.op_flags & URING_CMD_FIXED != 0,
+        _ => false,
+    };
+    FixedResources { file: raw.flags & io_uring::squeue::Flags::FIXED_FILE.bits() != 0, buffer }
+}
+
+/// Set `IORING_RECVSEND_POLL_FIRST` on a send or receive entry, such as `Send`,
+/// `Recv`, `SendMsg` or `RecvMsg`. The kernel then waits for the socket to be
+/// ready before attempting the operation, rather than first attempting it
+/// speculatively. This avoids wasted work on sockets that are mostly idle.
+///
+/// # Panics
+///
+/// Panics if `entry` is not a send or receive entry.
+pub fn poll_first(mut entry: io_uring::squeue::Entry) -> io_uring::squeue::Entry {
+    use io_uring::opcode;
+    let raw = raw_mut(&mut entry);
+    assert!(
+        [opcode::Send::CODE, opcode::Recv::CODE, opcode::SendMsg::CODE, opcode::RecvMsg::CODE,
+            opcode::SendZc::CODE, opcode::SendMsgZc::CODE].contains(&raw.opcode),
+        "poll_first requires a send or receive entry"
+    );
+    raw.ioprio |= RECVSEND_POLL_FIRST;
+    entry
+}
+
+/// An I/O priority, as used by `ioprio_set(2)`, which the block layer uses to
+/// order requests competing for the same device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// The real-time class, with a level from 0 (highest) to 7 (lowest).
+    RealTime(u8),
+    /// The best-effort class, with a level from 0 (highest) to 7 (lowest).
+    BestEffort(u8),
+    /// The idle class, which is only served when the device is otherwise idle.
+    Idle,
+}
+
+impl IoPriority {
+    /// Returns the raw ioprio encoding, with the class in the top three bits
+    /// and the level in the rest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the level is larger than 7.
+    pub fn to_raw(self) -> u16 {
+        const CLASS_SHIFT: u16 = 13;
+        let (class, level) = match self {
+            IoPriority::RealTime(level) => (1, level),
+            IoPriority::BestEffort(level) => (2, level),
+            IoPriority::Idle => (3, 0),
+        };
+        assert!(level <= 7, "ioprio level must be between 0 and 7");
+        (class << CLASS_SHIFT) | level as u16
+    }
+}
+
+/// Set the I/O priority of a read or write entry, such as `Read`, `Write`,
+/// `ReadFixed` or `WriteFixed`.
+///
+/// # Panics
+///
+/// Panics if `entry` is not a read or write entry.
+pub fn ioprio(mut entry: io_uring::squeue::Entry, priority: IoPriority) -> io_uring::squeue::Entry {
+    use io_uring::opcode;
+    let raw = raw_mut(&mut entry);
+    assert!(
+        [opcode::Read::CODE, opcode::Write::CODE, opcode::ReadFixed::CODE, opcode::WriteFixed::CODE,
+            opcode::Readv::CODE, opcode::Writev::CODE].contains(&raw.opcode),
+        "ioprio requires a read or write entry"
+    );
+    raw.ioprio = priority.to_raw();
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use io_uring::{opcode, types};
+    use super::IoPriority;
+
+    #[test]
+    fn ioprio() {
+        assert_eq!(IoPriority::RealTime(3).to_raw(), 1 << 13 | 3);
+        assert_eq!(IoPriority::BestEffort(7).to_raw(), 2 << 13 | 7);
+        assert_eq!(IoPriority::Idle.to_raw(), 3 << 13);
+        let mut entry = super::ioprio(opcode::Read::new(types::Fd(0), std::ptr::null_mut(), 0).build(), IoPriority::Idle);
+        assert_eq!(super::raw_mut(&mut entry).ioprio, 3 << 13);
+    }
+
+    #[test]
+    #[should_panic(expected = "ioprio requires a read or write entry")]
+    fn ioprio_not_rw() {
+        super::ioprio(opcode::Nop::new().build(), IoPriority::Idle);
+    }
 }
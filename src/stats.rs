@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Statistics describing how an [`IoUringAsync`](crate::IoUringAsync) has been
+/// used. A snapshot can be taken with [`IoUringAsync::stats`](crate::IoUringAsync::stats).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RingStats {
+    /// The number of submission queue entries pushed onto the submission queue.
+    pub pushed: u64,
+    /// The number of submission queue entries consumed by the kernel.
+    pub submitted: u64,
+    /// The number of `io_uring_enter` calls made to submit entries or wait
+    /// for completions.
+    pub submit_calls: u64,
+    /// The number of those calls that were interrupted by a signal before
+    /// submitting anything, and were therefore retried.
+    pub interrupted_submits: u64,
+    /// The number of completion queue entries whose user_data did not belong to
+    /// any in-flight Op, and were therefore discarded.
+    pub orphan_completions: u64,
+    /// The number of completion queue entries for an Op that had already
+    /// completed, which were therefore discarded. This happens if an entry that
+    /// posts multiple completions is pushed as a regular Op rather than with
+    /// [`IoUringAsync::push_multishot`](crate::IoUringAsync::push_multishot).
+    pub unexpected_completions: u64,
+    /// The number of Ops whose latency was split into [`RingStats::sq_time`]
+    /// and [`RingStats::kernel_time`]. These are only recorded if enabled with
+    /// [`Builder::latency_stats`](crate::builder::Builder::latency_stats).
+    pub latency_samples: u64,
+    /// The total time that those Ops spent between being pushed and being
+    /// submitted to the kernel, which is the delay added by batching
+    /// submissions.
+    pub sq_time: Duration,
+    /// The total time that those Ops spent between being submitted to the
+    /// kernel and their first completion being processed, which includes any
+    /// io-wq queueing.
+    pub kernel_time: Duration,
+}
+
+impl RingStats {
+    /// The average number of submission queue entries submitted per
+    /// `io_uring_enter` call. A low ratio indicates that entries are being
+    /// submitted more often than necessary. Returns 0 if nothing has been
+    /// submitted yet.
+    pub fn sqes_per_submit(&self) -> f64 {
+        if self.submit_calls == 0 {
+            0.0
+        } else {
+            self.submitted as f64 / self.submit_calls as f64
+        }
+    }
+
+    /// The mean of [`RingStats::sq_time`] per latency sample, or `None` if no
+    /// samples have been recorded.
+    pub fn mean_sq_time(&self) -> Option<Duration> {
+        self.sq_time.checked_div(self.latency_samples.try_into().ok()?)
+    }
+
+    /// The mean of [`RingStats::kernel_time`] per latency sample, or `None` if
+    /// no samples have been recorded.
+    pub fn mean_kernel_time(&self) -> Option<Duration> {
+        self.kernel_time.checked_div(self.latency_samples.try_into().ok()?)
+    }
+}
+
+// The times at which entries were submitted to the kernel, which are recorded
+// when latency stats are enabled. The kernel consumes the submission queue in
+// order, so the user_data of the entries that were submitted can be recovered
+// from the number that were.
+#[derive(Debug, Default)]
+pub(crate) struct SubmitTimes {
+    // The user_data of every entry pushed but not yet submitted, in order.
+    queued: VecDeque<u64>,
+    // The entries submitted since their times were last taken.
+    submitted: Vec<(u64, Instant)>,
+}
+
+impl SubmitTimes {
+    pub(crate) fn pushed(&mut self, user_data: u64) {
+        self.queued.push_back(user_data);
+    }
+
+    pub(crate) fn submitted(&mut self, count: usize) {
+        let now = Instant::now();
+        let count = count.min(self.queued.len());
+        self.submitted.extend(self.queued.drain(..count).map(|user_data| (user_data, now)));
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<(u64, Instant)> {
+        std::mem::take(&mut self.submitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::time::Duration;
+    use io_uring::opcode::Nop;
+    use crate::IoUringAsync;
+
+    #[test]
+    fn sqes_per_submit() {
+        let uring = IoUringAsync::new(8).unwrap();
+        assert_eq!(uring.stats().sqes_per_submit(), 0.0);
+
+        let ops: Vec<_> = (0..3).map(|_| uring.push(Nop::new().build())).collect();
+        uring.submit().unwrap();
+        let stats = uring.stats();
+        assert_eq!(stats.pushed, 3);
+        assert_eq!(stats.submitted, 3);
+        assert_eq!(stats.submit_calls, 1);
+        assert_eq!(stats.sqes_per_submit(), 3.0);
+        drop(ops);
+    }
+
+    #[test]
+    fn latency_stats() {
+        let uring = Rc::new(IoUringAsync::builder().latency_stats().build(8).unwrap());
+        assert_eq!(uring.stats().mean_sq_time(), None);
+        crate::tests::run(&uring, async {
+            let op = uring.push(Nop::new().build());
+            std::thread::sleep(Duration::from_millis(20));
+            op.await;
+            uring.push(Nop::new().build()).await;
+        });
+        let stats = uring.stats();
+        assert_eq!(stats.latency_samples, 2);
+        // The first Op sat in the submission queue while the thread slept.
+        assert!(stats.sq_time >= Duration::from_millis(20));
+        assert!(stats.kernel_time < stats.sq_time);
+        assert_eq!(stats.mean_sq_time(), Some(stats.sq_time / 2));
+
+        // Latency stats are off by default.
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, uring.push(Nop::new().build()));
+        assert_eq!(uring.stats().latency_samples, 0);
+    }
+}
@@ -0,0 +1,109 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use crate::{cqueue, Op};
+
+/// A stream of completion queue entries produced by re-arming a single-shot
+/// operation each time it completes. See [`repeat`].
+pub struct Repeat<C: cqueue::Entry, F: FnMut() -> Op<C>> {
+    push: F,
+    op: Option<Op<C>>,
+}
+
+/// Create a stream that pushes a fresh Op with `push` every time the previous
+/// one completes, yielding each completion queue entry. This gives multishot-like
+/// ergonomics for opcodes or kernels that lack true multishot support. For
+/// example, `repeat(|| uring.push(accept_sqe()))` yields every accepted connection.
+///
+/// The next Op is pushed as soon as the previous one completes, without waiting
+/// for the stream to be polled again. Dropping the stream cancels the in-flight Op.
+pub fn repeat<C: cqueue::Entry, F: FnMut() -> Op<C>>(push: F) -> Repeat<C, F> {
+    Repeat { push, op: None }
+}
+
+impl<C: cqueue::Entry, F: FnMut() -> Op<C>> Repeat<C, F> {
+    /// Poll for the next completion queue entry. This mirrors `Stream::poll_next`
+    /// from the futures crate. The stream never terminates.
+    pub fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<C>> {
+        let this = &mut *self;
+        let op = this.op.get_or_insert_with(&mut this.push);
+        match Pin::new(op).poll(cx) {
+            Poll::Ready(cqe) => {
+                this.op = Some((this.push)());
+                Poll::Ready(Some(cqe))
+            }
+            Poll::Pending => Poll::Pending
+        }
+    }
+
+    /// Wait for the next completion queue entry.
+    pub async fn next(&mut self) -> Option<C> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<C: cqueue::Entry, F: FnMut() -> Op<C>> Unpin for Repeat<C, F> {}
+
+impl<C: cqueue::Entry, F: FnMut() -> Op<C>> Drop for Repeat<C, F> {
+    fn drop(&mut self) {
+        if let Some(op) = self.op.take() {
+            op.cancel_detached();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::Poll;
+    use io_uring::{opcode, types};
+    use crate::IoUringAsync;
+    use super::repeat;
+
+    #[test]
+    fn repeat_nop() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let mut pushed = 0;
+            let mut stream = repeat(|| {
+                pushed += 1;
+                uring.push(opcode::Nop::new().build())
+            });
+            for _ in 0..3 {
+                assert_eq!(stream.next().await.unwrap().result(), 0);
+            }
+            drop(stream);
+            assert_eq!(pushed, 4);
+        });
+    }
+
+    #[test]
+    fn repeat_cancel_on_drop() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut buf = [0u8; 16];
+        let ptr = buf.as_mut_ptr();
+        crate::tests::run(&uring, async {
+            let mut stream = repeat(|| {
+                uring.push(opcode::Recv::new(types::Fd(a.as_raw_fd()), ptr, 16).build())
+            });
+            std::future::poll_fn(|cx| {
+                assert!(Pin::new(&mut stream).poll_next(cx).is_pending());
+                Poll::Ready(())
+            }).await;
+            drop(stream);
+
+            // The recv can only complete by being canceled.
+            for _ in 0..10 {
+                if uring.slab.borrow().is_empty() {
+                    break;
+                }
+                uring.push(opcode::Nop::new().build()).await;
+            }
+            assert!(uring.slab.borrow().is_empty());
+        });
+    }
+}
@@ -0,0 +1,122 @@
+//! Waiting for the completion of any Op pushed with a given tag, see
+//! [`IoUringAsync::await_tag`].
+
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use crate::{cqueue, squeue, IoUringAsync, Ring};
+
+struct Waiter<C: cqueue::Entry> {
+    tag: u64,
+    // Whether an entry has been received, which is then taken by the poll.
+    done: bool,
+    cqe: Option<C>,
+    waker: Option<Waker>,
+}
+
+// The tasks waiting for an Op with a given tag to complete.
+pub(crate) struct TagWaiters<C: cqueue::Entry> {
+    waiters: slab::Slab<Waiter<C>>,
+}
+
+impl<C: cqueue::Entry> Default for TagWaiters<C> {
+    fn default() -> Self {
+        Self { waiters: slab::Slab::new() }
+    }
+}
+
+impl<C: cqueue::Entry> TagWaiters<C> {
+    // Hand a completion queue entry of an Op with `tag` to every waiter for the
+    // tag that has not yet received one, returning the wakers of their tasks.
+    pub(crate) fn complete(&mut self, tag: u64, cqe: &C) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        for (_, waiter) in self.waiters.iter_mut() {
+            if waiter.tag == tag && !waiter.done {
+                waiter.done = true;
+                waiter.cqe = Some(cqe.clone());
+                wakers.extend(waiter.waker.take());
+            }
+        }
+        wakers
+    }
+}
+
+/// A future that resolves to the completion queue entry of the first Op with a
+/// given tag that completes. See [`IoUringAsync::await_tag`].
+pub struct TagWait<S: squeue::Entry, C: cqueue::Entry> {
+    uring: Rc<Ring<S, C>>,
+    index: usize,
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Future for TagWait<S, C> {
+    type Output = C;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<C> {
+        let mut waiters = self.uring.tag_waiters.borrow_mut();
+        let waiter = &mut waiters.waiters[self.index];
+        if waiter.done {
+            return Poll::Ready(waiter.cqe.take().expect("TagWait polled after completion"));
+        }
+        waiter.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> Drop for TagWait<S, C> {
+    fn drop(&mut self) {
+        self.uring.tag_waiters.borrow_mut().waiters.remove(self.index);
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Wait for an Op pushed with `tag`, such as with [`IoUringAsync::push_tagged`]
+    /// or [`IoUringAsync::push_with_tagged`], to complete, resolving to a copy
+    /// of its completion queue entry. The Op still receives the entry as well.
+    ///
+    /// The wait starts when this is called rather than when the future is first
+    /// polled, and only completions processed after that are observed. If
+    /// several Ops share the tag, the first of them to complete wins, and
+    /// later completions are ignored. Completions must be processed while
+    /// waiting, for example by [`IoUringAsync::listen`].
+    pub fn await_tag(&self, tag: u64) -> TagWait<S, C> {
+        let index = self.uring.tag_waiters.borrow_mut().waiters.insert(Waiter { tag, done: false, cqe: None, waker: None });
+        TagWait { uring: self.uring.clone(), index }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use io_uring::opcode::Nop;
+    use crate::IoUringAsync;
+
+    #[test]
+    fn await_tag() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            // The first completion with the tag wins, even that of a callback.
+            let wait = uring.await_tag(7);
+            let other = uring.await_tag(8);
+            let called = Rc::new(Cell::new(None));
+            let user_data = called.clone();
+            uring.push_with_tagged(Nop::new().build(), 7, move |cqe| user_data.set(Some(cqe.user_data())));
+            let op = uring.push_tagged(Nop::new().build(), 7);
+            let cqe = wait.await;
+            assert_eq!(Some(cqe.user_data()), called.get());
+            assert_ne!(op.await.user_data(), cqe.user_data());
+
+            // A wait only observes completions after it starts.
+            let op = uring.push_tagged(Nop::new().build(), 8);
+            op.await;
+            let wait = uring.await_tag(8);
+            uring.push_tagged(Nop::new().build(), 9).await;
+            let op = uring.push_tagged(Nop::new().build(), 8);
+            let cqe = wait.await;
+            assert_eq!(op.await.user_data(), cqe.user_data());
+            drop(other);
+            assert!(uring.uring.tag_waiters.borrow().waiters.is_empty());
+        });
+    }
+}
@@ -0,0 +1,76 @@
+//! Helpers for testing code that is built on top of this crate. These are only
+//! available with the `test-helpers` feature.
+
+use std::io;
+use std::sync::OnceLock;
+use io_uring::opcode::Nop;
+use crate::{cqueue, squeue, IoUringAsync, Op};
+
+// Instructs the kernel to complete a Nop with the result stored in the
+// entry's len field.
+const IORING_NOP_INJECT_RESULT: u32 = 1 << 0;
+
+// Build a Nop that completes with the result `res` on kernels that support
+// IORING_NOP_INJECT_RESULT.
+fn nop_entry(res: i32) -> io_uring::squeue::Entry {
+    let mut entry = Nop::new().build();
+    let raw = squeue::raw_mut(&mut entry);
+    raw.op_flags = IORING_NOP_INJECT_RESULT;
+    raw.len = res as u32;
+    entry
+}
+
+// Returns whether the kernel injects the result of a Nop, which older kernels
+// ignore, completing the Nop with a result of 0. This is checked once, with a
+// ring of its own.
+fn nop_result_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        let Ok(mut uring) = io_uring::IoUring::new(1) else {
+            return false;
+        };
+        if unsafe { uring.submission().push(&nop_entry(1)) }.is_err() || uring.submit_and_wait(1).is_err() {
+            return false;
+        }
+        let cqe = uring.completion().next();
+        cqe.is_some_and(|cqe| cqe.result() == 1)
+    })
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Push a Nop that completes with the result `res`, which can be used to
+    /// simulate errors without performing real IO. Injecting a result requires
+    /// `IORING_NOP_INJECT_RESULT` (Linux 6.10). Older kernels would complete the
+    /// Nop with a result of 0, so an `Unsupported` error is returned instead.
+    pub fn nop_with_result(&self, res: i32) -> io::Result<Op<C>> {
+        if !self.supports_nop_result() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "the kernel does not support IORING_NOP_INJECT_RESULT"));
+        }
+        Ok(self.push(nop_entry(res)))
+    }
+
+    /// Returns whether [`IoUringAsync::nop_with_result`] is supported by the
+    /// kernel, so that tests that depend on it can be skipped otherwise.
+    pub fn supports_nop_result(&self) -> bool {
+        nop_result_supported()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::IoUringAsync;
+
+    #[test]
+    fn nop_with_result() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        if !uring.supports_nop_result() {
+            assert_eq!(uring.nop_with_result(42).unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+            return;
+        }
+        crate::tests::run(&uring, async {
+            assert_eq!(uring.nop_with_result(42).unwrap().await.result(), 42);
+            assert_eq!(uring.nop_with_result(-libc::EINVAL).unwrap().await.result(), -libc::EINVAL);
+        });
+    }
+}
@@ -0,0 +1,243 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use io_uring::{opcode, types};
+use crate::{cqueue, squeue, IoUringAsync, Op, OwnedOp};
+
+// The timeout clock flags, which are not exposed by the io_uring crate.
+const TIMEOUT_BOOTTIME: u32 = 1 << 2;
+const TIMEOUT_REALTIME: u32 = 1 << 3;
+
+// Convert a Duration into the timespec representation used by the kernel.
+pub(crate) fn timespec(duration: Duration) -> types::Timespec {
+    types::Timespec::new()
+        .sec(duration.as_secs())
+        .nsec(duration.subsec_nanos())
+}
+
+// Convert an Instant into an absolute CLOCK_MONOTONIC timespec. Instant is
+// backed by CLOCK_MONOTONIC on Linux, so the result can be used with
+// IORING_TIMEOUT_ABS.
+pub(crate) fn monotonic_timespec(instant: Instant) -> types::Timespec {
+    timespec(Clock::Monotonic.now() + instant.saturating_duration_since(Instant::now()))
+}
+
+/// The clock that a timeout is measured against.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// `CLOCK_MONOTONIC`, which does not advance while the system is suspended.
+    #[default]
+    Monotonic,
+    /// `CLOCK_BOOTTIME`, which includes time spent suspended.
+    Boottime,
+    /// `CLOCK_REALTIME`, the wall-clock time, which is also advanced across
+    /// suspend and may jump when the system time is set.
+    Realtime,
+}
+
+impl Clock {
+    /// Returns the current time of the clock, as the duration since its epoch.
+    /// This can be used to compute absolute timeouts.
+    pub fn now(self) -> Duration {
+        let id = match self {
+            Clock::Monotonic => libc::CLOCK_MONOTONIC,
+            Clock::Boottime => libc::CLOCK_BOOTTIME,
+            Clock::Realtime => libc::CLOCK_REALTIME,
+        };
+        let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { libc::clock_gettime(id, &mut now) };
+        Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+    }
+
+    fn timeout_flags(self) -> u32 {
+        match self {
+            Clock::Monotonic => 0,
+            Clock::Boottime => TIMEOUT_BOOTTIME,
+            Clock::Realtime => TIMEOUT_REALTIME,
+        }
+    }
+}
+
+/// Options for [`IoUringAsync::timeout`]. The default is a relative timeout
+/// measured against the monotonic clock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutOpts {
+    /// Interpret the time as an absolute time of the clock, as returned by
+    /// [`Clock::now`], rather than relative to when the timeout starts
+    /// (`IORING_TIMEOUT_ABS`).
+    pub absolute: bool,
+    /// The clock that the timeout is measured against.
+    pub clock: Clock,
+}
+
+/// The error returned when an operation was canceled because its deadline
+/// elapsed before it completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineExceeded;
+
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("deadline exceeded")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+impl From<DeadlineExceeded> for std::io::Error {
+    fn from(err: DeadlineExceeded) -> Self {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, err)
+    }
+}
+
+/// A future that completes once a duration has elapsed, created with
+/// [`IoUringAsync::sleep`]. Dropping it before then cancels the timeout.
+pub struct Sleep<C: cqueue::Entry> {
+    op: Option<Op<C>>,
+}
+
+impl<C: cqueue::Entry> Future for Sleep<C> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let op = self.op.as_mut().expect("Sleep polled after completion");
+        match Pin::new(op).poll(cx) {
+            Poll::Ready(_) => {
+                self.op = None;
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<C: cqueue::Entry> Drop for Sleep<C> {
+    fn drop(&mut self) {
+        if let Some(op) = self.op.take() {
+            op.cancel_detached();
+        }
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> IoUringAsync<S, C> {
+    /// Sleep for `duration` using a `Timeout` on the ring, without depending on
+    /// tokio's timer. Like any Op, the timeout is only started once it has been
+    /// submitted.
+    pub fn sleep(&self, duration: Duration) -> Sleep<C> {
+        let timespec = Box::new(timespec(duration));
+        let mut op = self.push(opcode::Timeout::new(&*timespec).build());
+        op.inner.as_mut().unwrap().resources = Some(timespec);
+        Sleep { op: Some(op) }
+    }
+
+    /// Push a timeout that completes after `time`, or at `time` if `opts` makes
+    /// it absolute. Resolves to `Ok` when the timeout expires, or to the error
+    /// that completed it otherwise, such as `ECANCELED`.
+    pub fn timeout(&self, time: Duration, opts: TimeoutOpts) -> impl Future<Output = std::io::Result<()>> {
+        let timespec = Box::new(timespec(time));
+        let flags = if opts.absolute { types::TimeoutFlags::ABS } else { types::TimeoutFlags::empty() };
+        let mut entry = opcode::Timeout::new(&*timespec).flags(flags).build();
+        squeue::raw_mut(&mut entry).op_flags |= opts.clock.timeout_flags();
+        let op = OwnedOp::new(self.push(entry), timespec);
+        async move {
+            let (cqe, _) = op.await;
+            match cqe.result() {
+                result if result == -libc::ETIME => Ok(()),
+                result => cqueue::result_to_io(result).map(drop),
+            }
+        }
+    }
+
+    /// Push an entry that is canceled by the kernel if it has not completed by
+    /// `deadline`. The entry is linked to an absolute `LinkTimeout`, so any time
+    /// the entry spends waiting to be submitted counts against the deadline.
+    ///
+    /// Resolves to [`DeadlineExceeded`] if the entry was canceled because the
+    /// deadline elapsed. Any other result, including cancellation for another
+    /// reason, is returned as the entry's completion queue entry.
+    pub fn with_deadline(&self, entry: impl Into<S>, deadline: Instant) -> impl Future<Output = Result<C, DeadlineExceeded>> {
+        let mut ops = self.link()
+            .push(entry)
+            .timeout(monotonic_timespec(deadline), types::TimeoutFlags::ABS)
+            .finish();
+        let timeout = ops.pop().unwrap();
+        let op = ops.pop().unwrap();
+        async move {
+            let cqe = op.await;
+            let timeout = timeout.await;
+            if timeout.result() == -libc::ETIME && cqe.result() == -libc::ECANCELED {
+                Err(DeadlineExceeded)
+            } else {
+                Ok(cqe)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+    use io_uring::{opcode, types};
+    use crate::IoUringAsync;
+    use super::{Clock, DeadlineExceeded, TimeoutOpts};
+
+    #[test]
+    fn with_deadline() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (a, _b) = UnixStream::pair().unwrap();
+        let mut buf = [0u8; 16];
+        crate::tests::run(&uring, async {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let cqe = uring.with_deadline(opcode::Nop::new().build(), deadline).await.unwrap();
+            assert_eq!(cqe.result(), 0);
+
+            // Nothing is ever written to the socket, so the recv can only be
+            // completed by the deadline.
+            let recv = opcode::Recv::new(types::Fd(a.as_raw_fd()), buf.as_mut_ptr(), buf.len() as u32).build();
+            let deadline = Instant::now() + Duration::from_millis(20);
+            assert_eq!(uring.with_deadline(recv, deadline).await.unwrap_err(), DeadlineExceeded);
+            assert!(Instant::now() >= deadline);
+        });
+    }
+
+    #[test]
+    fn timeout_clocks() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let start = Instant::now();
+            uring.timeout(Duration::from_millis(10), TimeoutOpts::default()).await.unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(10));
+
+            for clock in [Clock::Monotonic, Clock::Boottime, Clock::Realtime] {
+                let start = Instant::now();
+                let opts = TimeoutOpts { absolute: true, clock };
+                uring.timeout(clock.now() + Duration::from_millis(10), opts).await.unwrap();
+                assert!(start.elapsed() >= Duration::from_millis(5), "{:?}", clock);
+
+                // An absolute time in the past expires immediately.
+                uring.timeout(Duration::ZERO, opts).await.unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn sleep() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        crate::tests::run(&uring, async {
+            let start = Instant::now();
+            uring.sleep(Duration::from_millis(10)).await;
+            assert!(start.elapsed() >= Duration::from_millis(10));
+
+            // Dropping the sleep cancels the timeout rather than waiting for it.
+            let sleep = uring.sleep(Duration::from_secs(10));
+            uring.submit().unwrap();
+            drop(sleep);
+            uring.drain().await;
+            assert!(start.elapsed() < Duration::from_secs(5));
+        });
+        assert!(uring.slab.borrow().is_empty());
+    }
+}
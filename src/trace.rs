@@ -0,0 +1,152 @@
+//! Observability hooks for the operations performed by an [`IoUringAsync`](crate::IoUringAsync).
+//! Hooks are installed with [`Builder::on_completion`](crate::builder::Builder::on_completion)
+//! and [`Builder::on_unexpected_completion`](crate::builder::Builder::on_unexpected_completion).
+//!
+//! With the `tracing` feature, submissions to the kernel and the processing of
+//! completions are recorded as `io_uring_submit` and `io_uring_complete` spans,
+//! and every completion of an Op pushed with [`IoUringAsync::push`](crate::IoUringAsync::push)
+//! or [`IoUringAsync::push_tagged`](crate::IoUringAsync::push_tagged) as a
+//! debug event with its opcode, user_data, result and latency in microseconds.
+
+use std::time::Duration;
+
+pub(crate) type CompletionHook = dyn Fn(&CompletionEvent);
+pub(crate) type UnexpectedHook = dyn Fn(&UnexpectedCompletion);
+
+/// A description of an Op that has completed, which is passed to the hook
+/// installed with [`Builder::on_completion`](crate::builder::Builder::on_completion).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompletionEvent {
+    /// The opcode of the Op's submission queue entry.
+    pub opcode: u8,
+    /// The user_data of the Op's submission queue entry.
+    pub user_data: u64,
+    /// The tag that the Op was pushed with, if any.
+    pub tag: Option<u64>,
+    /// The position of the Op in the order that Ops were pushed onto the ring,
+    /// which is only recorded with the `op-sequence` feature.
+    pub seq: Option<u64>,
+    /// The result of the Op's completion queue entry.
+    pub result: i32,
+    /// The time between pushing the Op and processing its completion.
+    pub latency: Duration,
+}
+
+/// A completion queue entry for an Op that had already completed, or that does
+/// not belong to any Op, which is passed to the hook installed with
+/// [`Builder::on_unexpected_completion`](crate::builder::Builder::on_unexpected_completion).
+/// The entry is discarded after the hook returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnexpectedCompletion {
+    /// The user_data of the completion queue entry.
+    pub user_data: u64,
+    /// The result of the completion queue entry.
+    pub result: i32,
+    /// The flags of the completion queue entry.
+    pub flags: u32,
+    /// Whether the entry does not belong to any Op, for example because it was
+    /// pushed onto the ring by a foreign submitter, rather than to an Op that
+    /// has already completed. Such entries are counted in
+    /// [`RingStats::orphan_completions`](crate::stats::RingStats::orphan_completions).
+    pub orphan: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use io_uring::opcode;
+    use crate::IoUringAsync;
+
+    #[test]
+    fn on_completion() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let hook_events = events.clone();
+        let uring = IoUringAsync::builder()
+            .on_completion(move |event| hook_events.borrow_mut().push(*event))
+            .build(8)
+            .unwrap();
+        let uring = Rc::new(uring);
+        crate::tests::run(&uring, async {
+            uring.push_tagged(opcode::Nop::new().build(), 7).await;
+            let timespec = crate::time::timespec(Duration::from_millis(10));
+            uring.push(opcode::Timeout::new(&timespec).build()).await;
+        });
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].opcode, opcode::Nop::CODE);
+        assert_eq!(events[0].tag, Some(7));
+        assert_eq!(events[0].result, 0);
+        assert_eq!(events[1].opcode, opcode::Timeout::CODE);
+        assert_eq!(events[1].result, -libc::ETIME);
+        assert!(events[1].latency >= Duration::from_millis(10));
+        assert_eq!(events[1].seq, cfg!(feature = "op-sequence").then_some(1));
+    }
+
+    // Records the names of the spans and the fields of the events that are
+    // emitted with the tracing feature.
+    #[cfg(feature = "tracing")]
+    #[derive(Default)]
+    struct Recorder {
+        spans: std::sync::Mutex<Vec<&'static str>>,
+        events: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span.metadata().name());
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Fields(String);
+            impl tracing::field::Visit for Fields {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                    self.0 += &format!("{}={:?} ", field.name(), value);
+                }
+            }
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.events.lock().unwrap().push(fields.0);
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing() {
+        let recorder = std::sync::Arc::new(Recorder::default());
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        tracing::subscriber::with_default(recorder.clone(), || {
+            crate::tests::run(&uring, async {
+                let timespec = crate::time::timespec(Duration::from_millis(10));
+                uring.push(opcode::Timeout::new(&timespec).build()).await;
+            });
+        });
+
+        let spans = recorder.spans.lock().unwrap();
+        assert!(spans.contains(&"io_uring_submit"));
+        assert!(spans.contains(&"io_uring_complete"));
+        let events = recorder.events.lock().unwrap();
+        let completed = events.iter().find(|event| event.starts_with("message=io_uring op completed")).unwrap();
+        assert!(completed.contains(&format!("opcode={} ", opcode::Timeout::CODE)));
+        assert!(completed.contains(&format!("result={} ", -libc::ETIME)));
+        let latency: u64 = completed.split("latency_us=").nth(1).unwrap().split(' ').next().unwrap().parse().unwrap();
+        assert!(latency >= 10_000);
+    }
+}
@@ -0,0 +1,163 @@
+use std::io;
+use crate::ops::RwTarget;
+use crate::{cqueue, squeue, IoUringAsync};
+
+const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+/// A writer that accumulates small writes in an owned buffer, and writes them
+/// to a file with a single [`IoUringAsync::write`] once the buffer is full or
+/// [`UringBufWriter::flush`] is called. This reduces the number of Ops pushed
+/// for chatty write patterns, such as serialized log records.
+///
+/// Buffered data that has not been flushed is discarded when the writer is
+/// dropped, so `flush` should be awaited before dropping it. A flush that is
+/// cancelled may or may not have written its data, which is discarded from the
+/// buffer either way.
+pub struct UringBufWriter<'a, S: squeue::Entry, C: cqueue::Entry> {
+    uring: &'a IoUringAsync<S, C>,
+    fd: RwTarget,
+    buf: Vec<u8>,
+    capacity: usize,
+    // The offset of the next write, or u64::MAX to use the file position.
+    offset: u64,
+}
+
+impl<'a, S: squeue::Entry, C: cqueue::Entry> UringBufWriter<'a, S, C> {
+    /// Create a writer for `fd` with a buffer of 8 KiB.
+    pub fn new(uring: &'a IoUringAsync<S, C>, fd: impl Into<RwTarget> + 'a) -> Self {
+        Self::with_capacity(uring, DEFAULT_CAPACITY, fd)
+    }
+
+    /// Create a writer for `fd` that flushes once `capacity` bytes are buffered.
+    pub fn with_capacity(uring: &'a IoUringAsync<S, C>, capacity: usize, fd: impl Into<RwTarget> + 'a) -> Self {
+        UringBufWriter { uring, fd: fd.into(), buf: Vec::with_capacity(capacity), capacity, offset: 0 }
+    }
+
+    /// Write at `offset`, which advances by the number of bytes written by each
+    /// flush. Defaults to zero, which must be used for descriptors that are not
+    /// seekable. `u64::MAX` uses and advances the file position, like `write(2)`.
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Returns the file that the writer writes to.
+    pub fn get_ref(&self) -> RwTarget {
+        self.fd
+    }
+
+    /// Returns the data that is buffered but not yet written.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Returns the number of bytes that are buffered before the writer flushes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Append `data` to the buffer, flushing it if it becomes full. Data larger
+    /// than the buffer is written together with what was already buffered.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= self.capacity {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Write all of the buffered data, resubmitting the remainder after a partial
+    /// write. If a write fails, the data that was not written stays buffered,
+    /// and the error is returned.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        while !self.buf.is_empty() {
+            let buf = std::mem::take(&mut self.buf);
+            let (res, mut buf) = self.uring.write(self.fd, buf).offset(self.offset).push().await;
+            let n = match res {
+                Ok(n) if n > 0 => n,
+                res => {
+                    self.buf = buf;
+                    return Err(res.err().unwrap_or_else(|| io::ErrorKind::WriteZero.into()));
+                }
+            };
+            if self.offset != u64::MAX {
+                self.offset += n as u64;
+            }
+            buf.drain(..n);
+            self.buf = buf;
+        }
+        Ok(())
+    }
+}
+
+impl<S: squeue::Entry, C: cqueue::Entry> std::fmt::Debug for UringBufWriter<'_, S, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UringBufWriter")
+            .field("fd", &self.fd)
+            .field("buffered", &self.buf.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::os::unix::prelude::AsRawFd;
+    use std::rc::Rc;
+    use crate::IoUringAsync;
+    use super::UringBufWriter;
+
+    #[test]
+    fn buf_writer() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let (rx, tx) = crate::fd::pipe().unwrap();
+        let mut rx = std::fs::File::from(std::os::fd::OwnedFd::from(rx));
+        crate::tests::run(&uring, async {
+            let mut writer = UringBufWriter::with_capacity(&uring, 8, tx.as_raw_fd());
+            writer.write(b"abc").await.unwrap();
+            writer.write(b"def").await.unwrap();
+            assert_eq!(writer.buffer(), b"abcdef");
+
+            // Filling the buffer flushes it.
+            writer.write(b"gh").await.unwrap();
+            assert!(writer.buffer().is_empty());
+            let mut buf = [0; 8];
+            rx.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"abcdefgh");
+
+            writer.write(b"ij").await.unwrap();
+            writer.flush().await.unwrap();
+            let mut buf = [0; 2];
+            rx.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"ij");
+        });
+
+        // A failed write keeps the data buffered.
+        drop(rx);
+        crate::tests::run(&uring, async {
+            let mut writer = UringBufWriter::new(&uring, tx.as_raw_fd());
+            writer.write(b"lost").await.unwrap();
+            let err = writer.flush().await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EPIPE));
+            assert_eq!(writer.buffer(), b"lost");
+        });
+    }
+
+    #[test]
+    fn buf_writer_offset() {
+        let uring = Rc::new(IoUringAsync::new(8).unwrap());
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("io-uring-async-writer-{}", std::process::id()));
+        let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        crate::tests::run(&uring, async {
+            let mut writer = UringBufWriter::with_capacity(&uring, 4, file.as_raw_fd()).offset(2);
+            for record in [&b"one"[..], b"two", b"three"] {
+                writer.write(record).await.unwrap();
+            }
+            writer.flush().await.unwrap();
+        });
+        assert_eq!(std::fs::read(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap(), b"\0\0onetwothree");
+    }
+}